@@ -0,0 +1,342 @@
+use crate::persist::write_json_atomic;
+use crate::providers::reconnect_backoff;
+use anyhow::{Context, Result};
+use ethers::providers::Middleware;
+use ethers::types::{Address, Filter, H256, Log};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Substrings providers use when a `eth_getLogs` range is too wide or would
+/// return too many results. Matched case-insensitively.
+const RANGE_ERROR_MARKERS: &[&str] = &[
+    "block range",
+    "range too large",
+    "range is too large",
+    "exceed maximum block range",
+    "more than 10000 results",
+    "query returned more than",
+    "too many results",
+    "response size exceeded",
+    "response size should not greater",
+    "log response size",
+    "-32005",
+];
+
+/// Substrings of provider rate-limit errors. Checked before the range
+/// markers, since some providers reuse the same error code for both, and
+/// retried with backoff instead of shrinking the chunk.
+const RATE_LIMIT_MARKERS: &[&str] = &[
+    "rate limit",
+    "rate-limit",
+    "too many requests",
+    "request count exceeded",
+];
+
+const MAX_TRANSIENT_RETRIES: u32 = 5;
+
+#[derive(Clone, Debug)]
+pub struct BackfillRequest {
+    pub addresses: Vec<Address>,
+    pub topics: Vec<H256>,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub initial_chunk_blocks: u64,
+    pub min_chunk_blocks: u64,
+    pub max_chunk_blocks: u64,
+    pub checkpoint_path: Option<PathBuf>,
+}
+
+/// Resume point written after every completed chunk.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackfillCheckpoint {
+    pub addresses: Vec<Address>,
+    pub topics: Vec<H256>,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub next_block: u64,
+    pub chunk_blocks: u64,
+    pub logs_fetched: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BackfillSummary {
+    pub event: String,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub resumed_from_block: u64,
+    pub chunks: u64,
+    pub range_splits: u64,
+    pub logs_fetched: u64,
+    pub final_chunk_blocks: u64,
+}
+
+impl BackfillCheckpoint {
+    /// The end block is left out: it usually defaults to the chain head, so
+    /// it moves between runs of the same backfill. Each run reads up to its
+    /// own end block.
+    fn matches(&self, request: &BackfillRequest) -> bool {
+        self.addresses == request.addresses && self.topics == request.topics && self.from_block == request.from_block
+    }
+}
+
+pub fn load_checkpoint(path: &Path) -> Result<Option<BackfillCheckpoint>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed reading checkpoint at {}", path.display()))?;
+    let checkpoint = serde_json::from_str(&content)
+        .with_context(|| format!("failed parsing checkpoint at {}", path.display()))?;
+    Ok(Some(checkpoint))
+}
+
+/// Writes through a temp file and rename so an interrupted write never
+/// leaves a truncated checkpoint behind.
+pub fn save_checkpoint(path: &Path, checkpoint: &BackfillCheckpoint) -> Result<()> {
+    write_json_atomic(path, checkpoint, "checkpoint")
+}
+
+pub fn is_rate_limit_error(message: &str) -> bool {
+    let lower = message.to_ascii_lowercase();
+    RATE_LIMIT_MARKERS.iter().any(|marker| lower.contains(marker))
+}
+
+pub fn is_range_error(message: &str) -> bool {
+    let lower = message.to_ascii_lowercase();
+    !is_rate_limit_error(&lower) && RANGE_ERROR_MARKERS.iter().any(|marker| lower.contains(marker))
+}
+
+/// Fetches logs for `request` chunk by chunk, handing each chunk's logs and
+/// last covered block to `on_logs` in block order.
+///
+/// A chunk the provider rejects as too large is halved and retried down to
+/// `min_chunk_blocks`; after a success the chunk grows back towards
+/// `max_chunk_blocks`. Rate limits and other errors are retried with
+/// backoff. With a checkpoint path set, progress is saved after each chunk
+/// and a checkpoint for the same filter and start block is resumed on the
+/// next run, up to the requested end block. A checkpoint already past that
+/// block is an error.
+pub async fn backfill_logs<M, F>(provider: &M, request: &BackfillRequest, mut on_logs: F) -> Result<BackfillSummary>
+where
+    M: Middleware,
+    F: FnMut(&[Log], u64) -> Result<()>,
+{
+    if request.from_block > request.to_block {
+        anyhow::bail!(
+            "backfill range is empty: from_block={} to_block={}",
+            request.from_block,
+            request.to_block
+        );
+    }
+
+    let min_chunk = request.min_chunk_blocks.max(1);
+    let max_chunk = request.max_chunk_blocks.max(min_chunk);
+    let mut checkpoint = BackfillCheckpoint {
+        addresses: request.addresses.clone(),
+        topics: request.topics.clone(),
+        from_block: request.from_block,
+        to_block: request.to_block,
+        next_block: request.from_block,
+        chunk_blocks: request.initial_chunk_blocks.clamp(min_chunk, max_chunk),
+        logs_fetched: 0,
+    };
+    if let Some(path) = &request.checkpoint_path
+        && let Some(saved) = load_checkpoint(path)?
+    {
+        if saved.matches(request) {
+            if saved.next_block > request.to_block.saturating_add(1) {
+                anyhow::bail!(
+                    "backfill checkpoint at {} has already read up to block {}, past to_block={}",
+                    path.display(),
+                    saved.next_block - 1,
+                    request.to_block
+                );
+            }
+            checkpoint.next_block = saved.next_block;
+            checkpoint.chunk_blocks = saved.chunk_blocks.clamp(min_chunk, max_chunk);
+            checkpoint.logs_fetched = saved.logs_fetched;
+        } else {
+            eprintln!(
+                "Ignoring backfill checkpoint at {}: it was written for a different start block or filter.",
+                path.display()
+            );
+        }
+    }
+
+    let to_block = checkpoint.to_block;
+    let mut summary = BackfillSummary {
+        event: "backfill_summary".to_string(),
+        from_block: request.from_block,
+        to_block,
+        resumed_from_block: checkpoint.next_block,
+        chunks: 0,
+        range_splits: 0,
+        logs_fetched: 0,
+        final_chunk_blocks: checkpoint.chunk_blocks,
+    };
+    let base_filter = Filter::new()
+        .address(request.addresses.clone())
+        .topic0(request.topics.clone());
+    let mut transient_failures: u32 = 0;
+
+    while checkpoint.next_block <= to_block {
+        let start = checkpoint.next_block;
+        let end = start
+            .saturating_add(checkpoint.chunk_blocks - 1)
+            .min(to_block);
+        let filter = base_filter.clone().from_block(start).to_block(end);
+
+        match provider.get_logs(&filter).await {
+            Ok(logs) => {
+                transient_failures = 0;
+                on_logs(&logs, end)?;
+                checkpoint.logs_fetched = checkpoint.logs_fetched.saturating_add(logs.len() as u64);
+                checkpoint.next_block = end.saturating_add(1);
+                summary.chunks = summary.chunks.saturating_add(1);
+                summary.logs_fetched = summary.logs_fetched.saturating_add(logs.len() as u64);
+                if let Some(path) = &request.checkpoint_path {
+                    save_checkpoint(path, &checkpoint)?;
+                }
+                checkpoint.chunk_blocks = checkpoint.chunk_blocks.saturating_mul(2).min(max_chunk);
+            }
+            Err(err) => {
+                let message = err.to_string();
+                if is_range_error(&message) && checkpoint.chunk_blocks > min_chunk {
+                    checkpoint.chunk_blocks = (checkpoint.chunk_blocks / 2).max(min_chunk);
+                    summary.range_splits = summary.range_splits.saturating_add(1);
+                    continue;
+                }
+
+                transient_failures = transient_failures.saturating_add(1);
+                if transient_failures > MAX_TRANSIENT_RETRIES {
+                    anyhow::bail!("eth_getLogs failed for blocks {start}..={end}: {message}");
+                }
+                tokio::time::sleep(reconnect_backoff(500, 10_000, transient_failures)).await;
+            }
+        }
+    }
+
+    summary.final_chunk_blocks = checkpoint.chunk_blocks;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse, Provider};
+
+    fn request(from_block: u64, to_block: u64, initial: u64, max: u64) -> BackfillRequest {
+        BackfillRequest {
+            addresses: vec![Address::repeat_byte(1)],
+            topics: vec![H256::repeat_byte(2)],
+            from_block,
+            to_block,
+            initial_chunk_blocks: initial,
+            min_chunk_blocks: 1,
+            max_chunk_blocks: max,
+            checkpoint_path: None,
+        }
+    }
+
+    fn rpc_error(code: i64, message: &str) -> MockResponse {
+        MockResponse::Error(JsonRpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        })
+    }
+
+    /// A provider answering `eth_getLogs` calls with `responses` in order,
+    /// `None` standing for an empty result.
+    fn provider(responses: Vec<Option<MockResponse>>) -> Provider<MockProvider> {
+        let (provider, mock) = Provider::mocked();
+        // The mock answers from the back of its queue.
+        for response in responses.into_iter().rev() {
+            match response {
+                Some(response) => mock.push_response(response),
+                None => mock.push::<Vec<Log>, _>(Vec::new()).unwrap(),
+            }
+        }
+        provider
+    }
+
+    /// Runs a backfill and returns its summary and each chunk's last block.
+    async fn run(provider: &Provider<MockProvider>, request: &BackfillRequest) -> Result<(BackfillSummary, Vec<u64>)> {
+        let mut ends = Vec::new();
+        let summary = backfill_logs(provider, request, |_, end| {
+            ends.push(end);
+            Ok(())
+        })
+        .await?;
+        Ok((summary, ends))
+    }
+
+    #[test]
+    fn rate_limits_are_not_range_errors() {
+        assert!(is_range_error("query returned more than 10000 results"));
+        assert!(is_range_error("(code: -32005, message: block range too large)"));
+        assert!(!is_range_error("(code: -32005, message: rate limit exceeded)"));
+        assert!(is_rate_limit_error("429 Too Many Requests"));
+    }
+
+    #[tokio::test]
+    async fn range_error_halves_the_chunk_then_it_grows_back() {
+        let provider = provider(vec![
+            Some(rpc_error(-32005, "query returned more than 10000 results")),
+            Some(rpc_error(-32005, "query returned more than 10000 results")),
+            None,
+            None,
+            None,
+        ]);
+        let (summary, ends) = run(&provider, &request(0, 99, 80, 80)).await.unwrap();
+        // 80 -> 40 -> 20 blocks, then 40 and 80 after each success.
+        assert_eq!(ends, vec![19, 59, 99]);
+        assert_eq!(summary.range_splits, 2);
+        assert_eq!(summary.chunks, 3);
+        assert_eq!(summary.final_chunk_blocks, 80);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_retries_at_the_same_size() {
+        let provider = provider(vec![Some(rpc_error(-32005, "rate limit exceeded")), None, None]);
+        let (summary, ends) = run(&provider, &request(0, 99, 50, 50)).await.unwrap();
+        assert_eq!(ends, vec![49, 99]);
+        assert_eq!(summary.range_splits, 0);
+        assert_eq!(summary.final_chunk_blocks, 50);
+    }
+
+    #[tokio::test]
+    async fn chunks_grow_up_to_the_maximum() {
+        let provider = provider(vec![None, None, None, None]);
+        let (summary, ends) = run(&provider, &request(0, 99, 10, 40)).await.unwrap();
+        assert_eq!(ends, vec![9, 29, 69, 99]);
+        assert_eq!(summary.final_chunk_blocks, 40);
+    }
+
+    #[tokio::test]
+    async fn resume_stops_at_the_requested_end_block() {
+        let dir = std::env::temp_dir().join(format!("backfill-resume-{}", std::process::id()));
+        let path = dir.join("checkpoint.json");
+        let mut request = request(0, 150, 100, 100);
+        request.checkpoint_path = Some(path.clone());
+        let saved = BackfillCheckpoint {
+            addresses: request.addresses.clone(),
+            topics: request.topics.clone(),
+            from_block: 0,
+            to_block: 500,
+            next_block: 100,
+            chunk_blocks: 100,
+            logs_fetched: 0,
+        };
+        save_checkpoint(&path, &saved).unwrap();
+
+        let (summary, ends) = run(&provider(vec![None]), &request).await.unwrap();
+        assert_eq!(summary.resumed_from_block, 100);
+        assert_eq!(ends, vec![150]);
+
+        save_checkpoint(&path, &BackfillCheckpoint { next_block: 200, ..saved }).unwrap();
+        assert!(run(&provider(Vec::new()), &request).await.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
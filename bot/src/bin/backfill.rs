@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use ethers::providers::Middleware;
+use ethers::types::H256;
+use evm_flashloans_l2_arb::backfill::{BackfillRequest, backfill_logs};
+use evm_flashloans_l2_arb::config::{BackfillConfig, PoolListenerConfig, RuntimeConfig};
+use evm_flashloans_l2_arb::providers::{http_provider, masked_rpc_url};
+use evm_flashloans_l2_arb::venues::AdapterOptions;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+struct ErrorLogGate {
+    min_interval: Duration,
+    last_emit: Option<Instant>,
+    suppressed: u64,
+}
+
+impl ErrorLogGate {
+    fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last_emit: None,
+            suppressed: 0,
+        }
+    }
+
+    fn log(&mut self, prefix: &str, details: &str) {
+        let now = Instant::now();
+        let should_emit = self
+            .last_emit
+            .is_none_or(|last| now.duration_since(last) >= self.min_interval);
+
+        if should_emit {
+            if self.suppressed > 0 {
+                eprintln!(
+                    "{prefix}: {details} (suppressed {} similar log lines)",
+                    self.suppressed
+                );
+                self.suppressed = 0;
+            } else {
+                eprintln!("{prefix}: {details}");
+            }
+            self.last_emit = Some(now);
+        } else {
+            self.suppressed = self.suppressed.saturating_add(1);
+        }
+    }
+
+    fn flush(&mut self, prefix: &str) {
+        if self.suppressed > 0 {
+            eprintln!("{prefix}: suppressed {} similar log lines", self.suppressed);
+            self.suppressed = 0;
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let runtime = RuntimeConfig::from_env()?;
+    let pools = PoolListenerConfig::from_env()?;
+    let backfill = BackfillConfig::from_env()?;
+    let provider = http_provider(&runtime.rpc_https_url)?;
+
+    let to_block = match backfill.to_block {
+        Some(block) => block,
+        None => provider
+            .get_block_number()
+            .await
+            .context("failed to fetch latest block number")?
+            .as_u64(),
+    };
+    let mut tokens = runtime.token_resolver()?;
+    let mut tracked = Vec::with_capacity(pools.markets.len());
+    for config in &pools.markets {
+        let (market, adapter) = config.connect(&provider, &mut tokens, &AdapterOptions::default()).await?;
+        if adapter.state_topics().is_empty() {
+            eprintln!(
+                "Skipping {} pool {:#x}: its state is not carried by logs",
+                adapter.venue().as_str(),
+                adapter.pool()
+            );
+            continue;
+        }
+        tracked.push((market, adapter));
+    }
+    let topics: BTreeSet<H256> = tracked.iter().flat_map(|(_, adapter)| adapter.state_topics()).collect();
+    let request = BackfillRequest {
+        addresses: tracked.iter().map(|(_, adapter)| adapter.pool()).collect(),
+        topics: topics.into_iter().collect(),
+        from_block: backfill.from_block,
+        to_block,
+        initial_chunk_blocks: backfill.initial_chunk_blocks,
+        min_chunk_blocks: backfill.min_chunk_blocks,
+        max_chunk_blocks: backfill.max_chunk_blocks,
+        checkpoint_path: backfill.checkpoint_path.as_ref().map(PathBuf::from),
+    };
+
+    eprintln!(
+        "Backfill start: network={}, http_provider={}, from_block={}, to_block={}, chunk_blocks={}, checkpoint={}",
+        runtime.network_name,
+        masked_rpc_url(&runtime.rpc_https_url),
+        request.from_block,
+        request.to_block,
+        request.initial_chunk_blocks,
+        backfill.checkpoint_path.as_deref().unwrap_or("none")
+    );
+
+    // Rows come from the logs alone, so pool state is never loaded.
+    // A log that fails to decode is skipped rather than ending the run.
+    let mut updates = 0_u64;
+    let mut skipped = 0_u64;
+    let mut decode_errors = ErrorLogGate::new(Duration::from_secs(15));
+    let summary = backfill_logs(&provider, &request, |logs, _| {
+        for log in logs {
+            let Some((market, adapter)) = tracked.iter().find(|(_, adapter)| adapter.pool() == log.address) else {
+                continue;
+            };
+            let update = log
+                .block_number
+                .context("log is missing block number")
+                .and_then(|block| adapter.normalize(market, block.as_u64(), Some(log)));
+            match update {
+                Ok(Some(update)) => {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&update).context("failed to serialize pool update")?
+                    );
+                    updates += 1;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    skipped += 1;
+                    decode_errors.log(
+                        "log decode failed (skipped)",
+                        &format!(
+                            "pool={:#x}, tx={:?}, log_index={:?}: {err:#}",
+                            log.address, log.transaction_hash, log.log_index
+                        ),
+                    );
+                }
+            }
+        }
+        Ok(())
+    })
+    .await?;
+    decode_errors.flush("log decode failures");
+
+    eprintln!(
+        "{}",
+        serde_json::to_string(&summary).context("failed to serialize backfill summary")?
+    );
+    eprintln!(
+        "Backfill complete: pools={}, updates={updates}, skipped_logs={skipped}",
+        tracked.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use ethers::providers::Middleware;
+use evm_flashloans_l2_arb::backfill::{BackfillRequest, backfill_logs};
+use evm_flashloans_l2_arb::config::{DiscoveryConfig, RuntimeConfig};
+use evm_flashloans_l2_arb::discovery::{PoolRegistry, decode_pool_created};
+use evm_flashloans_l2_arb::providers::{http_provider, masked_rpc_url};
+use std::path::Path;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let runtime = RuntimeConfig::from_env()?;
+    let discovery = DiscoveryConfig::from_env(runtime.expected_chain_id)?;
+    let provider = http_provider(&runtime.rpc_https_url)?;
+
+    let chain_id = provider
+        .get_chainid()
+        .await
+        .context("failed to fetch chain id from RPC")?
+        .as_u64();
+    if chain_id != runtime.expected_chain_id {
+        anyhow::bail!("chain id mismatch: expected {}, got {chain_id}", runtime.expected_chain_id);
+    }
+    let to_block = match discovery.to_block {
+        Some(block) => block,
+        None => provider
+            .get_block_number()
+            .await
+            .context("failed to fetch latest block number")?
+            .as_u64(),
+    };
+
+    let registry_path = Path::new(&discovery.registry_path);
+    let mut registry = PoolRegistry::load_or_new(registry_path, chain_id)?;
+    eprintln!(
+        "Pool discovery start: network={}, http_provider={}, factories={}, to_block={}, registry={}, known_pools={}",
+        runtime.network_name,
+        masked_rpc_url(&runtime.rpc_https_url),
+        discovery.factories.len(),
+        to_block,
+        registry_path.display(),
+        registry.len()
+    );
+
+    for source in &discovery.factories {
+        let from_block = registry.next_block(source);
+        if from_block > to_block {
+            eprintln!(
+                "Factory {:#x} ({:?}) already scanned through block {}",
+                source.factory,
+                source.kind,
+                from_block.saturating_sub(1)
+            );
+            continue;
+        }
+        let request = BackfillRequest {
+            addresses: vec![source.factory],
+            topics: vec![source.kind.creation_topic()],
+            from_block,
+            to_block,
+            initial_chunk_blocks: discovery.initial_chunk_blocks,
+            min_chunk_blocks: discovery.min_chunk_blocks,
+            max_chunk_blocks: discovery.max_chunk_blocks,
+            checkpoint_path: None,
+        };
+
+        let mut added = 0_u64;
+        let summary = backfill_logs(&provider, &request, |logs, through_block| {
+            for log in logs {
+                let Some(record) = decode_pool_created(source.kind, log)? else {
+                    continue;
+                };
+                if registry.insert(record.clone()) {
+                    added += 1;
+                    println!(
+                        "{}",
+                        serde_json::to_string(&record).context("failed to serialize pool record")?
+                    );
+                }
+            }
+            registry.mark_scanned(source.factory, through_block);
+            // Empty chunks only move the scan cursor; persisting that can
+            // wait for the next chunk with pools or the end of the scan.
+            if logs.is_empty() {
+                Ok(())
+            } else {
+                registry.save(registry_path)
+            }
+        })
+        .await?;
+        registry.save(registry_path)?;
+
+        eprintln!(
+            "Factory {:#x} ({:?}) scanned: blocks={}..={}, chunks={}, range_splits={}, new_pools={}",
+            source.factory, source.kind, summary.from_block, summary.to_block, summary.chunks, summary.range_splits, added
+        );
+    }
+
+    eprintln!("Pool discovery complete: known_pools={}", registry.len());
+    Ok(())
+}
@@ -1,6 +1,8 @@
 use anyhow::{Context, Result};
 use dotenvy::from_filename_override;
-use ethers::providers::{Middleware, Provider, Ws};
+use ethers::providers::{Http, Middleware, Provider, Ws};
+use ethers::types::{Block, TxHash};
+use evm_flashloans_l2_arb::reorg::{BlockRef, BlockTracker, track_block};
 use futures_util::StreamExt;
 use reqwest::header::{ACCEPT, CONTENT_TYPE};
 use serde_json::{Value, json};
@@ -152,10 +154,41 @@ fn parse_hex_u64(value: &str) -> Result<u64> {
         .with_context(|| format!("Invalid hex value: {value}"))
 }
 
-fn print_block_if_new(last_block: &mut Option<u64>, block: u64) {
+fn print_block_if_new(last_block: &mut Option<u64>, block: u64) -> bool {
     if *last_block != Some(block) {
         println!("New Block: {block}");
         *last_block = Some(block);
+        return true;
+    }
+    false
+}
+
+/// Records the head's hash and prints a `{"event":"reorg",...}` line when it
+/// does not link to the blocks seen before. Heartbeat holds no pool state, so
+/// there is nothing to roll back.
+async fn observe_head<M: Middleware>(tracker: &mut BlockTracker, provider: &M, block: &Block<TxHash>) {
+    let head = match BlockRef::from_block(block) {
+        Ok(head) => head,
+        Err(err) => {
+            eprintln!("Block header incomplete: {}", sanitize_error(&err));
+            return;
+        }
+    };
+    match track_block(tracker, provider, head).await {
+        Ok(Some(reorg)) => match serde_json::to_string(&reorg) {
+            Ok(json) => println!("{json}"),
+            Err(err) => eprintln!("reorg serialization failed: {}", sanitize_error(&err)),
+        },
+        Ok(None) => {}
+        Err(err) => eprintln!("Block tracking failed: {}", sanitize_error(&err)),
+    }
+}
+
+async fn observe_block_number(tracker: &mut BlockTracker, provider: &Provider<Http>, number: u64) {
+    match provider.get_block(number).await {
+        Ok(Some(block)) => observe_head(tracker, provider, &block).await,
+        Ok(None) => eprintln!("Block {number} not found for reorg tracking"),
+        Err(err) => eprintln!("Block fetch for reorg tracking failed: {}", sanitize_error(&err)),
     }
 }
 
@@ -242,13 +275,16 @@ async fn log_http_chain_id(client: &reqwest::Client, https_url: &str, expected_c
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run_http_polling_window(
     client: &reqwest::Client,
+    http_provider: &Provider<Http>,
     https_url: &str,
     expected_chain_id: u64,
     poll_interval: Duration,
     window: Duration,
     last_block: &mut Option<u64>,
+    tracker: &mut BlockTracker,
 ) -> bool {
     log_http_chain_id(client, https_url, expected_chain_id).await;
     let mut error_gate = ErrorLogGate::new(Duration::from_secs(15));
@@ -264,7 +300,11 @@ async fn run_http_polling_window(
             _ = tokio::signal::ctrl_c() => return true,
             result = fetch_block_number_http(client, https_url) => {
                 match result {
-                    Ok(block) => print_block_if_new(last_block, block),
+                    Ok(block) => {
+                        if print_block_if_new(last_block, block) {
+                            observe_block_number(tracker, http_provider, block).await;
+                        }
+                    }
                     Err(err) => {
                         error_gate.log("HTTPS polling error (retrying)", &sanitize_error(&err))
                     }
@@ -291,7 +331,12 @@ async fn connect_ws(url: &str, timeout: Duration) -> Result<Provider<Ws>> {
     connect.with_context(|| format!("WebSocket connect failed for {}", masked_rpc_url(url)))
 }
 
-async fn run_ws_loop(provider: Provider<Ws>, expected_chain_id: u64, last_block: &mut Option<u64>) -> WsLoopExit {
+async fn run_ws_loop(
+    provider: Provider<Ws>,
+    expected_chain_id: u64,
+    last_block: &mut Option<u64>,
+    tracker: &mut BlockTracker,
+) -> WsLoopExit {
     match provider.get_chainid().await {
         Ok(actual) => {
             let actual = actual.as_u64();
@@ -319,7 +364,11 @@ async fn run_ws_loop(provider: Provider<Ws>, expected_chain_id: u64, last_block:
                 match maybe_block {
                     Some(block) => {
                         match block.number {
-                            Some(number) => print_block_if_new(last_block, number.as_u64()),
+                            Some(number) => {
+                                if print_block_if_new(last_block, number.as_u64()) {
+                                    observe_head(tracker, &provider, &block).await;
+                                }
+                            }
                             None => println!("New Block: <pending>"),
                         }
                     }
@@ -344,6 +393,7 @@ async fn main() -> Result<()> {
     let ws_backoff_initial_ms = env_u64_or_default("WS_RECONNECT_INITIAL_MS", 1_000);
     let ws_backoff_max_ms = env_u64_or_default("WS_RECONNECT_MAX_MS", 30_000);
     let http_poll_interval = Duration::from_secs(env_u64_or_default("HTTP_POLL_INTERVAL_SECS", 2));
+    let reorg_track_depth = env_u64_or_default("REORG_TRACK_DEPTH", 64);
 
     println!(
         "Startup Diagnostics: ws_provider={}, http_provider={}, expected_chain_id={}, ws_timeout_s={}, http_poll_s={}, mode=ws-first",
@@ -357,6 +407,9 @@ async fn main() -> Result<()> {
     let client = reqwest::Client::builder()
         .build()
         .context("Failed to initialize HTTP client")?;
+    let http_provider = Provider::<Http>::try_from(https_url.as_str())
+        .context("Failed to initialize HTTPS provider")?;
+    let mut tracker = BlockTracker::new(reorg_track_depth);
     let mut last_block: Option<u64> = None;
     let mut ws_attempt: u32 = 0;
 
@@ -366,7 +419,7 @@ async fn main() -> Result<()> {
                 ws_attempt = 0;
                 println!("Connected via WebSocket.");
 
-                match run_ws_loop(provider, expected_chain_id, &mut last_block).await {
+                match run_ws_loop(provider, expected_chain_id, &mut last_block, &mut tracker).await {
                     WsLoopExit::Shutdown => break,
                     WsLoopExit::Disconnected => {
                         ws_attempt = ws_attempt.saturating_add(1);
@@ -385,11 +438,13 @@ async fn main() -> Result<()> {
                 eprintln!("Falling back to HTTPS polling for {} ms.", wait.as_millis());
                 if run_http_polling_window(
                     &client,
+                    &http_provider,
                     &https_url,
                     expected_chain_id,
                     http_poll_interval,
                     wait,
                     &mut last_block,
+                    &mut tracker,
                 )
                 .await
                 {
@@ -0,0 +1,615 @@
+use anyhow::{Context, Result};
+use ethers::providers::{Http, Middleware, Provider, Ws};
+use ethers::types::{Address, BlockNumber, Filter, H256, Log};
+use evm_flashloans_l2_arb::backfill::{BackfillRequest, backfill_logs};
+use evm_flashloans_l2_arb::config::{PoolListenerConfig, RuntimeConfig};
+use evm_flashloans_l2_arb::providers::{connect_ws_with_timeout, http_provider, masked_rpc_url, reconnect_backoff};
+use evm_flashloans_l2_arb::reorg::{BlockRef, BlockTracker, track_block};
+use evm_flashloans_l2_arb::types::market::{
+    EventPosition, Market, NormalizedUpdate, PoolStateStore, end_of_block_position, log_position,
+};
+use evm_flashloans_l2_arb::venues::{AdapterOptions, PoolAdapter, refresh_pool};
+use futures_util::{Stream, StreamExt};
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+const MAX_POLL_RANGE_BLOCKS: u64 = 500;
+
+enum WsLoopExit {
+    Shutdown,
+    Disconnected,
+}
+
+struct ErrorLogGate {
+    min_interval: Duration,
+    last_emit: Option<Instant>,
+    suppressed: u64,
+}
+
+impl ErrorLogGate {
+    fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last_emit: None,
+            suppressed: 0,
+        }
+    }
+
+    fn log(&mut self, prefix: &str, details: &str) {
+        let now = Instant::now();
+        let should_emit = self
+            .last_emit
+            .is_none_or(|last| now.duration_since(last) >= self.min_interval);
+
+        if should_emit {
+            if self.suppressed > 0 {
+                eprintln!(
+                    "{prefix}: {details} (suppressed {} similar log lines)",
+                    self.suppressed
+                );
+                self.suppressed = 0;
+            } else {
+                eprintln!("{prefix}: {details}");
+            }
+            self.last_emit = Some(now);
+        } else {
+            self.suppressed = self.suppressed.saturating_add(1);
+        }
+    }
+
+    fn flush(&mut self, prefix: &str) {
+        if self.suppressed > 0 {
+            eprintln!("{prefix}: suppressed {} similar log lines", self.suppressed);
+            self.suppressed = 0;
+        }
+    }
+}
+
+/// Tracks how far the listener has read so the HTTP fallback can resume
+/// where the WebSocket stream stopped without re-emitting updates.
+#[derive(Default)]
+struct LogCursor {
+    next_block: Option<u64>,
+    last_log: Option<EventPosition>,
+}
+
+impl LogCursor {
+    fn is_new(&self, position: EventPosition) -> bool {
+        self.last_log.is_none_or(|last| position > last)
+    }
+
+    fn advance_log(&mut self, position: EventPosition) {
+        self.last_log = Some(position);
+        self.next_block = Some(self.next_block.map_or(position.block, |next| next.max(position.block)));
+    }
+
+    fn advance_head(&mut self, head: u64) {
+        self.next_block = Some(head.saturating_add(1));
+    }
+
+    /// Starts reading again after state loaded at the end of `block`.
+    fn reset(&mut self, block: u64) {
+        self.last_log = Some(end_of_block_position(block));
+        self.next_block = Some(block.saturating_add(1));
+    }
+
+    /// Forgets everything at or after `position` so it is read again.
+    fn rewind(&mut self, position: EventPosition) {
+        if self.last_log.is_some_and(|last| last >= position) {
+            self.last_log = Some(position_before(position));
+        }
+        self.next_block = Some(self.next_block.map_or(position.block, |next| next.min(position.block)));
+    }
+}
+
+fn position_before(position: EventPosition) -> EventPosition {
+    match position.log_index.checked_sub(1) {
+        Some(log_index) => EventPosition {
+            block: position.block,
+            log_index,
+        },
+        None => end_of_block_position(position.block.saturating_sub(1)),
+    }
+}
+
+/// A configured pool: its adapter and the tokens its rows are quoted in.
+struct TrackedPool {
+    market: Market,
+    adapter: Box<dyn PoolAdapter>,
+}
+
+struct Listener {
+    provider: Provider<Http>,
+    pools: Vec<TrackedPool>,
+    state: PoolStateStore,
+    cursor: LogCursor,
+    blocks: BlockTracker,
+    /// Set when rolling back is not enough (a reorg deeper than the tracked
+    /// window, or a pool left without state) or a log failed to apply:
+    /// every pool is reloaded at the end of this block and logs are read
+    /// again from the next one. Logs are dropped until then.
+    reload_at: Option<u64>,
+}
+
+impl Listener {
+    /// Pools whose state follows their logs; the rest are refreshed per head.
+    fn addresses(&self) -> Vec<Address> {
+        self.pools
+            .iter()
+            .filter(|pool| !pool.adapter.state_topics().is_empty())
+            .map(|pool| pool.adapter.pool())
+            .collect()
+    }
+
+    fn topics(&self) -> Vec<H256> {
+        let topics: BTreeSet<H256> = self
+            .pools
+            .iter()
+            .flat_map(|pool| pool.adapter.state_topics())
+            .collect();
+        topics.into_iter().collect()
+    }
+
+    fn filter(&self) -> Filter {
+        Filter::new().address(self.addresses()).topic0(self.topics())
+    }
+
+    fn schedule_reload(&mut self, block: u64) {
+        self.reload_at = Some(self.reload_at.map_or(block, |pending| pending.min(block)));
+    }
+
+    /// Reloads every pool at the end of `block` and rewinds the cursor to
+    /// read logs from the block after.
+    async fn reload(&mut self, block: u64) -> Result<()> {
+        for pool in &mut self.pools {
+            self.state.forget(pool.adapter.pool());
+            refresh_pool(&self.provider, pool.adapter.as_mut(), &mut self.state, block)
+                .await
+                .with_context(|| format!("failed loading pool {:#x} at block {block}", pool.adapter.pool()))?;
+        }
+        self.cursor.reset(block);
+        self.reload_at = None;
+        Ok(())
+    }
+
+    /// Refreshes the pools that are not driven by logs at `head` and prints
+    /// their rows.
+    async fn refresh_polled(&mut self, head: u64) -> Result<()> {
+        for pool in &mut self.pools {
+            if !pool.adapter.state_topics().is_empty() {
+                continue;
+            }
+            refresh_pool(&self.provider, pool.adapter.as_mut(), &mut self.state, head).await?;
+            if let Some(update) = pool.adapter.normalize(&pool.market, head, None)? {
+                print_update(&update)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_log(&mut self, log: &Log) -> Result<()> {
+        let position = log_position(log)?;
+        if log.removed == Some(true) {
+            self.state.rollback_pool(log.address, position);
+            self.cursor.rewind(position);
+            if let Some(pool) = self.pools.iter_mut().find(|pool| pool.adapter.pool() == log.address) {
+                pool.adapter.sync(&self.state);
+                if self.state.get(log.address).is_none() {
+                    self.schedule_reload(position.block.saturating_sub(1));
+                }
+            }
+            eprintln!(
+                "Log removed by reorg: pool={:#x}, block={}, log_index={}",
+                log.address, position.block, position.log_index
+            );
+            return Ok(());
+        }
+        if self.reload_at.is_some() || !self.cursor.is_new(position) {
+            return Ok(());
+        }
+
+        let Some(index) = self.pools.iter().position(|pool| pool.adapter.pool() == log.address) else {
+            return Ok(());
+        };
+        let pool = &mut self.pools[index];
+        if let Err(err) = pool.adapter.apply_log(&mut self.state, log) {
+            self.schedule_reload(position.block.saturating_sub(1));
+            return Err(err);
+        }
+        pool.adapter.sync(&self.state);
+        self.cursor.advance_log(position);
+        if let Some(update) = pool.adapter.normalize(&pool.market, position.block, Some(log))? {
+            print_update(&update)?;
+        }
+        Ok(())
+    }
+
+    /// Records a new head and, if it orphaned blocks we already consumed,
+    /// rolls pool state and the log cursor back to the common ancestor.
+    /// Returns whether logs need catching up.
+    async fn observe_head<M: Middleware>(&mut self, provider: &M, head: BlockRef) -> Result<bool> {
+        let reorg = track_block(&mut self.blocks, provider, head).await?;
+        if let Some(lowest) = self.blocks.lowest() {
+            self.state.prune_history(lowest);
+        }
+        let Some(reorg) = reorg else {
+            return Ok(self.reload_at.is_some());
+        };
+
+        self.state.rollback_to(reorg.common_ancestor);
+        self.cursor.rewind(EventPosition {
+            block: reorg.common_ancestor.saturating_add(1),
+            log_index: 0,
+        });
+        for pool in &mut self.pools {
+            pool.adapter.sync(&self.state);
+        }
+        let lost_state = self.addresses().iter().any(|pool| self.state.get(*pool).is_none());
+        if reorg.exceeded_tracked_depth || lost_state {
+            self.schedule_reload(reorg.common_ancestor);
+        }
+        println!(
+            "{}",
+            serde_json::to_string(&reorg).context("failed to serialize reorg record")?
+        );
+        Ok(true)
+    }
+}
+
+fn print_update(update: &NormalizedUpdate) -> Result<()> {
+    let json = serde_json::to_string(update).context("failed to serialize pool update")?;
+    println!("{json}");
+    Ok(())
+}
+
+fn sanitize_token(token: &str) -> String {
+    let leading_bytes = token
+        .chars()
+        .take_while(|c| matches!(c, '"' | '\'' | '(' | '[' | '{' | '<'))
+        .map(char::len_utf8)
+        .sum::<usize>();
+    let trailing_bytes = token
+        .chars()
+        .rev()
+        .take_while(|c| matches!(c, '"' | '\'' | ')' | ']' | '}' | '>' | ',' | '.' | ';' | ':'))
+        .map(char::len_utf8)
+        .sum::<usize>();
+
+    if leading_bytes + trailing_bytes >= token.len() {
+        return token.to_string();
+    }
+
+    let core_end = token.len().saturating_sub(trailing_bytes);
+    let core = &token[leading_bytes..core_end];
+    if core.starts_with("https://")
+        || core.starts_with("http://")
+        || core.starts_with("wss://")
+        || core.starts_with("ws://")
+    {
+        let mut masked = String::new();
+        masked.push_str(&token[..leading_bytes]);
+        masked.push_str(&masked_rpc_url(core));
+        masked.push_str(&token[core_end..]);
+        return masked;
+    }
+
+    token.to_string()
+}
+
+fn sanitize_log_text(message: &str) -> String {
+    message
+        .split_whitespace()
+        .map(sanitize_token)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn sanitize_error(err: impl std::fmt::Display) -> String {
+    sanitize_log_text(&err.to_string())
+}
+
+async fn wait_or_shutdown(duration: Duration) -> bool {
+    tokio::select! {
+        _ = sleep(duration) => false,
+        _ = tokio::signal::ctrl_c() => true,
+    }
+}
+
+/// Reads logs from the cursor up to `head` with `eth_getLogs`, reloading
+/// pool state first if a reload is pending or the cursor is too far behind.
+async fn catch_up_logs<M: Middleware>(provider: &M, listener: &mut Listener, head: u64) -> Result<()> {
+    let oldest = head.saturating_sub(MAX_POLL_RANGE_BLOCKS);
+    if listener.cursor.next_block.unwrap_or(head) < oldest {
+        listener.schedule_reload(oldest.saturating_sub(1));
+    }
+    if let Some(block) = listener.reload_at {
+        listener.reload(block).await?;
+    }
+    if listener.addresses().is_empty() {
+        listener.cursor.advance_head(head);
+        return Ok(());
+    }
+    let from_block = listener.cursor.next_block.unwrap_or(head);
+    if from_block > head {
+        return Ok(());
+    }
+
+    let filter = listener.filter().from_block(from_block).to_block(head);
+    let logs = provider
+        .get_logs(&filter)
+        .await
+        .map_err(|err| anyhow::anyhow!("eth_getLogs failed for blocks {from_block}..={head}: {err}"))?;
+    for log in &logs {
+        if let Err(err) = listener.handle_log(log) {
+            eprintln!("log decode failed: {}", sanitize_error(&err));
+        }
+    }
+    listener.cursor.advance_head(head);
+    Ok(())
+}
+
+async fn poll_logs_once(provider: &Provider<Http>, listener: &mut Listener) -> Result<()> {
+    let block = provider
+        .get_block(BlockNumber::Latest)
+        .await
+        .context("eth_getBlockByNumber(latest) failed")?
+        .context("latest block missing")?;
+    let head = BlockRef::from_block(&block)?;
+    listener.observe_head(provider, head).await?;
+    catch_up_logs(provider, listener, head.number).await?;
+    listener.refresh_polled(head.number).await
+}
+
+/// Loads every pool `blocks` blocks behind the head and replays the logs
+/// since, printing their rows, so live updates start from current state.
+async fn warm_start(listener: &mut Listener, blocks: u64) -> Result<()> {
+    let head = listener
+        .provider
+        .get_block_number()
+        .await
+        .context("failed to fetch latest block number")?
+        .as_u64();
+    let loaded_at = head.saturating_sub(blocks);
+    listener.reload(loaded_at).await?;
+    let request = BackfillRequest {
+        addresses: listener.addresses(),
+        topics: listener.topics(),
+        from_block: loaded_at.saturating_add(1),
+        to_block: head,
+        initial_chunk_blocks: blocks,
+        min_chunk_blocks: 1,
+        max_chunk_blocks: blocks,
+        checkpoint_path: None,
+    };
+    let (mut logs_fetched, mut range_splits) = (0, 0);
+    if blocks > 0 && !request.addresses.is_empty() {
+        let provider = listener.provider.clone();
+        let summary = backfill_logs(&provider, &request, |logs, _| {
+            for log in logs {
+                if let Err(err) = listener.handle_log(log) {
+                    eprintln!("log decode failed: {}", sanitize_error(&err));
+                }
+            }
+            Ok(())
+        })
+        .await?;
+        logs_fetched = summary.logs_fetched;
+        range_splits = summary.range_splits;
+    }
+    listener.cursor.advance_head(head);
+    listener.refresh_polled(head).await?;
+    eprintln!(
+        "Warm start complete: loaded_at={loaded_at}, replayed_blocks={blocks}, logs={logs_fetched}, range_splits={range_splits}, pools={}",
+        listener.pools.len()
+    );
+    Ok(())
+}
+
+async fn run_http_polling_window(
+    provider: &Provider<Http>,
+    listener: &mut Listener,
+    poll_interval: Duration,
+    window: Duration,
+) -> bool {
+    eprintln!("Mode: http-fallback (eth_getLogs)");
+    let mut error_gate = ErrorLogGate::new(Duration::from_secs(15));
+
+    let started = Instant::now();
+    loop {
+        if started.elapsed() >= window {
+            error_gate.flush("HTTPS log polling errors");
+            return false;
+        }
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return true,
+            result = poll_logs_once(provider, listener) => {
+                if let Err(err) = result {
+                    error_gate.log("HTTPS log polling error (retrying)", &sanitize_error(&err));
+                }
+            }
+        }
+
+        let remaining = window.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return false;
+        }
+        let sleep_for = poll_interval.min(remaining);
+        if wait_or_shutdown(sleep_for).await {
+            error_gate.flush("HTTPS log polling errors");
+            return true;
+        }
+    }
+}
+
+async fn run_ws_loop(provider: Provider<Ws>, expected_chain_id: u64, listener: &mut Listener) -> WsLoopExit {
+    match provider.get_chainid().await {
+        Ok(actual) => {
+            let actual = actual.as_u64();
+            if actual == expected_chain_id {
+                eprintln!("Mode: ws, chain_id={actual}");
+            } else {
+                eprintln!("Mode: ws, chain_id={actual}, expected_chain_id={expected_chain_id}");
+            }
+        }
+        Err(err) => eprintln!("WS chain id diagnostic failed: {}", sanitize_error(&err)),
+    }
+
+    // With only polled pools there are no logs to subscribe to.
+    let mut logs = if listener.addresses().is_empty() {
+        None
+    } else {
+        match provider.subscribe_logs(&listener.filter()).await {
+            Ok(stream) => Some(stream),
+            Err(err) => {
+                eprintln!("WS log subscribe failed: {}", sanitize_error(&err));
+                return WsLoopExit::Disconnected;
+            }
+        }
+    };
+    let mut blocks = match provider.subscribe_blocks().await {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("WS block subscribe failed: {}", sanitize_error(&err));
+            return WsLoopExit::Disconnected;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return WsLoopExit::Shutdown,
+            maybe_log = next_log(&mut logs) => {
+                match maybe_log {
+                    Some(log) => {
+                        if let Err(err) = listener.handle_log(&log) {
+                            eprintln!("log decode failed: {}", sanitize_error(&err));
+                        }
+                    }
+                    None => {
+                        eprintln!("WebSocket log stream ended.");
+                        return WsLoopExit::Disconnected;
+                    }
+                }
+            }
+            maybe_block = blocks.next() => {
+                let Some(block) = maybe_block else {
+                    eprintln!("WebSocket block stream ended.");
+                    return WsLoopExit::Disconnected;
+                };
+                let head = match BlockRef::from_block(&block) {
+                    Ok(head) => head,
+                    Err(err) => {
+                        eprintln!("block header skipped: {}", sanitize_error(&err));
+                        continue;
+                    }
+                };
+                match listener.observe_head(&provider, head).await {
+                    Ok(true) => {
+                        if let Err(err) = catch_up_logs(&provider, listener, head.number).await {
+                            eprintln!("post-reorg log catch-up failed: {}", sanitize_error(&err));
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(err) => eprintln!("block tracking failed: {}", sanitize_error(&err)),
+                }
+                if let Err(err) = listener.refresh_polled(head.number).await {
+                    eprintln!("pool refresh failed: {}", sanitize_error(&err));
+                }
+            }
+        }
+    }
+}
+
+/// The next log from `logs`, or never when there is no subscription.
+async fn next_log<S: Stream<Item = Log> + Unpin>(logs: &mut Option<S>) -> Option<Log> {
+    match logs {
+        Some(stream) => stream.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let runtime = RuntimeConfig::from_env()?;
+    let pools = PoolListenerConfig::from_env()?;
+    let http = http_provider(&runtime.rpc_https_url)?;
+    let mut tokens = runtime.token_resolver()?;
+    let mut tracked = Vec::with_capacity(pools.markets.len());
+    for config in &pools.markets {
+        let (market, adapter) = config.connect(&http, &mut tokens, &AdapterOptions::default()).await?;
+        tracked.push(TrackedPool { market, adapter });
+    }
+    let ws_connect_timeout = Duration::from_secs(runtime.ws_connect_timeout_secs);
+    let http_poll_interval = Duration::from_secs(runtime.http_poll_interval_secs.max(1));
+
+    eprintln!(
+        "Pool listener start: network={}, ws_provider={}, http_provider={}, pools=[{}], ws_timeout_s={}, http_poll_s={}",
+        runtime.network_name,
+        masked_rpc_url(&runtime.rpc_wss_url),
+        masked_rpc_url(&runtime.rpc_https_url),
+        tracked
+            .iter()
+            .map(|pool| format!("{}:{:#x}", pool.adapter.venue().as_str(), pool.adapter.pool()))
+            .collect::<Vec<_>>()
+            .join(","),
+        ws_connect_timeout.as_secs(),
+        http_poll_interval.as_secs()
+    );
+
+    let mut listener = Listener {
+        provider: http.clone(),
+        pools: tracked,
+        state: PoolStateStore::new(),
+        cursor: LogCursor::default(),
+        blocks: BlockTracker::new(runtime.reorg_track_depth),
+        reload_at: None,
+    };
+    warm_start(&mut listener, pools.warm_start_blocks).await?;
+    let mut ws_attempt: u32 = 0;
+
+    loop {
+        match connect_ws_with_timeout(&runtime.rpc_wss_url, ws_connect_timeout).await {
+            Ok(provider) => {
+                ws_attempt = 0;
+                eprintln!("Connected via WebSocket.");
+
+                match run_ws_loop(provider, runtime.expected_chain_id, &mut listener).await {
+                    WsLoopExit::Shutdown => break,
+                    WsLoopExit::Disconnected => {
+                        ws_attempt = ws_attempt.saturating_add(1);
+                        let wait = reconnect_backoff(
+                            runtime.ws_reconnect_initial_ms,
+                            runtime.ws_reconnect_max_ms,
+                            ws_attempt,
+                        );
+                        eprintln!("WS disconnected. Polling eth_getLogs for {} ms.", wait.as_millis());
+                        if run_http_polling_window(&http, &mut listener, http_poll_interval, wait).await {
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                ws_attempt = ws_attempt.saturating_add(1);
+                let wait = reconnect_backoff(
+                    runtime.ws_reconnect_initial_ms,
+                    runtime.ws_reconnect_max_ms,
+                    ws_attempt,
+                );
+                eprintln!("WS connect failed: {}", sanitize_error(&err));
+                eprintln!("Falling back to eth_getLogs polling for {} ms.", wait.as_millis());
+                if run_http_polling_window(&http, &mut listener, http_poll_interval, wait).await {
+                    break;
+                }
+            }
+        }
+    }
+
+    eprintln!(
+        "Pool listener shutdown complete. tracked_pools={}, pools_with_state={}",
+        listener.pools.len(),
+        listener.state.len()
+    );
+    Ok(())
+}
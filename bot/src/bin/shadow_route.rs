@@ -1,46 +1,381 @@
 use anyhow::{Context, Result};
 use dotenvy::from_filename_override;
-use ethers::abi::{ParamType, Token, decode, encode};
 use ethers::providers::{Http, Middleware, Provider};
-use ethers::types::transaction::eip2718::TypedTransaction;
-use ethers::types::{Address, BlockId, BlockNumber, Bytes, TransactionRequest, U256};
-use ethers::utils::id;
+use ethers::types::{Address, I256, U256};
+use evm_flashloans_l2_arb::capital::{CapitalSource, SourceKind, SourceOffer, cheapest};
+use evm_flashloans_l2_arb::config::{discovery_registry_path, token_metadata_cache_path};
+use evm_flashloans_l2_arb::costs::{L1Fee, L1FeeOracle};
+use evm_flashloans_l2_arb::discovery::{PoolRegistry, SpecDefaults};
+use evm_flashloans_l2_arb::executor::{FlashLoanTx, TokenRiskConfig, operator, token_risk_config};
+use evm_flashloans_l2_arb::fees::{Eip1559Fees, FeeModel};
+use evm_flashloans_l2_arb::gas::{GasHistory, GasModel, GasUnits};
+use evm_flashloans_l2_arb::pathfinding::{Cycle, Hop, TokenGraph};
+use evm_flashloans_l2_arb::pricing::{ChainlinkFeed, GasRate, pool_rate};
+use evm_flashloans_l2_arb::reorg::{BlockRef, BlockTracker, ReorgEvent, track_block};
+use evm_flashloans_l2_arb::selection::{Opportunity, SelectionLimits, TradeCost, Verdict, select};
+use evm_flashloans_l2_arb::sizing::{golden_section_max, optimal_constant_product_input, signed_net};
+use evm_flashloans_l2_arb::tokens::TokenResolver;
+use evm_flashloans_l2_arb::types::market::PoolStateStore;
+use evm_flashloans_l2_arb::venues::{
+    AdapterOptions, PoolAdapter, PoolSpec, SwapCall, Venue, connect, refresh_pool, replay_logs,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::fs;
+use std::path::Path;
 use std::str::FromStr;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+/// Longest gap we catch up through `eth_getLogs` before reloading pool
+/// state from scratch.
+const MAX_STATE_CATCHUP_BLOCKS: u64 = 500;
+
+/// Route name used for the aggregate summary across all routes.
+const AGGREGATE_ROUTE: &str = "all";
+
+/// Route name for rows and summaries from the cycle search.
+const PATHFINDING_ROUTE: &str = "pathfinding";
+
+/// The size search stops once its bracket is this fraction of the range.
+const SIZE_SEARCH_RESOLUTION: u64 = 1_000_000;
+
+/// Top-level sizing, fee and threshold fields are defaults for routes that
+/// do not set their own. `route` is the single-route form and is evaluated
+/// alongside any `routes`.
 #[derive(Debug, Deserialize)]
 struct ShadowConfig {
     network: String,
     chain_id: u64,
     poll_interval_ms: u64,
     max_block_age_secs: u64,
-    route: RouteConfig,
-    input_sizes_wei: Vec<String>,
-    flash_loan_fee_bps: u64,
-    gas_units_estimate: u64,
-    max_gas_price_wei: String,
-    min_profit_wei: String,
+    #[serde(default)]
+    route: Option<RouteConfig>,
+    #[serde(default)]
+    routes: Vec<RouteConfig>,
+    #[serde(default)]
+    pathfinding: Option<PathfindingConfig>,
+    #[serde(default)]
+    selection: SelectionConfig,
+    #[serde(default)]
+    capital_sources: Vec<CapitalSourceConfig>,
+    #[serde(default)]
+    fee_model: FeeModelConfig,
+    #[serde(default)]
+    gas_model: GasModelConfig,
+    /// The wrapped native token gas is paid in; loan tokens other than it
+    /// need a `gas_pricing` entry.
+    #[serde(default = "default_native_token")]
+    native_token: String,
+    #[serde(default)]
+    gas_pricing: Vec<GasPricingConfig>,
+    #[serde(flatten)]
+    defaults: RouteSettings,
+    #[serde(default, alias = "v3_quote_mode")]
+    quote_mode: QuoteMode,
+    #[serde(default = "default_v3_bitmap_word_radius")]
+    v3_bitmap_word_radius: i16,
+}
+
+/// How each leg is quoted: `local` simulates against the adapter's cached
+/// pool state (falling back to the venue's on-chain quoter, e.g. QuoterV2 or
+/// Vault `queryBatchSwap`, if the local quote fails), `quoter` always calls
+/// the on-chain quoter, and `verify` does both and reports any difference.
+/// Venues without an on-chain quoter are always quoted locally.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum QuoteMode {
+    #[default]
+    Local,
+    Quoter,
+    Verify,
+}
+
+/// Which way a route's cycle is traded. `Forward` follows the configured
+/// legs; `Reverse` runs the same pools backwards, selling each leg's
+/// `token_out` for its `token_in`. Search cycles are always `Forward`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+impl Direction {
+    const ALL: [Direction; 2] = [Direction::Forward, Direction::Reverse];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Forward => "forward",
+            Direction::Reverse => "reverse",
+        }
+    }
+
+    /// The route's hops in trading order.
+    fn hops(self, route: &ParsedRoute) -> Vec<Hop> {
+        match self {
+            Direction::Forward => route.hops.clone(),
+            Direction::Reverse => route
+                .hops
+                .iter()
+                .rev()
+                .map(|hop| Hop {
+                    pool: hop.pool,
+                    token_in: hop.token_out,
+                    token_out: hop.token_in,
+                })
+                .collect(),
+        }
+    }
+}
+
+struct LegQuote {
+    amount_out: U256,
+    source: &'static str,
+    mismatch: Option<String>,
+    /// The on-chain quoter's `gasEstimate`, when one was called.
+    gas_estimate: Option<u64>,
+}
+
+/// Per-route sizing, fees and thresholds. Unset fields fall back to the
+/// config's top-level values. Every route is sized by search up to
+/// `max_input_wei`; `input_sizes_wei` adds fixed sizes for comparison.
+#[derive(Clone, Debug, Default, Deserialize)]
+struct RouteSettings {
+    #[serde(default)]
+    max_input_wei: Option<String>,
+    #[serde(default)]
+    input_sizes_wei: Option<Vec<String>>,
+    #[serde(default)]
+    flash_loan_fee_bps: Option<u64>,
+    #[serde(default)]
+    max_base_fee_wei: Option<String>,
+    #[serde(default)]
+    max_priority_fee_wei: Option<String>,
+    #[serde(default)]
+    min_profit_wei: Option<String>,
+    #[serde(default)]
+    execution: Option<Execution>,
+}
+
+/// How a cycle is funded. `flash_loan` borrows the input from a flash-loan
+/// source. `v2_flash_swap` applies when the first hop trades on a Uniswap V2
+/// pair: the pair sends its output up front and is repaid the hop's input
+/// in `uniswapV2Call`, so the pair's swap fee, already in the quote, is the
+/// only charge. Paths starting on any other venue fall back to a flash loan.
+/// The executor contract has no `uniswapV2Call`, so flash swaps are priced
+/// for comparison only.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Execution {
+    #[default]
+    FlashLoan,
+    V2FlashSwap,
 }
 
+impl Execution {
+    fn as_str(self) -> &'static str {
+        match self {
+            Execution::FlashLoan => "flash_loan",
+            Execution::V2FlashSwap => "v2_flash_swap",
+        }
+    }
+}
+
+/// A cycle through `legs` in order: each leg's `token_out` is the next leg's
+/// `token_in`, and the last leg returns the first leg's `token_in`, which is
+/// the loan token. With `reverse` (the default) the same pools are also
+/// quoted the other way round.
 #[derive(Debug, Deserialize)]
 struct RouteConfig {
     name: String,
-    token_in_symbol: String,
-    token_mid_symbol: String,
-    token_out_symbol: String,
-    token_in_address: String,
-    token_mid_address: String,
-    token_in_decimals: u8,
-    token_mid_decimals: u8,
-    v2_pair: String,
-    v2_fee_bps: u64,
-    v3_pool: String,
-    v3_pool_fee: u32,
-    v3_quoter_v2: String,
+    legs: Vec<LegConfig>,
+    #[serde(default = "default_reverse")]
+    reverse: bool,
+    #[serde(flatten)]
+    settings: RouteSettings,
+}
+
+/// One swap of a route. `token_in_symbol` and `token_in_decimals` are
+/// optional checks against the token contract.
+#[derive(Debug, Deserialize)]
+struct LegConfig {
+    #[serde(flatten)]
+    pool: PoolConfig,
+    token_in: String,
+    token_out: String,
+    #[serde(default)]
+    token_in_symbol: Option<String>,
+    #[serde(default)]
+    token_in_decimals: Option<u8>,
+}
+
+/// Cycle search over every tracked pool: the routes' pools plus `pools` and
+/// any loaded from the discovery `registry`. Cycles start and end in one of
+/// `start_tokens`, which should be tokens the flash-loan source can lend.
+/// Sizes and thresholds fall back to the top-level values like a route's.
+#[derive(Debug, Deserialize)]
+struct PathfindingConfig {
+    start_tokens: Vec<String>,
+    #[serde(default = "default_max_hops")]
+    max_hops: usize,
+    /// Best cycles per start token that get fully quoted each block.
+    #[serde(default = "default_max_cycles_per_token")]
+    max_cycles_per_token: usize,
+    #[serde(default)]
+    pools: Vec<PoolConfig>,
+    #[serde(default)]
+    registry: Option<RegistryConfig>,
+    #[serde(flatten)]
+    settings: RouteSettings,
+}
+
+/// Pools to load from the `discover_pools` registry at
+/// `DISCOVERY_REGISTRY_PATH`: every pool whose two tokens are both among
+/// `tokens` and the start tokens. Creation events carry no Uniswap V2 fee or
+/// quoter, so those come from here; concentrated pools on a venue without a
+/// quoter are left out.
+#[derive(Debug, Deserialize)]
+struct RegistryConfig {
+    #[serde(default)]
+    tokens: Vec<String>,
+    #[serde(default = "default_registry_v2_fee_bps")]
+    uniswap_v2_fee_bps: u64,
+    #[serde(default)]
+    uniswap_v3_quoter: Option<String>,
+    #[serde(default)]
+    slipstream_quoter: Option<String>,
+}
+
+/// How many of a block's `would_trade` rows to act on. With `bundle` the
+/// chosen trades share one transaction and may share pools; otherwise each
+/// is sent alone and pools must not overlap.
+#[derive(Debug, Deserialize)]
+struct SelectionConfig {
+    #[serde(default = "default_max_selected")]
+    max_selected: usize,
+    #[serde(default)]
+    bundle: bool,
+}
+
+impl Default for SelectionConfig {
+    fn default() -> Self {
+        Self {
+            max_selected: default_max_selected(),
+            bundle: false,
+        }
+    }
+}
+
+/// How a send's fees are priced: the priority fee is the median over the
+/// last `history_blocks` of each block's `priority_percentile` tip, and
+/// `maxFeePerGas` is the base fee times `base_fee_headroom_bps` plus that.
+#[derive(Debug, Deserialize)]
+struct FeeModelConfig {
+    #[serde(default = "default_fee_history_blocks")]
+    history_blocks: u64,
+    #[serde(default = "default_priority_percentile")]
+    priority_percentile: f64,
+    #[serde(default = "default_base_fee_headroom_bps")]
+    base_fee_headroom_bps: u64,
+}
+
+impl Default for FeeModelConfig {
+    fn default() -> Self {
+        Self {
+            history_blocks: default_fee_history_blocks(),
+            priority_percentile: default_priority_percentile(),
+            base_fee_headroom_bps: default_base_fee_headroom_bps(),
+        }
+    }
+}
+
+/// Gas charged per cycle before anything better is known: the executor's
+/// flash loan and callback until `eth_estimateGas` has measured the route,
+/// plus `per_leg_overhead` on each leg's swap. `history_depth` executor
+/// estimates are kept per route.
+#[derive(Debug, Deserialize)]
+struct GasModelConfig {
+    #[serde(default = "default_flash_loan_overhead")]
+    flash_loan_overhead: u64,
+    #[serde(default = "default_per_leg_overhead")]
+    per_leg_overhead: u64,
+    #[serde(default = "default_gas_history_depth")]
+    history_depth: usize,
+}
+
+impl Default for GasModelConfig {
+    fn default() -> Self {
+        Self {
+            flash_loan_overhead: default_flash_loan_overhead(),
+            per_leg_overhead: default_per_leg_overhead(),
+            history_depth: default_gas_history_depth(),
+        }
+    }
+}
+
+/// How gas is converted into `token`: through a reference pool trading it
+/// against the native token, or a Chainlink feed.
+#[derive(Debug, Deserialize)]
+struct GasPricingConfig {
+    token: String,
+    #[serde(flatten)]
+    source: GasRateConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum GasRateConfig {
+    ReferencePool(PoolConfig),
+    Chainlink(ChainlinkConfig),
+}
+
+/// A feed pricing the native token in the loan token, or with `invert` the
+/// loan token in the native token.
+#[derive(Debug, Deserialize)]
+struct ChainlinkConfig {
+    feed: String,
+    #[serde(default)]
+    invert: bool,
+    #[serde(default = "default_feed_max_age_secs")]
+    max_age_secs: u64,
+}
+
+/// A flash-loan source to borrow from: `address` is the Balancer Vault, the
+/// Aave V3 Pool, a Uniswap V3 pool or Morpho Blue. With none configured,
+/// loans cost `flash_loan_fee_bps`.
+#[derive(Debug, Deserialize)]
+struct CapitalSourceConfig {
+    kind: SourceKind,
+    address: String,
+}
+
+/// A pool as configured on a route leg or for the cycle search. Fields
+/// mirror [`PoolSpec`].
+#[derive(Debug, Deserialize)]
+struct PoolConfig {
+    venue: Venue,
+    pool: String,
+    #[serde(default)]
+    fee_bps: Option<u64>,
+    #[serde(default)]
+    fee_pips: Option<u32>,
+    #[serde(default)]
+    tick_spacing: Option<i32>,
+    #[serde(default)]
+    quoter: Option<String>,
+}
+
+impl PoolConfig {
+    fn spec(&self) -> Result<PoolSpec> {
+        Ok(PoolSpec {
+            venue: self.venue,
+            pool: parse_address(&self.pool)?,
+            fee_bps: self.fee_bps,
+            fee_pips: self.fee_pips,
+            tick_spacing: self.tick_spacing,
+            quoter: self.quoter.as_deref().map(parse_address).transpose()?,
+        })
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -49,57 +384,677 @@ struct ShadowDecisionLog {
     ts_unix_ms: u64,
     network: String,
     route: String,
+    direction: String,
+    /// The search cycle's path, on pathfinding rows only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cycle: Option<String>,
     block: u64,
     block_age_secs: u64,
+    sizing: String,
+    sizing_method: String,
+    max_input_wei: String,
+    optimal_input_wei: String,
+    expected_profit_wei: String,
     input_wei: String,
+    /// The block's base fee and the sampled tip; `gas_price_wei` is their
+    /// sum, what L2 execution is charged at, and `max_fee_per_gas_wei` the
+    /// cap a send would carry.
+    base_fee_wei: String,
+    priority_fee_wei: String,
     gas_price_wei: String,
-    gas_cost_wei: String,
+    max_fee_per_gas_wei: String,
+    /// The cycle's gas: the executor's share, from `executor_gas_source`
+    /// (`simulated`, `learned` or `model`), plus every leg's.
+    gas_units: u64,
+    executor_gas_units: u64,
+    executor_gas_source: String,
+    l2_execution_cost_wei: String,
+    /// The L2 and L1 gas in loan-token wei, converted at
+    /// `gas_rate_loan_per_native` loan-token wei per native token from
+    /// `gas_rate_source` (`native`, `reference_pool` or `chainlink`).
+    gas_cost_loan_wei: String,
+    gas_rate_source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gas_rate_address: Option<Address>,
+    gas_rate_loan_per_native: String,
+    /// The L1 data fee for posting the row's `executeFlashLoan` transaction,
+    /// and whether it is `get_l1_fee` for the quoted swaps or the
+    /// `get_l1_fee_upper_bound` used for sizing.
+    l1_data_cost_wei: String,
+    l1_fee_method: String,
     flash_fee_wei: String,
+    execution: String,
+    flash_source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flash_source_address: Option<Address>,
+    /// The executor cannot borrow from `flash_source`, so the row is priced
+    /// without `eth_estimateGas` and never trades.
+    hypothetical: bool,
+    /// On flash-swap rows: what a Balancer flash loan of the same input
+    /// would cost, and this row's flash fee less that (negative is cheaper).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balancer_flash_fee_wei: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost_vs_balancer_wei: Option<String>,
     total_cost_wei: String,
-    v2_out_mid_wei: String,
-    v3_out_wei: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    marginal_edge_bps: Option<f64>,
+    legs: Vec<LegLog>,
+    output_wei: String,
     net_wei: String,
     edge_bps: String,
-    v3_quote_latency_ms: u64,
     decision: String,
     reason: String,
 }
 
-#[derive(Debug)]
+/// One leg of a row's cycle in trading order. Legs that were not quoted
+/// (the row was skipped first, or an earlier leg failed) keep an output of
+/// zero and a `quote_source` of `none`.
+#[derive(Clone, Debug, Serialize)]
+struct LegLog {
+    venue: String,
+    pool: Address,
+    token_in: Address,
+    token_out: Address,
+    amount_out_wei: String,
+    quote_source: String,
+    quote_latency_us: u64,
+    /// Swap gas charged for the leg, from `gas_source`: `quoter`, `learned`
+    /// or the venue `default`.
+    gas_units: u64,
+    gas_source: String,
+}
+
+impl LegLog {
+    fn unquoted(pools: &[Box<dyn PoolAdapter>], hop: &Hop) -> Self {
+        Self {
+            venue: pools[hop.pool].venue().as_str().to_string(),
+            pool: pools[hop.pool].pool(),
+            token_in: hop.token_in,
+            token_out: hop.token_out,
+            amount_out_wei: "0".to_string(),
+            quote_source: "none".to_string(),
+            quote_latency_us: 0,
+            gas_units: 0,
+            gas_source: "none".to_string(),
+        }
+    }
+}
+
+/// A cycle through `hops`, which index into the run's shared pool list.
+/// `hops[0].token_in` is the loan token.
 struct ParsedRoute {
     name: String,
-    token_in: Address,
-    token_mid: Address,
-    v2_pair: Address,
-    v2_fee_bps: u64,
-    v2_token0_to1: bool,
-    v3_pool: Address,
-    v3_pool_fee: u32,
-    v3_quoter_v2: Address,
+    hops: Vec<Hop>,
+    reverse: bool,
+    limits: RouteLimits,
+}
+
+impl ParsedRoute {
+    fn directions(&self) -> &'static [Direction] {
+        if self.reverse {
+            &Direction::ALL
+        } else {
+            &Direction::ALL[..1]
+        }
+    }
+
+    fn loan_token(&self) -> Address {
+        self.hops[0].token_in
+    }
+}
+
+/// [`RouteSettings`] resolved against the top-level defaults.
+struct RouteLimits {
+    max_input: U256,
+    input_sizes: Vec<U256>,
+    flash_loan_fee_bps: u64,
+    max_base_fee: U256,
+    max_priority_fee: U256,
+    min_profit: U256,
+    execution: Execution,
+}
+
+impl RouteLimits {
+    fn resolve(settings: &RouteSettings, defaults: &RouteSettings) -> Result<Self> {
+        let max_input = settings
+            .max_input_wei
+            .as_ref()
+            .or(defaults.max_input_wei.as_ref())
+            .context("max_input_wei is not set for the route or at the top level")?;
+        let input_sizes = settings
+            .input_sizes_wei
+            .as_ref()
+            .or(defaults.input_sizes_wei.as_ref())
+            .map(|sizes| parse_u256_list(sizes))
+            .transpose()?
+            .unwrap_or_default();
+        let max_base_fee = settings
+            .max_base_fee_wei
+            .as_ref()
+            .or(defaults.max_base_fee_wei.as_ref())
+            .context("max_base_fee_wei is not set for the route or at the top level")?;
+        let max_priority_fee = settings
+            .max_priority_fee_wei
+            .as_ref()
+            .or(defaults.max_priority_fee_wei.as_ref())
+            .context("max_priority_fee_wei is not set for the route or at the top level")?;
+        let min_profit = settings
+            .min_profit_wei
+            .as_ref()
+            .or(defaults.min_profit_wei.as_ref())
+            .context("min_profit_wei is not set for the route or at the top level")?;
+        Ok(Self {
+            max_input: parse_u256_dec(max_input)?,
+            input_sizes,
+            flash_loan_fee_bps: settings
+                .flash_loan_fee_bps
+                .or(defaults.flash_loan_fee_bps)
+                .context("flash_loan_fee_bps is not set for the route or at the top level")?,
+            max_base_fee: parse_u256_dec(max_base_fee)?,
+            max_priority_fee: parse_u256_dec(max_priority_fee)?,
+            min_profit: parse_u256_dec(min_profit)?,
+            execution: settings.execution.or(defaults.execution).unwrap_or_default(),
+        })
+    }
+
+    /// The base-fee and priority-fee caps, then profit after the input,
+    /// flash fee and gas, all in integer wei.
+    fn decide(&self, fees: &Eip1559Fees, output: U256, total_cost: U256) -> (&'static str, &'static str) {
+        if fees.base_fee > self.max_base_fee {
+            ("would_skip", "base_fee_too_high")
+        } else if fees.priority_fee > self.max_priority_fee {
+            ("would_skip", "priority_fee_too_high")
+        } else if output <= total_cost || output - total_cost < self.min_profit {
+            ("would_skip", "below_min_profit")
+        } else {
+            ("would_trade", "edge_above_threshold")
+        }
+    }
+}
+
+/// A row that would trade, kept until the block's selection.
+struct TradeCandidate {
+    route: String,
+    direction: Direction,
+    cycle: Option<String>,
+    sizing: &'static str,
+    hops: Vec<Hop>,
+    input: U256,
+    cost: TradeCost,
+    output: U256,
+    min_profit: U256,
+    /// Prices the loan of a bundle this trade joins.
+    loan: LoanPricing,
+}
+
+impl TradeCandidate {
+    fn label(&self) -> String {
+        match &self.cycle {
+            Some(cycle) => format!("{}/{}/{}", self.route, cycle, self.sizing),
+            None => format!("{}/{}/{}", self.route, self.direction.as_str(), self.sizing),
+        }
+    }
+}
+
+/// The selector's verdict on one candidate. `bundle_net_wei` is the profit
+/// re-quoted after the trades selected before it, when bundling.
+#[derive(Debug, Serialize)]
+struct ShadowSelectionLog {
+    run_id: String,
+    ts_unix_ms: u64,
+    network: String,
+    block: u64,
+    route: String,
+    direction: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cycle: Option<String>,
+    sizing: String,
+    candidate: String,
+    rank: usize,
+    loan_token: Address,
+    input_wei: String,
+    net_wei: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bundle_net_wei: Option<String>,
+    decision: String,
+    reason: String,
+}
+
+struct Pathfinder {
+    start_tokens: Vec<Address>,
+    max_hops: usize,
+    max_cycles_per_token: usize,
+    limits: RouteLimits,
+}
+
+/// Pools shared by every route: each distinct pool is tracked once, so
+/// routes through the same pool see the same state and cost one refresh.
+#[derive(Default)]
+struct PoolSet {
+    specs: Vec<PoolSpec>,
+    adapters: Vec<Box<dyn PoolAdapter>>,
+    state: PoolStateStore,
+}
+
+impl PoolSet {
+    fn contains(&self, pool: Address) -> bool {
+        self.specs.iter().any(|known| known.pool == pool)
+    }
+
+    fn forget_all(&mut self) {
+        for adapter in &self.adapters {
+            self.state.forget(adapter.pool());
+        }
+    }
+
+    /// Returns the index of the pool for `spec`, connecting it on first use.
+    /// The same pool configured two different ways is an error.
+    async fn get_or_connect(
+        &mut self,
+        provider: &Provider<Http>,
+        spec: PoolSpec,
+        options: &AdapterOptions,
+    ) -> Result<usize> {
+        if let Some(index) = self.specs.iter().position(|known| known.pool == spec.pool) {
+            if self.specs[index] != spec {
+                anyhow::bail!(
+                    "pool {:#x} is configured differently by two routes: {:?} vs {:?}",
+                    spec.pool,
+                    self.specs[index],
+                    spec
+                );
+            }
+            return Ok(index);
+        }
+        let adapter = connect(provider, &spec, options)
+            .await
+            .with_context(|| format!("route leg {} pool {:#x}", spec.venue.as_str(), spec.pool))?;
+        self.specs.push(spec);
+        self.adapters.push(adapter);
+        Ok(self.adapters.len() - 1)
+    }
+}
+
+/// Identifiers and settings shared by every row of a run.
+struct RunContext<'a> {
+    run_id: &'a str,
+    network: &'a str,
+    chain_id: u64,
+    quote_mode: QuoteMode,
+    selection: SelectionLimits,
+    /// The flash-loan contract, or the zero address when it is not set;
+    /// either way the same length for the L1 data fee.
+    executor: Address,
+    /// The contract's `operator()`, which `eth_estimateGas` sends from;
+    /// `None` without `FLASH_LOAN_CONTRACT`.
+    operator: Option<Address>,
+    l1_oracle: L1FeeOracle,
+    gas_model: GasModel,
+}
+
+/// Chain state every route is evaluated against in one block.
+struct BlockSnapshot<'a> {
+    block: u64,
+    block_age_secs: u64,
+    fees: Eip1559Fees,
+    loan_caps: &'a LoanCaps,
+    capital: &'a CapitalBook,
+    gas_rates: &'a GasRates,
+}
+
+/// What gas costs in each loan token, re-read every block. A rate that
+/// cannot be read is missing for that block.
+struct GasRates {
+    native: Address,
+    sources: BTreeMap<Address, GasRateSource>,
+    rates: BTreeMap<Address, GasRate>,
+}
+
+enum GasRateSource {
+    /// Index of the reference pool in the run's pool list.
+    Pool(usize),
+    Chainlink { feed: ChainlinkFeed, token_decimals: u8 },
+}
+
+impl GasRates {
+    async fn refresh(
+        &mut self,
+        provider: &Provider<Http>,
+        pools: &[Box<dyn PoolAdapter>],
+        block: u64,
+        block_timestamp: u64,
+        errors: &mut ErrorLogGate,
+    ) {
+        for (token, source) in &self.sources {
+            let rate = match source {
+                GasRateSource::Pool(index) => pool_rate(pools[*index].as_ref(), self.native, *token),
+                GasRateSource::Chainlink { feed, token_decimals } => {
+                    feed.rate(provider, *token_decimals, block, block_timestamp).await
+                }
+            };
+            match rate {
+                Ok(rate) => {
+                    self.rates.insert(*token, rate);
+                }
+                Err(err) => {
+                    self.rates.remove(token);
+                    errors.log("gas rate read failed", &sanitize_error(&err));
+                }
+            }
+        }
+    }
+
+    fn rate(&self, token: Address) -> Option<GasRate> {
+        if token == self.native {
+            return Some(GasRate::native());
+        }
+        self.rates.get(&token).copied()
+    }
+}
+
+/// Every configured flash-loan source's offer for each loan token,
+/// re-read every block. A source whose read fails is left out for that
+/// block.
+struct CapitalBook {
+    sources: Vec<CapitalSource>,
+    tokens: BTreeSet<Address>,
+    offers: BTreeMap<Address, Vec<SourceOffer>>,
+}
+
+impl CapitalBook {
+    async fn refresh(&mut self, provider: &Provider<Http>, block: u64, errors: &mut ErrorLogGate) {
+        self.offers.clear();
+        for token in &self.tokens {
+            let mut offers = Vec::with_capacity(self.sources.len());
+            for source in &self.sources {
+                match source.offer(provider, *token, block).await {
+                    Ok(Some(offer)) => offers.push(offer),
+                    Ok(None) => {}
+                    Err(err) => errors.log(
+                        &format!("{} offer read failed", source.kind.as_str()),
+                        &sanitize_error(&err),
+                    ),
+                }
+            }
+            self.offers.insert(*token, offers);
+        }
+    }
+
+    /// How a loan for `hops` is priced this block. Sources that would lock
+    /// one of the path's pools, or whose rate is above the loan token's
+    /// `max_fee_bps`, are left out.
+    fn pricing(
+        &self,
+        pools: &[Box<dyn PoolAdapter>],
+        hops: &[Hop],
+        limits: &RouteLimits,
+        max_fee_bps: Option<u16>,
+    ) -> LoanPricing {
+        let first = pools[hops[0].pool].as_ref();
+        if limits.execution == Execution::V2FlashSwap && first.venue() == Venue::UniswapV2 {
+            return LoanPricing::FlashSwap(first.pool());
+        }
+        let flash_loan_fee_bps = limits.flash_loan_fee_bps;
+        if self.sources.is_empty() {
+            return LoanPricing::Fixed(flash_loan_fee_bps);
+        }
+        let offers = self
+            .offers
+            .get(&hops[0].token_in)
+            .map(|offers| {
+                offers
+                    .iter()
+                    .filter(|offer| {
+                        !hops
+                            .iter()
+                            .any(|hop| offer.locks(pools[hop.pool].venue(), pools[hop.pool].pool()))
+                    })
+                    .filter(|offer| max_fee_bps.is_none_or(|max| offer.fee.ceil_bps() <= u64::from(max)))
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default();
+        LoanPricing::Sources { offers, max_fee_bps }
+    }
+
+    /// What borrowing `amount` of `token` from Balancer would cost: the
+    /// Vault's offer when Balancer is a configured source, otherwise
+    /// `flash_loan_fee_bps`, which assumes Balancer.
+    fn balancer_fee(&self, token: Address, amount: U256, flash_loan_fee_bps: u64) -> U256 {
+        self.offers
+            .get(&token)
+            .and_then(|offers| offers.iter().find(|offer| offer.kind == SourceKind::Balancer))
+            .map_or_else(|| fee_from_bps(amount, flash_loan_fee_bps), |offer| offer.fee.fee(amount))
+    }
+}
+
+/// The loan's cost: the route's `flash_loan_fee_bps`, the cheapest source
+/// offer that covers the size within the token's `max_fee_bps`, or nothing
+/// for a flash swap on the first hop's pair.
+#[derive(Clone)]
+enum LoanPricing {
+    Fixed(u64),
+    Sources {
+        offers: Vec<SourceOffer>,
+        max_fee_bps: Option<u16>,
+    },
+    FlashSwap(Address),
+}
+
+#[derive(Clone, Copy)]
+struct LoanChoice {
+    source: &'static str,
+    address: Option<Address>,
+    fee: U256,
+    hypothetical: bool,
+}
+
+impl LoanPricing {
+    fn fund(&self, amount: U256) -> Option<LoanChoice> {
+        match self {
+            LoanPricing::Fixed(bps) => Some(LoanChoice {
+                source: "flash_loan_fee_bps",
+                address: None,
+                fee: fee_from_bps(amount, *bps),
+                hypothetical: false,
+            }),
+            LoanPricing::Sources { offers, max_fee_bps } => {
+                cheapest(offers, amount, *max_fee_bps).map(|funding| LoanChoice {
+                    source: funding.kind.as_str(),
+                    address: Some(funding.address),
+                    fee: funding.fee,
+                    hypothetical: !funding.kind.executable(),
+                })
+            }
+            LoanPricing::FlashSwap(pair) => Some(LoanChoice {
+                source: Execution::V2FlashSwap.as_str(),
+                address: Some(*pair),
+                fee: U256::zero(),
+                hypothetical: true,
+            }),
+        }
+    }
+
+    fn execution(&self) -> Execution {
+        match self {
+            LoanPricing::FlashSwap(_) => Execution::V2FlashSwap,
+            _ => Execution::FlashLoan,
+        }
+    }
+
+    /// The most any source can lend.
+    fn max_amount(&self) -> U256 {
+        match self {
+            LoanPricing::Fixed(_) | LoanPricing::FlashSwap(_) => U256::MAX,
+            LoanPricing::Sources { offers, .. } => offers
+                .iter()
+                .map(|offer| offer.available)
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The cheapest rate in whole basis points, for the closed-form size.
+    fn sizing_fee_bps(&self) -> u64 {
+        match self {
+            LoanPricing::Fixed(bps) => *bps,
+            LoanPricing::FlashSwap(_) => 0,
+            LoanPricing::Sources { offers, .. } => offers
+                .iter()
+                .map(|offer| offer.fee.ceil_bps())
+                .min()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Per-token borrowing limits from the flash-loan contract's
+/// `tokenRiskConfig`, re-read every block. Without `FLASH_LOAN_CONTRACT`
+/// only the routes' `max_input_wei` applies.
+struct LoanCaps {
+    contract: Option<Address>,
+    tokens: BTreeSet<Address>,
+    configs: BTreeMap<Address, TokenRiskConfig>,
+}
+
+impl LoanCaps {
+    /// Reads every loan token's config at `block`. A failed read keeps the
+    /// last value seen for that token.
+    async fn refresh(&mut self, provider: &Provider<Http>, block: u64, errors: &mut ErrorLogGate) {
+        let Some(contract) = self.contract else {
+            return;
+        };
+        for token in &self.tokens {
+            match token_risk_config(provider, contract, *token, block).await {
+                Ok(config) => {
+                    self.configs.insert(*token, config);
+                }
+                Err(err) => errors.log("token risk config read failed", &sanitize_error(&err)),
+            }
+        }
+    }
+
+    /// The largest size to consider borrowing `token`, or the skip reason
+    /// when the contract would not lend it at all.
+    fn cap(&self, token: Address, max_input: U256) -> std::result::Result<U256, &'static str> {
+        if self.contract.is_none() {
+            return Ok(max_input);
+        }
+        match self.configs.get(&token) {
+            Some(config) => match config.loan_cap() {
+                Some(cap) if !cap.is_zero() => Ok(cap.min(max_input)),
+                _ => Err("loan_token_disabled"),
+            },
+            None => Err("quote_error:loan_cap_unavailable"),
+        }
+    }
+
+    /// The contract's `maxFeeBps` for `token`, once it has been read.
+    fn max_fee_bps(&self, token: Address) -> Option<u16> {
+        self.configs.get(&token).map(|config| config.max_fee_bps)
+    }
+}
+
+/// How a row's input size was picked and what the optimizer expected
+/// there. `expected_profit` is net of the flash fee and gas.
+#[derive(Clone, Copy)]
+struct SizeChoice {
+    method: &'static str,
+    max_input: U256,
+    optimal_input: U256,
+    expected_profit: I256,
+}
+
+impl SizeChoice {
+    fn none(max_input: U256) -> Self {
+        Self {
+            method: "none",
+            max_input,
+            optimal_input: U256::zero(),
+            expected_profit: I256::zero(),
+        }
+    }
+}
+
+/// One cycle to size and quote: a configured route in one direction, or a
+/// cycle from the search.
+struct PathCandidate<'a> {
+    route: &'a str,
+    direction: Direction,
+    cycle: Option<String>,
+    marginal_edge_bps: Option<f64>,
+    hops: &'a [Hop],
+    limits: &'a RouteLimits,
+}
+
+impl PathCandidate<'_> {
+    /// Names the path in the gas history: `route/direction`, or
+    /// `pathfinding/<cycle>` for a search cycle.
+    fn gas_key(&self) -> String {
+        match &self.cycle {
+            Some(cycle) => format!("{}/{}", self.route, cycle),
+            None => format!("{}/{}", self.route, self.direction.as_str()),
+        }
+    }
 }
 
+#[derive(Clone, Copy)]
 struct EmitContext<'a> {
     run_id: &'a str,
     network: &'a str,
     route: &'a str,
+    direction: Direction,
+    cycle: Option<&'a str>,
+    marginal_edge_bps: Option<f64>,
     block: u64,
     block_age_secs: u64,
+    sizing: &'a str,
+    size: SizeChoice,
     input: U256,
-    gas_price: U256,
-    gas_cost: U256,
+    fees: Eip1559Fees,
+    gas_units: u64,
+    executor_gas: GasUnits,
+    l2_cost: U256,
+    l1_fee: L1Fee,
+    gas_rate: Option<GasRate>,
     flash_fee: U256,
-    v2_out_mid: U256,
-    v3_out: U256,
-    v3_quote_latency_ms: u64,
+    execution: Execution,
+    flash_source: &'a str,
+    flash_source_address: Option<Address>,
+    hypothetical: bool,
+    balancer_flash_fee: Option<U256>,
+    legs: &'a [LegLog],
+    output: U256,
+}
+
+impl EmitContext<'_> {
+    /// L2 and L1 gas in loan-token wei; unconverted when the block has no
+    /// rate, which only skipped rows carry.
+    fn gas_cost(&self) -> U256 {
+        let native = self.l2_cost.saturating_add(self.l1_fee.wei);
+        self.gas_rate.map_or(native, |rate| rate.convert(native))
+    }
+
+    /// The executor's share of `gas_cost`, which a bundle pays once.
+    fn executor_cost(&self) -> U256 {
+        let native = self
+            .fees
+            .effective_gas_price()
+            .saturating_mul(U256::from(self.executor_gas.units));
+        self.gas_rate.map_or(native, |rate| rate.convert(native))
+    }
 }
 
 struct ErrorEmitContext<'a> {
     run_id: &'a str,
     network: &'a str,
     route: &'a str,
+    direction: Direction,
     block: u64,
     block_age_secs: u64,
     input_sizes: &'a [U256],
+    legs: &'a [LegLog],
 }
 
 struct ErrorLogGate {
@@ -147,9 +1102,19 @@ impl ErrorLogGate {
     }
 }
 
+#[derive(Serialize)]
+struct ShadowReorgLog {
+    run_id: String,
+    ts_unix_ms: u64,
+    network: String,
+    #[serde(flatten)]
+    reorg: ReorgEvent,
+}
+
 #[derive(Default)]
 struct ShadowStats {
     blocks_seen: u64,
+    reorgs_seen: u64,
     rows_emitted: u64,
     would_trade: u64,
     would_skip: u64,
@@ -179,6 +1144,7 @@ struct ShadowSummaryLog {
     summary_kind: String,
     latest_block: u64,
     blocks_seen: u64,
+    reorgs_seen: u64,
     rows_emitted: u64,
     would_trade: u64,
     would_skip: u64,
@@ -191,6 +1157,21 @@ struct ReasonCount {
     count: u64,
 }
 
+/// Row counters per route plus the aggregate over all routes. Block and
+/// reorg counts are shared, so only the aggregate tracks them.
+#[derive(Default)]
+struct ShadowStatsBook {
+    aggregate: ShadowStats,
+    routes: BTreeMap<String, ShadowStats>,
+}
+
+impl ShadowStatsBook {
+    fn record(&mut self, route: &str, decision: &str, reason: &str) {
+        self.aggregate.record(decision, reason);
+        self.routes.entry(route.to_string()).or_default().record(decision, reason);
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     from_filename_override(".env").ok();
@@ -201,13 +1182,46 @@ async fn main() -> Result<()> {
         .unwrap_or_else(|| "bot/config/routes.base.json".to_string());
 
     let config = load_config(&config_path)?;
+    if config.selection.bundle && config.quote_mode == QuoteMode::Quoter {
+        anyhow::bail!(
+            "selection.bundle re-quotes trades on local pool state, which quote_mode \"quoter\" does not keep; use \"local\" or \"verify\""
+        );
+    }
     let provider = http_provider_from_env()?;
     validate_network(&provider, config.chain_id).await?;
 
-    let max_gas_price = parse_u256_dec(&config.max_gas_price_wei)?;
-    let min_profit = parse_u256_dec(&config.min_profit_wei)?;
-    let input_sizes = parse_u256_list(&config.input_sizes_wei)?;
-    let route = parse_and_validate_route(&provider, &config.route).await?;
+    let mut tokens = TokenResolver::open(token_metadata_cache_path(config.chain_id), config.chain_id)?;
+    let adapter_options = AdapterOptions {
+        simulate_locally: config.quote_mode != QuoteMode::Quoter,
+        v3_bitmap_word_radius: config.v3_bitmap_word_radius,
+    };
+    let route_configs: Vec<&RouteConfig> = config.route.iter().chain(config.routes.iter()).collect();
+    if route_configs.is_empty() && config.pathfinding.is_none() {
+        anyhow::bail!("config at {config_path} defines no routes and no pathfinding");
+    }
+    let mut pools = PoolSet::default();
+    let mut routes = Vec::with_capacity(route_configs.len());
+    let mut route_names = BTreeSet::new();
+    for raw in route_configs {
+        if !route_names.insert(raw.name.clone()) {
+            anyhow::bail!("duplicate route name {}", raw.name);
+        }
+        let route = parse_and_validate_route(&provider, &mut tokens, &mut pools, raw, &config.defaults, &adapter_options)
+            .await
+            .with_context(|| format!("route {}", raw.name))?;
+        routes.push(route);
+    }
+    if route_names.contains(PATHFINDING_ROUTE) {
+        anyhow::bail!("route name {PATHFINDING_ROUTE} is reserved for the cycle search");
+    }
+    let pathfinder = match &config.pathfinding {
+        Some(raw) => Some(
+            parse_pathfinder(&provider, &mut pools, raw, &config, &adapter_options)
+                .await
+                .context("pathfinding")?,
+        ),
+        None => None,
+    };
 
     let max_blocks = env::var("SHADOW_MAX_BLOCKS")
         .ok()
@@ -215,26 +1229,151 @@ async fn main() -> Result<()> {
     let summary_every_blocks = env_u64_or_default("SHADOW_SUMMARY_EVERY_BLOCKS", 25).max(1);
     let verbose_block_logs = env_bool_or_default("SHADOW_VERBOSE_BLOCK_LOGS", false);
     let run_id = format!("shadow-{}", unix_now_millis()?);
-    let mut stats = ShadowStats::default();
+    if !(0.0..=100.0).contains(&config.fee_model.priority_percentile) {
+        anyhow::bail!("fee_model.priority_percentile must be between 0 and 100");
+    }
+    if config.fee_model.base_fee_headroom_bps < 10_000 {
+        anyhow::bail!("fee_model.base_fee_headroom_bps must be at least 10000");
+    }
+    let fee_model = FeeModel {
+        history_blocks: config.fee_model.history_blocks,
+        priority_percentile: config.fee_model.priority_percentile,
+        base_fee_headroom_bps: config.fee_model.base_fee_headroom_bps,
+    };
+    let flash_loan_contract = env::var("FLASH_LOAN_CONTRACT")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .map(|value| parse_address(&value))
+        .transpose()
+        .context("FLASH_LOAN_CONTRACT")?;
+    let run = RunContext {
+        run_id: &run_id,
+        network: &config.network,
+        chain_id: config.chain_id,
+        quote_mode: config.quote_mode,
+        selection: SelectionLimits {
+            max_selected: config.selection.max_selected,
+            bundle: config.selection.bundle,
+        },
+        executor: flash_loan_contract.unwrap_or_default(),
+        operator: match flash_loan_contract {
+            Some(contract) => Some(operator(&provider, contract).await.context("FLASH_LOAN_CONTRACT operator")?),
+            None => None,
+        },
+        l1_oracle: L1FeeOracle::connect(&provider).await.context("GasPriceOracle")?,
+        gas_model: GasModel {
+            flash_loan_overhead: config.gas_model.flash_loan_overhead,
+            per_leg_overhead: config.gas_model.per_leg_overhead,
+        },
+    };
+    let mut gas_history = GasHistory::new(config.gas_model.history_depth);
+    let mut loan_caps = LoanCaps {
+        contract: flash_loan_contract,
+        tokens: routes
+            .iter()
+            .map(ParsedRoute::loan_token)
+            .chain(pathfinder.iter().flat_map(|pathfinder| pathfinder.start_tokens.iter().copied()))
+            .collect(),
+        configs: BTreeMap::new(),
+    };
+    let mut capital = CapitalBook {
+        sources: Vec::with_capacity(config.capital_sources.len()),
+        tokens: loan_caps.tokens.clone(),
+        offers: BTreeMap::new(),
+    };
+    for raw in &config.capital_sources {
+        let address = parse_address(&raw.address)?;
+        let source = CapitalSource::connect(&provider, raw.kind, address)
+            .await
+            .with_context(|| format!("capital source {} {address:#x}", raw.kind.as_str()))?;
+        capital.sources.push(source);
+    }
+    let mut gas_rates = GasRates {
+        native: parse_address(&config.native_token).context("native_token")?,
+        sources: BTreeMap::new(),
+        rates: BTreeMap::new(),
+    };
+    for raw in &config.gas_pricing {
+        let token = parse_address(&raw.token)?;
+        let source = match &raw.source {
+            GasRateConfig::ReferencePool(pool) => {
+                let index = pools.get_or_connect(&provider, pool.spec()?, &adapter_options).await?;
+                let pool_tokens = pools.adapters[index].tokens();
+                if !pool_tokens.contains(&token) || !pool_tokens.contains(&gas_rates.native) {
+                    anyhow::bail!(
+                        "gas_pricing reference pool {:#x} does not trade {token:#x} against the native token",
+                        pools.adapters[index].pool()
+                    );
+                }
+                GasRateSource::Pool(index)
+            }
+            GasRateConfig::Chainlink(feed) => GasRateSource::Chainlink {
+                feed: ChainlinkFeed::connect(&provider, parse_address(&feed.feed)?, feed.invert, feed.max_age_secs)
+                    .await
+                    .with_context(|| format!("gas_pricing feed for {token:#x}"))?,
+                token_decimals: tokens.resolve(&provider, token).await?.decimals,
+            },
+        };
+        if gas_rates.sources.insert(token, source).is_some() {
+            anyhow::bail!("gas_pricing lists {token:#x} twice");
+        }
+    }
+    if let Some(token) = loan_caps
+        .tokens
+        .iter()
+        .find(|token| **token != gas_rates.native && !gas_rates.sources.contains_key(token))
+    {
+        anyhow::bail!("loan token {token:#x} is not the native token and has no gas_pricing entry");
+    }
+    let mut stats = ShadowStatsBook::default();
     let mut infra_error_gate = ErrorLogGate::new(Duration::from_secs(15));
 
     eprintln!(
-        "Shadow mode start: run_id={}, network={}, route={}, leg=v2->v3, pair={:#x}, pool={:#x}, quoter={:#x}, inputs={}, polling_ms={}, max_blocks={}, summary_every_blocks={}, verbose_block_logs={}",
+        "Shadow mode start: run_id={}, network={}, l1_fee_oracle={}, fee_model=p{}/{}blocks/headroom_bps={}, routes={}, pathfinding={}, pools={}, capital_sources=[{}], quote_mode={:?}, polling_ms={}, max_blocks={}, summary_every_blocks={}, verbose_block_logs={}",
         run_id,
         config.network,
-        route.name,
-        route.v2_pair,
-        route.v3_pool,
-        route.v3_quoter_v2,
-        input_sizes.len(),
+        run.l1_oracle.upgrade.as_str(),
+        fee_model.priority_percentile,
+        fee_model.history_blocks,
+        fee_model.base_fee_headroom_bps,
+        routes.len(),
+        pathfinder.is_some(),
+        pools.adapters.len(),
+        capital
+            .sources
+            .iter()
+            .map(|source| format!("{}:{:#x}", source.kind.as_str(), source.address))
+            .collect::<Vec<_>>()
+            .join(", "),
+        config.quote_mode,
         config.poll_interval_ms,
         max_blocks.unwrap_or(0),
         summary_every_blocks,
         verbose_block_logs
     );
+    for route in &routes {
+        let directions = route
+            .directions()
+            .iter()
+            .map(|direction| direction.as_str())
+            .collect::<Vec<_>>()
+            .join("+");
+        eprintln!(
+            "Shadow route: route={}, directions={}, legs=[{}], execution={}, max_input_wei={}, fixed_inputs={}, flash_loan_fee_bps={}",
+            route.name,
+            directions,
+            hops_label(&pools.adapters, &route.hops),
+            route.limits.execution.as_str(),
+            route.limits.max_input,
+            route.limits.input_sizes.len(),
+            route.limits.flash_loan_fee_bps
+        );
+    }
 
     let mut last_block: Option<u64> = None;
     let mut processed_blocks: u64 = 0;
+    let mut state_synced_through: Option<u64> = None;
+    let mut block_tracker = BlockTracker::new(env_u64_or_default("REORG_TRACK_DEPTH", 64));
     let poll_interval = Duration::from_millis(config.poll_interval_ms.max(250));
 
     loop {
@@ -258,327 +1397,730 @@ async fn main() -> Result<()> {
         }
         last_block = Some(block_number);
         processed_blocks = processed_blocks.saturating_add(1);
-        stats.blocks_seen = stats.blocks_seen.saturating_add(1);
+        stats.aggregate.blocks_seen = stats.aggregate.blocks_seen.saturating_add(1);
 
-        let block_timestamp = match provider.get_block(block_number).await {
-            Ok(Some(block)) => block.timestamp.as_u64(),
+        let block = match provider.get_block(block_number).await {
+            Ok(Some(block)) => block,
             Ok(None) => {
-                log_route_error(
-                    ErrorEmitContext {
-                        run_id: &run_id,
-                        network: &config.network,
-                        route: &route.name,
-                        block: block_number,
-                        block_age_secs: 0,
-                        input_sizes: &input_sizes,
-                    },
-                    "quote_error",
-                    "missing_block".to_string(),
-                    &mut stats,
-                );
+                log_block_error(&run, &pools.adapters, &routes, block_number, 0, "quote_error", "missing_block", &mut stats);
                 continue;
             }
             Err(err) => {
                 infra_error_gate.log("block payload fetch failed", &sanitize_error(&err));
-                log_route_error(
-                    ErrorEmitContext {
-                        run_id: &run_id,
-                        network: &config.network,
-                        route: &route.name,
-                        block: block_number,
-                        block_age_secs: 0,
-                        input_sizes: &input_sizes,
-                    },
-                    "quote_error",
-                    "block_fetch_failed".to_string(),
-                    &mut stats,
-                );
+                log_block_error(&run, &pools.adapters, &routes, block_number, 0, "quote_error", "block_fetch_failed", &mut stats);
                 continue;
             }
         };
 
+        let block_timestamp = block.timestamp.as_u64();
+
+        match BlockRef::from_block(&block) {
+            Ok(head) => match track_block(&mut block_tracker, &provider, head).await {
+                Ok(Some(reorg)) => {
+                    stats.aggregate.reorgs_seen = stats.aggregate.reorgs_seen.saturating_add(1);
+                    roll_back_pools(&mut pools, &mut state_synced_through, &reorg);
+                    emit_reorg(&run_id, &config.network, reorg);
+                }
+                Ok(None) => {}
+                Err(err) => infra_error_gate.log("block tracking failed", &sanitize_error(&err)),
+            },
+            Err(err) => infra_error_gate.log("block header incomplete", &sanitize_error(&err)),
+        }
+        if let Some(lowest) = block_tracker.lowest() {
+            pools.state.prune_history(lowest);
+        }
+
         let now = unix_now_secs()?;
         let block_age_secs = now.saturating_sub(block_timestamp);
         if block_age_secs > config.max_block_age_secs {
-            log_route_error(
-                ErrorEmitContext {
-                    run_id: &run_id,
-                    network: &config.network,
-                    route: &route.name,
-                    block: block_number,
-                    block_age_secs,
-                    input_sizes: &input_sizes,
-                },
+            log_block_error(
+                &run,
+                &pools.adapters,
+                &routes,
+                block_number,
+                block_age_secs,
                 "stale_data",
-                format!(
-                    "block_age_secs={}",
-                    block_age_secs
-                ),
+                &format!("block_age_secs={block_age_secs}"),
                 &mut stats,
             );
             continue;
         }
 
-        let call_block = Some(BlockId::Number(BlockNumber::Number(block_number.into())));
-
-        let gas_price = match provider.get_gas_price().await {
-            Ok(value) => value,
-            Err(err) => {
-                infra_error_gate.log("gas price fetch failed", &sanitize_error(&err));
-                log_route_error(
-                    ErrorEmitContext {
-                        run_id: &run_id,
-                        network: &config.network,
-                        route: &route.name,
-                        block: block_number,
-                        block_age_secs,
-                        input_sizes: &input_sizes,
-                    },
-                    "quote_error",
-                    "gas_price_failed".to_string(),
-                    &mut stats,
-                );
-                continue;
+        let fees = match fee_model.quote(&provider, &block).await {
+            Ok(value) => value,
+            Err(err) => {
+                infra_error_gate.log("fee model failed", &sanitize_error(&err));
+                log_block_error(
+                    &run,
+                    &pools.adapters,
+                    &routes,
+                    block_number,
+                    block_age_secs,
+                    "quote_error",
+                    "fee_model_failed",
+                    &mut stats,
+                );
+                continue;
+            }
+        };
+
+        sync_pools(
+            &provider,
+            &mut pools,
+            &mut state_synced_through,
+            block_number,
+            &mut infra_error_gate,
+        )
+        .await;
+
+        if verbose_block_logs {
+            let pool_states = pools
+                .adapters
+                .iter()
+                .map(|pool| {
+                    let at = pools
+                        .state
+                        .position(pool.pool())
+                        .map_or_else(|| "none".to_string(), |at| format!("{}:{}", at.block, at.log_index));
+                    format!("{}:{:#x}@{}=[{}]", pool.venue().as_str(), pool.pool(), at, pool.describe_state())
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            eprintln!(
+                "Block diagnostics: run_id={}, block={}, block_age_secs={}, base_fee_wei={}, priority_fee_wei={}, pools=[{}]",
+                run_id, block_number, block_age_secs, fees.base_fee, fees.priority_fee, pool_states
+            );
+        }
+
+        loan_caps
+            .refresh(&provider, block_number, &mut infra_error_gate)
+            .await;
+        capital
+            .refresh(&provider, block_number, &mut infra_error_gate)
+            .await;
+        gas_rates
+            .refresh(&provider, &pools.adapters, block_number, block_timestamp, &mut infra_error_gate)
+            .await;
+        let snapshot = BlockSnapshot {
+            block: block_number,
+            block_age_secs,
+            fees,
+            loan_caps: &loan_caps,
+            capital: &capital,
+            gas_rates: &gas_rates,
+        };
+        let mut candidates = Vec::new();
+        for route in &routes {
+            evaluate_route(
+                &provider,
+                &pools.adapters,
+                route,
+                &run,
+                &snapshot,
+                &mut gas_history,
+                &mut stats,
+                &mut infra_error_gate,
+                &mut candidates,
+            )
+            .await?;
+        }
+        if let Some(pathfinder) = &pathfinder {
+            evaluate_cycles(
+                &provider,
+                &pools.adapters,
+                pathfinder,
+                &run,
+                &snapshot,
+                &mut gas_history,
+                &mut stats,
+                &mut infra_error_gate,
+                &mut candidates,
+            )
+            .await?;
+        }
+        select_trades(&pools.adapters, &candidates, &run, &snapshot).await?;
+
+        if processed_blocks.is_multiple_of(summary_every_blocks) {
+            emit_summaries(&run, block_number, "periodic", &stats);
+        }
+
+        if max_blocks.is_some_and(|limit| processed_blocks >= limit) {
+            eprintln!("Shadow mode reached SHADOW_MAX_BLOCKS={processed_blocks}; exiting.");
+            break;
+        }
+    }
+
+    infra_error_gate.flush("shadow infra errors");
+
+    let latest_block = last_block.unwrap_or(0);
+    emit_summaries(&run, latest_block, "final", &stats);
+
+    Ok(())
+}
+
+/// Quotes every input size of one route in each of its directions against
+/// the block's shared pool state and emits a decision row for each.
+#[allow(clippy::too_many_arguments)]
+async fn evaluate_route(
+    provider: &Provider<Http>,
+    pools: &[Box<dyn PoolAdapter>],
+    route: &ParsedRoute,
+    run: &RunContext<'_>,
+    snapshot: &BlockSnapshot<'_>,
+    gas: &mut GasHistory,
+    stats: &mut ShadowStatsBook,
+    infra_error_gate: &mut ErrorLogGate,
+    candidates: &mut Vec<TradeCandidate>,
+) -> Result<()> {
+    for &direction in route.directions() {
+        let hops = direction.hops(route);
+        let path = PathCandidate {
+            route: &route.name,
+            direction,
+            cycle: None,
+            marginal_edge_bps: None,
+            hops: &hops,
+            limits: &route.limits,
+        };
+        evaluate_path(provider, pools, &path, run, snapshot, gas, stats, infra_error_gate, candidates).await?;
+    }
+    Ok(())
+}
+
+/// Sizes one cycle, then quotes its legs in order for the optimal size and
+/// any fixed `input_sizes_wei`, emitting a row per size. Sizes that would
+/// trade become candidates for the block's selection.
+#[allow(clippy::too_many_arguments)]
+async fn evaluate_path(
+    provider: &Provider<Http>,
+    pools: &[Box<dyn PoolAdapter>],
+    path: &PathCandidate<'_>,
+    run: &RunContext<'_>,
+    snapshot: &BlockSnapshot<'_>,
+    gas: &mut GasHistory,
+    stats: &mut ShadowStatsBook,
+    infra_error_gate: &mut ErrorLogGate,
+    candidates: &mut Vec<TradeCandidate>,
+) -> Result<()> {
+    let limits = path.limits;
+    // Until the legs are quoted, gas is what the route and its pools have
+    // used before.
+    let gas_key = path.gas_key();
+    let executor_gas = gas.executor(&gas_key, None, &run.gas_model);
+    let mut unquoted: Vec<LegLog> = path.hops.iter().map(|hop| LegLog::unquoted(pools, hop)).collect();
+    for (hop, leg) in path.hops.iter().zip(unquoted.iter_mut()) {
+        let pool = pools[hop.pool].as_ref();
+        let swap_gas = gas.swap(pool.venue(), pool.pool(), hop.token_in, None);
+        leg.gas_units = swap_gas.units;
+        leg.gas_source = swap_gas.source.to_string();
+    }
+    let gas_units = run
+        .gas_model
+        .total(executor_gas.units, unquoted.iter().map(|leg| leg.gas_units));
+    let l2_cost = snapshot
+        .fees
+        .effective_gas_price()
+        .saturating_mul(U256::from(gas_units));
+    let tx = FlashLoanTx {
+        chain_id: run.chain_id,
+        contract: run.executor,
+        gas_limit: gas_units,
+        max_fee_per_gas: snapshot.fees.max_fee_per_gas,
+        max_priority_fee_per_gas: snapshot.fees.max_priority_fee_per_gas,
+    };
+    let base = EmitContext {
+        run_id: run.run_id,
+        network: run.network,
+        route: path.route,
+        direction: path.direction,
+        cycle: path.cycle.as_deref(),
+        marginal_edge_bps: path.marginal_edge_bps,
+        block: snapshot.block,
+        block_age_secs: snapshot.block_age_secs,
+        sizing: "optimal",
+        size: SizeChoice::none(limits.max_input),
+        input: U256::zero(),
+        fees: snapshot.fees,
+        gas_units,
+        executor_gas,
+        l2_cost,
+        l1_fee: L1Fee {
+            wei: U256::zero(),
+            method: "none",
+        },
+        gas_rate: snapshot.gas_rates.rate(path.hops[0].token_in),
+        flash_fee: U256::zero(),
+        execution: Execution::FlashLoan,
+        flash_source: "none",
+        flash_source_address: None,
+        hypothetical: false,
+        balancer_flash_fee: None,
+        legs: &unquoted,
+        output: U256::zero(),
+    };
+
+    if base.gas_rate.is_none() {
+        return emit_row(base, "would_skip", "quote_error:gas_rate_unavailable", stats);
+    }
+    let cap = match snapshot.loan_caps.cap(path.hops[0].token_in, limits.max_input) {
+        Ok(cap) => cap,
+        Err(reason) => return emit_row(base, "would_skip", reason, stats),
+    };
+    let max_fee_bps = snapshot.loan_caps.max_fee_bps(path.hops[0].token_in);
+    let pricing = snapshot.capital.pricing(pools, path.hops, limits, max_fee_bps);
+    let base = EmitContext {
+        execution: pricing.execution(),
+        ..base
+    };
+    let cap = cap.min(pricing.max_amount());
+    if cap.is_zero() {
+        return emit_row(base, "would_skip", "no_capital_source", stats);
+    }
+    // Every size's transaction has the same length, so one bound covers the
+    // search; quoted rows are then charged for their own bytes.
+    let amounts = vec![cap; path.hops.len() + 1];
+    let l1_bound = match swap_plan(pools, path.hops, &amounts, run.executor) {
+        Ok(swaps) => {
+            let unsigned_tx = tx.unsigned_rlp(path.hops[0].token_in, cap, &swaps);
+            run.l1_oracle.l1_fee_bound(provider, &unsigned_tx, snapshot.block).await
+        }
+        Err(err) => Err(err),
+    };
+    let l1_bound = match l1_bound {
+        Ok(fee) => fee,
+        Err(err) => {
+            infra_error_gate.log("l1 fee bound failed", &sanitize_error(&err));
+            return emit_row(base, "would_skip", "quote_error:l1_fee_failed", stats);
+        }
+    };
+    let base = EmitContext {
+        l1_fee: l1_bound,
+        ..base
+    };
+    let fixed_cost = base.gas_cost();
+    let size = optimize_size(provider, pools, path.hops, run, snapshot, &pricing, cap, fixed_cost).await;
+    let mut inputs = vec![("optimal", size.map(|size| size.optimal_input))];
+    inputs.extend(limits.input_sizes.iter().map(|input| ("ladder", Some(*input))));
+
+    for (sizing, input) in inputs {
+        let input = input.unwrap_or_default();
+        let loan = pricing.fund(input);
+        let ctx = EmitContext {
+            sizing,
+            size: size.unwrap_or(SizeChoice::none(cap)),
+            input,
+            flash_fee: loan.map(|loan| loan.fee).unwrap_or_default(),
+            flash_source: loan.map_or("none", |loan| loan.source),
+            flash_source_address: loan.and_then(|loan| loan.address),
+            hypothetical: loan.is_some_and(|loan| loan.hypothetical),
+            balancer_flash_fee: (base.execution == Execution::V2FlashSwap).then(|| {
+                snapshot
+                    .capital
+                    .balancer_fee(path.hops[0].token_in, input, limits.flash_loan_fee_bps)
+            }),
+            ..base
+        };
+        if let Some(reason) = presize_skip_reason(sizing, size, input, cap) {
+            emit_row(ctx, "would_skip", reason, stats)?;
+            continue;
+        }
+        if loan.is_none() {
+            emit_row(ctx, "would_skip", "no_capital_source", stats)?;
+            continue;
+        }
+
+        let mut legs = unquoted.clone();
+        let quoted = quote_legs(
+            provider,
+            pools,
+            path.hops,
+            run.quote_mode,
+            input,
+            snapshot.block,
+            &mut legs,
+            gas,
+            infra_error_gate,
+        )
+        .await;
+        // A quoted row is charged for its own transaction: the executor gas
+        // `eth_estimateGas` measures for it, its legs' swap gas and the L1
+        // fee for its bytes. A hypothetical loan has no transaction to
+        // estimate, so it takes the modelled executor gas.
+        let swaps = match &quoted {
+            Ok(amounts) => swap_plan(pools, path.hops, amounts, run.executor)
+                .map_err(|err| infra_error_gate.log("swap plan failed", &sanitize_error(&err)))
+                .ok(),
+            Err(_) => None,
+        };
+        let mut simulated = None;
+        if let (Some(swaps), Some(operator), false) = (&swaps, run.operator, ctx.hypothetical) {
+            match tx
+                .estimate_gas(provider, operator, path.hops[0].token_in, input, swaps, snapshot.block)
+                .await
+            {
+                Ok(units) => {
+                    gas.record_executor(&gas_key, units);
+                    simulated = Some(units);
+                }
+                Err(err) => infra_error_gate.log("executeFlashLoan gas estimate failed", &sanitize_error(&err)),
+            }
+        }
+        let executor_gas = gas.executor(&gas_key, simulated, &run.gas_model);
+        let gas_units = run
+            .gas_model
+            .total(executor_gas.units, legs.iter().map(|leg| leg.gas_units));
+        let l2_cost = snapshot
+            .fees
+            .effective_gas_price()
+            .saturating_mul(U256::from(gas_units));
+        let l1_fee = match &swaps {
+            Some(swaps) => {
+                let unsigned_tx = FlashLoanTx {
+                    gas_limit: gas_units,
+                    ..tx
+                }
+                .unsigned_rlp(path.hops[0].token_in, input, swaps);
+                run.l1_oracle
+                    .l1_fee(provider, &unsigned_tx, snapshot.block)
+                    .await
+                    .unwrap_or_else(|err| {
+                        infra_error_gate.log("l1 fee read failed", &sanitize_error(&err));
+                        l1_bound
+                    })
             }
+            None => l1_bound,
         };
+        let ctx = EmitContext {
+            gas_units,
+            executor_gas,
+            l2_cost,
+            l1_fee,
+            legs: &legs,
+            output: quoted.as_ref().ok().and_then(|amounts| amounts.last().copied()).unwrap_or_default(),
+            ..ctx
+        };
+        let total_cost = input.saturating_add(ctx.flash_fee).saturating_add(ctx.gas_cost());
+        let (decision, reason) = match &quoted {
+            Ok(_) => match limits.decide(&snapshot.fees, ctx.output, total_cost) {
+                ("would_trade", _) if ctx.hypothetical => ("would_skip", "hypothetical_source"),
+                decision => decision,
+            },
+            Err(reason) => ("would_skip", reason.as_str()),
+        };
+        let cost = TradeCost {
+            total: total_cost,
+            flash_fee: ctx.flash_fee,
+            executor: ctx.executor_cost(),
+        };
+        emit_row(ctx, decision, reason, stats)?;
+        if decision == "would_trade" {
+            candidates.push(TradeCandidate {
+                route: path.route.to_string(),
+                direction: path.direction,
+                cycle: path.cycle.clone(),
+                sizing,
+                hops: path.hops.to_vec(),
+                input,
+                cost,
+                output: ctx.output,
+                min_profit: limits.min_profit,
+                loan: pricing.clone(),
+            });
+        }
+    }
+    Ok(())
+}
 
-        let (reserve0, reserve1) = match get_v2_reserves(&provider, route.v2_pair, call_block).await {
-            Ok(values) => values,
+/// Chains exact-input quotes through `hops`, recording each leg's output,
+/// source, latency and swap gas in `legs`. Returns the input followed by
+/// every leg's output, or the skip reason naming the first leg that failed
+/// or returned nothing.
+#[allow(clippy::too_many_arguments)]
+async fn quote_legs(
+    provider: &Provider<Http>,
+    pools: &[Box<dyn PoolAdapter>],
+    hops: &[Hop],
+    mode: QuoteMode,
+    amount_in: U256,
+    block: u64,
+    legs: &mut [LegLog],
+    gas: &mut GasHistory,
+    infra_error_gate: &mut ErrorLogGate,
+) -> std::result::Result<Vec<U256>, String> {
+    let mut amounts = vec![amount_in];
+    for (index, (hop, leg)) in hops.iter().zip(legs.iter_mut()).enumerate() {
+        let started = Instant::now();
+        let amount = amounts[index];
+        let pool = pools[hop.pool].as_ref();
+        let quote = quote_leg(provider, pool, mode, hop.token_in, hop.token_out, amount, block).await;
+        leg.quote_latency_us = started.elapsed().as_micros() as u64;
+        match quote {
+            Ok(quote) => {
+                if let Some(mismatch) = &quote.mismatch {
+                    infra_error_gate.log(&format!("leg {index} local/quoter mismatch"), mismatch);
+                }
+                leg.amount_out_wei = quote.amount_out.to_string();
+                leg.quote_source = quote.source.to_string();
+                let swap_gas = gas.swap(pool.venue(), pool.pool(), hop.token_in, quote.gas_estimate);
+                if let Some(units) = quote.gas_estimate {
+                    gas.record_swap(pool.pool(), hop.token_in, units);
+                }
+                leg.gas_units = swap_gas.units;
+                leg.gas_source = swap_gas.source.to_string();
+                if quote.amount_out.is_zero() {
+                    return Err(format!("bad_pool_state:leg_{index}_out_zero"));
+                }
+                amounts.push(quote.amount_out);
+            }
             Err(err) => {
-                infra_error_gate.log("v2 reserves fetch failed", &sanitize_error(&err));
-                log_route_error(
-                    ErrorEmitContext {
-                        run_id: &run_id,
-                        network: &config.network,
-                        route: &route.name,
-                        block: block_number,
-                        block_age_secs,
-                        input_sizes: &input_sizes,
-                    },
-                    "quote_error",
-                    "v2_reserves_failed".to_string(),
-                    &mut stats,
-                );
-                continue;
+                infra_error_gate.log(&format!("leg {index} quote failed"), &sanitize_error(&err));
+                return Err(format!("quote_error:leg_{index}_failed"));
             }
-        };
-        if reserve0.is_zero() || reserve1.is_zero() {
-            log_route_error(
-                ErrorEmitContext {
-                    run_id: &run_id,
-                    network: &config.network,
-                    route: &route.name,
-                    block: block_number,
-                    block_age_secs,
-                    input_sizes: &input_sizes,
-                },
-                "bad_pool_state",
-                "v2_zero_reserve".to_string(),
-                &mut stats,
-            );
-            continue;
         }
+    }
+    Ok(amounts)
+}
 
-        if verbose_block_logs {
-            eprintln!(
-                "Block diagnostics: run_id={}, block={}, block_age_secs={}, gas_price_wei={}, reserve0={}, reserve1={}",
-                run_id, block_number, block_age_secs, gas_price, reserve0, reserve1
-            );
-        }
+/// The swaps running `hops` in one `executeFlashLoan`, where `amounts[i]`
+/// goes into hop `i` and `amounts[i + 1]` is its minimum output.
+fn swap_plan(
+    pools: &[Box<dyn PoolAdapter>],
+    hops: &[Hop],
+    amounts: &[U256],
+    recipient: Address,
+) -> Result<Vec<SwapCall>> {
+    hops.iter()
+        .enumerate()
+        .map(|(index, hop)| {
+            pools[hop.pool].encode_swap(hop.token_in, hop.token_out, amounts[index], amounts[index + 1], recipient)
+        })
+        .collect()
+}
 
-        for input in &input_sizes {
-            let gas_cost = gas_price.saturating_mul(U256::from(config.gas_units_estimate));
-            let flash_fee = fee_from_bps(*input, config.flash_loan_fee_bps);
-            let v2_out_mid = quote_v2_exact_in(
-                *input,
-                reserve0,
-                reserve1,
-                route.v2_fee_bps,
-                route.v2_token0_to1,
-            );
-            if v2_out_mid.is_zero() {
-                emit_row(
-                    EmitContext {
-                        run_id: &run_id,
-                        network: &config.network,
-                        route: &route.name,
-                        block: block_number,
-                        block_age_secs,
-                        input: *input,
-                        gas_price,
-                        gas_cost,
-                        flash_fee,
-                        v2_out_mid: U256::zero(),
-                        v3_out: U256::zero(),
-                        v3_quote_latency_ms: 0,
-                    },
-                    "would_skip",
-                    "bad_pool_state:v2_out_zero",
-                    &mut stats,
-                )?;
-                continue;
-            }
+/// Why a size is skipped before quoting: the search failed or found
+/// nothing worth borrowing, or a fixed size is above the loan cap.
+fn presize_skip_reason(sizing: &str, size: Option<SizeChoice>, input: U256, cap: U256) -> Option<&'static str> {
+    match (sizing, size) {
+        ("optimal", None) => Some("quote_error:sizing_failed"),
+        ("optimal", Some(_)) if input.is_zero() => Some("below_min_profit:no_profitable_size"),
+        _ if input > cap => Some("above_max_loan"),
+        _ => None,
+    }
+}
 
-            let v3_quote_started = Instant::now();
-            let v3_out = match quote_v3_exact_input_single(
-                &provider,
-                route.v3_quoter_v2,
-                route.token_mid,
-                route.token_in,
-                v2_out_mid,
-                route.v3_pool_fee,
-                call_block,
-            )
+/// Finds the input in `[0, cap]` with the highest profit after the flash
+/// fee and `fixed_cost`, the L2 and L1 gas. Two constant-product hops use
+/// the closed form at the cheapest source's rate; anything else runs a
+/// golden-section search, quoting locally unless the run only trusts the
+/// on-chain quoters. `None` when no size could be quoted.
+#[allow(clippy::too_many_arguments)]
+async fn optimize_size(
+    provider: &Provider<Http>,
+    pools: &[Box<dyn PoolAdapter>],
+    hops: &[Hop],
+    run: &RunContext<'_>,
+    snapshot: &BlockSnapshot<'_>,
+    pricing: &LoanPricing,
+    cap: U256,
+    fixed_cost: U256,
+) -> Option<SizeChoice> {
+    let probe_mode = match run.quote_mode {
+        QuoteMode::Quoter => QuoteMode::Quoter,
+        _ => QuoteMode::Local,
+    };
+    let profit_at = async |input: U256| {
+        let output = quote_path(provider, pools, hops, probe_mode, input, snapshot.block)
             .await
-            {
-                Ok(value) => value,
-                Err(err) => {
-                    infra_error_gate.log("v3 quoter call failed", &sanitize_error(&err));
-                    emit_row(
-                        EmitContext {
-                            run_id: &run_id,
-                            network: &config.network,
-                            route: &route.name,
-                            block: block_number,
-                            block_age_secs,
-                            input: *input,
-                            gas_price,
-                            gas_cost,
-                            flash_fee,
-                            v2_out_mid,
-                            v3_out: U256::zero(),
-                            v3_quote_latency_ms: v3_quote_started.elapsed().as_millis() as u64,
-                        },
-                        "would_skip",
-                        "quote_error:v3_quoter_failed",
-                        &mut stats,
-                    )?;
-                    continue;
-                }
-            };
-            let v3_quote_latency_ms = v3_quote_started.elapsed().as_millis() as u64;
-
-            if gas_price > max_gas_price {
-                emit_row(
-                    EmitContext {
-                        run_id: &run_id,
-                        network: &config.network,
-                        route: &route.name,
-                        block: block_number,
-                        block_age_secs,
-                        input: *input,
-                        gas_price,
-                        gas_cost,
-                        flash_fee,
-                        v2_out_mid,
-                        v3_out,
-                        v3_quote_latency_ms,
-                    },
-                    "would_skip",
-                    "gas_too_high",
-                    &mut stats,
-                )?;
-                continue;
-            }
+            .ok()?;
+        let cost = input
+            .saturating_add(pricing.fund(input)?.fee)
+            .saturating_add(fixed_cost);
+        Some(signed_net(output, cost))
+    };
 
-            let total_cost = input.saturating_add(flash_fee).saturating_add(gas_cost);
-            if v3_out <= total_cost {
-                emit_row(
-                    EmitContext {
-                        run_id: &run_id,
-                        network: &config.network,
-                        route: &route.name,
-                        block: block_number,
-                        block_age_secs,
-                        input: *input,
-                        gas_price,
-                        gas_cost,
-                        flash_fee,
-                        v2_out_mid,
-                        v3_out,
-                        v3_quote_latency_ms,
-                    },
-                    "would_skip",
-                    "below_min_profit",
-                    &mut stats,
-                )?;
-                continue;
-            }
+    if let [first, second] = hops
+        && let Some(first_leg) = pools[first.pool].constant_product(first.token_in, first.token_out)
+        && let Some(second_leg) = pools[second.pool].constant_product(second.token_in, second.token_out)
+        && let Some(optimal) = optimal_constant_product_input(first_leg, second_leg, pricing.sizing_fee_bps())
+    {
+        let optimal_input = optimal.min(cap);
+        let expected_profit = if optimal_input.is_zero() {
+            signed_net(U256::zero(), fixed_cost)
+        } else {
+            profit_at(optimal_input).await?
+        };
+        return Some(SizeChoice {
+            method: "closed_form",
+            max_input: cap,
+            optimal_input,
+            expected_profit,
+        });
+    }
 
-            let net = v3_out - total_cost;
-            if net < min_profit {
-                emit_row(
-                    EmitContext {
-                        run_id: &run_id,
-                        network: &config.network,
-                        route: &route.name,
-                        block: block_number,
-                        block_age_secs,
-                        input: *input,
-                        gas_price,
-                        gas_cost,
-                        flash_fee,
-                        v2_out_mid,
-                        v3_out,
-                        v3_quote_latency_ms,
-                    },
-                    "would_skip",
-                    "below_min_profit",
-                    &mut stats,
-                )?;
-                continue;
-            }
+    let tolerance = cap / U256::from(SIZE_SEARCH_RESOLUTION);
+    let (optimal_input, expected_profit) = golden_section_max(U256::zero(), cap, tolerance, profit_at).await?;
+    Some(SizeChoice {
+        method: "golden_section",
+        max_input: cap,
+        optimal_input,
+        expected_profit,
+    })
+}
 
-            emit_row(
-                EmitContext {
-                    run_id: &run_id,
-                    network: &config.network,
-                    route: &route.name,
-                    block: block_number,
-                    block_age_secs,
-                    input: *input,
-                    gas_price,
-                    gas_cost,
-                    flash_fee,
-                    v2_out_mid,
-                    v3_out,
-                    v3_quote_latency_ms,
-                },
-                "would_trade",
-                "edge_above_threshold",
-                &mut stats,
-            )?;
-        }
+/// Exact-input quotes through `hops` in order, returning the final output.
+async fn quote_path(
+    provider: &Provider<Http>,
+    pools: &[Box<dyn PoolAdapter>],
+    hops: &[Hop],
+    mode: QuoteMode,
+    amount_in: U256,
+    block: u64,
+) -> Result<U256> {
+    let mut amount = amount_in;
+    for hop in hops {
+        amount = quote_leg(provider, pools[hop.pool].as_ref(), mode, hop.token_in, hop.token_out, amount, block)
+            .await?
+            .amount_out;
+    }
+    Ok(amount)
+}
 
-        if processed_blocks.is_multiple_of(summary_every_blocks) {
-            emit_summary(
-                &run_id,
-                &config.network,
-                &route.name,
-                block_number,
-                "periodic",
-                &stats,
-            );
+/// Builds the block's token graph, sizes and fully quotes the best cycles
+/// from each start token and emits a cycle row per size.
+#[allow(clippy::too_many_arguments)]
+async fn evaluate_cycles(
+    provider: &Provider<Http>,
+    pools: &[Box<dyn PoolAdapter>],
+    pathfinder: &Pathfinder,
+    run: &RunContext<'_>,
+    snapshot: &BlockSnapshot<'_>,
+    gas: &mut GasHistory,
+    stats: &mut ShadowStatsBook,
+    infra_error_gate: &mut ErrorLogGate,
+    candidates: &mut Vec<TradeCandidate>,
+) -> Result<()> {
+    let graph = TokenGraph::build(pools);
+    for start in &pathfinder.start_tokens {
+        let mut cycles = graph.find_cycles(*start, pathfinder.max_hops);
+        cycles.truncate(pathfinder.max_cycles_per_token);
+        for cycle in &cycles {
+            let path = PathCandidate {
+                route: PATHFINDING_ROUTE,
+                direction: Direction::Forward,
+                cycle: Some(cycle_label(pools, cycle)),
+                marginal_edge_bps: Some(cycle.marginal_edge_bps()),
+                hops: &cycle.hops,
+                limits: &pathfinder.limits,
+            };
+            evaluate_path(provider, pools, &path, run, snapshot, gas, stats, infra_error_gate, candidates).await?;
         }
+    }
+    Ok(())
+}
 
-        if max_blocks.is_some_and(|limit| processed_blocks >= limit) {
-            eprintln!("Shadow mode reached SHADOW_MAX_BLOCKS={processed_blocks}; exiting.");
-            break;
-        }
+/// `start>venue:pool>token>...` for a search cycle's rows.
+fn cycle_label(pools: &[Box<dyn PoolAdapter>], cycle: &Cycle) -> String {
+    let mut label = cycle
+        .start_token()
+        .map(|token| format!("{token:#x}"))
+        .unwrap_or_default();
+    for hop in &cycle.hops {
+        let pool = pools[hop.pool].as_ref();
+        label.push_str(&format!(">{}:{:#x}>{:#x}", pool.venue().as_str(), pool.pool(), hop.token_out));
     }
+    label
+}
 
-    infra_error_gate.flush("shadow infra errors");
+/// `venue:pool token_in>token_out` per hop, for the startup log.
+fn hops_label(pools: &[Box<dyn PoolAdapter>], hops: &[Hop]) -> String {
+    hops.iter()
+        .map(|hop| {
+            let pool = pools[hop.pool].as_ref();
+            format!(
+                "{}:{:#x} {:#x}>{:#x}",
+                pool.venue().as_str(),
+                pool.pool(),
+                hop.token_in,
+                hop.token_out
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-    let latest_block = last_block.unwrap_or(0);
-    emit_summary(
-        &run_id,
-        &config.network,
-        &route.name,
-        latest_block,
-        "final",
-        &stats,
-    );
+/// Picks the block's trades from its `would_trade` rows and logs a
+/// selection row for every candidate. Bundled trades are re-quoted in order
+/// against local pool copies that each earlier trade has already moved.
+async fn select_trades(
+    pools: &[Box<dyn PoolAdapter>],
+    candidates: &[TradeCandidate],
+    run: &RunContext<'_>,
+    snapshot: &BlockSnapshot<'_>,
+) -> Result<()> {
+    if candidates.is_empty() {
+        return Ok(());
+    }
+    let opportunities: Vec<Opportunity> = candidates
+        .iter()
+        .map(|candidate| Opportunity {
+            label: candidate.label(),
+            pools: candidate.hops.iter().map(|hop| pools[hop.pool].pool()).collect(),
+            loan_token: candidate.hops[0].token_in,
+            loan_amount: candidate.input,
+            net_profit: signed_net(candidate.output, candidate.cost.total),
+            min_profit: candidate.min_profit,
+        })
+        .collect();
+    let loan_cap = |token| snapshot.loan_caps.cap(token, U256::MAX).unwrap_or_default();
+    // The state is the pools the bundle has moved so far and its total loan.
+    let requote = async |index: usize, (state, borrowed): &(BTreeMap<usize, Box<dyn PoolAdapter>>, U256)| {
+        let candidate = &candidates[index];
+        let mut next = state.clone();
+        let mut amount = candidate.input;
+        for hop in &candidate.hops {
+            let pool = next.entry(hop.pool).or_insert_with(|| pools[hop.pool].clone_box());
+            amount = pool.simulate_swap(hop.token_in, hop.token_out, amount).ok()?;
+        }
+        let loan_fee = |amount| {
+            candidate
+                .loan
+                .fund(amount)
+                .filter(|loan| !loan.hypothetical)
+                .map(|loan| loan.fee)
+        };
+        let cost = candidate.cost.in_bundle(candidate.input, *borrowed, loan_fee)?;
+        Some((signed_net(amount, cost), (next, borrowed.saturating_add(candidate.input))))
+    };
+    let outcomes = select(&opportunities, run.selection, loan_cap, (BTreeMap::new(), U256::zero()), requote).await;
 
+    for outcome in outcomes {
+        let (candidate, opportunity) = (&candidates[outcome.index], &opportunities[outcome.index]);
+        let (decision, reason, bundle_net) = match outcome.verdict {
+            Verdict::Selected { net_profit } => ("selected", "no_conflict".to_string(), Some(net_profit)),
+            Verdict::Dropped { reason } => ("dropped", reason, None),
+        };
+        let row = ShadowSelectionLog {
+            run_id: run.run_id.to_string(),
+            ts_unix_ms: unix_now_millis()?,
+            network: run.network.to_string(),
+            block: snapshot.block,
+            route: candidate.route.clone(),
+            direction: candidate.direction.as_str().to_string(),
+            cycle: candidate.cycle.clone(),
+            sizing: candidate.sizing.to_string(),
+            candidate: opportunity.label.clone(),
+            rank: outcome.rank,
+            loan_token: opportunity.loan_token,
+            input_wei: candidate.input.to_string(),
+            net_wei: opportunity.net_profit.to_string(),
+            bundle_net_wei: bundle_net
+                .filter(|_| run.selection.bundle)
+                .map(|net| net.to_string()),
+            decision: decision.to_string(),
+            reason,
+        };
+        println!("{}", serde_json::to_string(&row).context("failed to serialize selection row")?);
+    }
     Ok(())
 }
 
@@ -607,70 +2149,227 @@ async fn validate_network(provider: &Provider<Http>, expected_chain_id: u64) ->
     Ok(())
 }
 
-async fn parse_and_validate_route(provider: &Provider<Http>, raw: &RouteConfig) -> Result<ParsedRoute> {
-    let token_in = parse_address(&raw.token_in_address)?;
-    let token_mid = parse_address(&raw.token_mid_address)?;
-    let v2_pair = parse_address(&raw.v2_pair)?;
-    let v3_pool = parse_address(&raw.v3_pool)?;
-    let v3_quoter_v2 = parse_address(&raw.v3_quoter_v2)?;
+async fn parse_and_validate_route(
+    provider: &Provider<Http>,
+    tokens: &mut TokenResolver,
+    pools: &mut PoolSet,
+    raw: &RouteConfig,
+    defaults: &RouteSettings,
+    options: &AdapterOptions,
+) -> Result<ParsedRoute> {
+    if raw.legs.len() < 2 {
+        anyhow::bail!("a route needs at least 2 legs, got {}", raw.legs.len());
+    }
+    let mut hops: Vec<Hop> = Vec::with_capacity(raw.legs.len());
+    for (index, leg) in raw.legs.iter().enumerate() {
+        let token_in = parse_address(&leg.token_in).with_context(|| format!("leg {index} token_in"))?;
+        let token_out = parse_address(&leg.token_out).with_context(|| format!("leg {index} token_out"))?;
+        if token_in == token_out {
+            anyhow::bail!("leg {index} swaps {token_in:#x} for itself");
+        }
+        let spec = leg.pool.spec().with_context(|| format!("leg {index}"))?;
+        let (venue, pool) = (spec.venue, spec.pool);
+        let pool_index = pools
+            .get_or_connect(provider, spec, options)
+            .await
+            .with_context(|| format!("leg {index}"))?;
+        if hops.iter().any(|hop| hop.pool == pool_index) {
+            anyhow::bail!("leg {index} trades pool {pool:#x} a second time");
+        }
+        let pool_tokens = pools.adapters[pool_index].tokens();
+        if !pool_tokens.contains(&token_in) || !pool_tokens.contains(&token_out) {
+            anyhow::bail!(
+                "bad_pool_state: leg {index} {} pool token mismatch pool={:#x} tokens={:?}",
+                venue.as_str(),
+                pool,
+                pool_tokens
+            );
+        }
+        tokens
+            .resolve_checked(provider, token_in, leg.token_in_symbol.as_deref(), leg.token_in_decimals)
+            .await
+            .with_context(|| format!("leg {index} token_in"))?;
+        hops.push(Hop {
+            pool: pool_index,
+            token_in,
+            token_out,
+        });
+    }
 
-    let v2_token0 = get_address_view(provider, v2_pair, "token0()").await?;
-    let v2_token1 = get_address_view(provider, v2_pair, "token1()").await?;
-    let v2_token0_to1 = if v2_token0 == token_in && v2_token1 == token_mid {
-        true
-    } else if v2_token0 == token_mid && v2_token1 == token_in {
-        false
-    } else {
+    for (index, pair) in hops.windows(2).enumerate() {
+        if pair[0].token_out != pair[1].token_in {
+            anyhow::bail!(
+                "leg {index} ends in {:#x} but leg {} starts from {:#x}",
+                pair[0].token_out,
+                index + 1,
+                pair[1].token_in
+            );
+        }
+    }
+    let (first, last) = (hops[0], hops[hops.len() - 1]);
+    if last.token_out != first.token_in {
         anyhow::bail!(
-            "bad_pool_state: v2 pair token mismatch pair={:#x} token0={:#x} token1={:#x}",
-            v2_pair,
-            v2_token0,
-            v2_token1
+            "legs do not form a cycle: the last leg ends in {:#x} but the first starts from {:#x}",
+            last.token_out,
+            first.token_in
         );
+    }
+
+    let route = ParsedRoute {
+        name: raw.name.clone(),
+        hops,
+        reverse: raw.reverse,
+        limits: RouteLimits::resolve(&raw.settings, defaults)?,
     };
+    if route.limits.execution == Execution::V2FlashSwap
+        && !route.directions().iter().any(|direction| {
+            let first = direction.hops(&route)[0];
+            pools.adapters[first.pool].venue() == Venue::UniswapV2
+        })
+    {
+        anyhow::bail!("v2_flash_swap needs a route that starts on a uniswap_v2 pair in some direction");
+    }
+    Ok(route)
+}
 
-    let v3_token0 = get_address_view(provider, v3_pool, "token0()").await?;
-    let v3_token1 = get_address_view(provider, v3_pool, "token1()").await?;
-    let v3_pool_fee = get_u24_view(provider, v3_pool, "fee()").await?;
+async fn parse_pathfinder(
+    provider: &Provider<Http>,
+    pools: &mut PoolSet,
+    raw: &PathfindingConfig,
+    config: &ShadowConfig,
+    options: &AdapterOptions,
+) -> Result<Pathfinder> {
+    if raw.start_tokens.is_empty() {
+        anyhow::bail!("start_tokens is empty");
+    }
+    if raw.max_hops < 2 {
+        anyhow::bail!("max_hops must be at least 2, got {}", raw.max_hops);
+    }
+    for pool in &raw.pools {
+        pools.get_or_connect(provider, pool.spec()?, options).await?;
+    }
+    let start_tokens: Vec<Address> = raw
+        .start_tokens
+        .iter()
+        .map(|token| parse_address(token))
+        .collect::<Result<_>>()?;
+    if let Some(registry) = &raw.registry {
+        load_registry_pools(provider, pools, registry, config.chain_id, &start_tokens, options)
+            .await
+            .context("registry")?;
+    }
+    Ok(Pathfinder {
+        start_tokens,
+        max_hops: raw.max_hops,
+        max_cycles_per_token: raw.max_cycles_per_token,
+        limits: RouteLimits::resolve(&raw.settings, &config.defaults)?,
+    })
+}
 
-    let v3_has_tokens =
-        (v3_token0 == token_in && v3_token1 == token_mid) || (v3_token0 == token_mid && v3_token1 == token_in);
-    if !v3_has_tokens {
-        anyhow::bail!(
-            "bad_pool_state: v3 pool token mismatch pool={:#x} token0={:#x} token1={:#x}",
-            v3_pool,
-            v3_token0,
-            v3_token1
-        );
+/// Connects every registry pool among the configured tokens that is not
+/// tracked already. The registry holds whatever the factories created, so a
+/// pool that fails to connect is reported and left out instead of failing
+/// startup.
+async fn load_registry_pools(
+    provider: &Provider<Http>,
+    pools: &mut PoolSet,
+    raw: &RegistryConfig,
+    chain_id: u64,
+    start_tokens: &[Address],
+    options: &AdapterOptions,
+) -> Result<()> {
+    let path = discovery_registry_path(chain_id);
+    if !Path::new(&path).exists() {
+        anyhow::bail!("no pool registry at {path}; run discover_pools first");
     }
-    if v3_pool_fee != raw.v3_pool_fee {
-        anyhow::bail!(
-            "bad_pool_state: v3 pool fee mismatch pool={:#x} configured={} onchain={}",
-            v3_pool,
-            raw.v3_pool_fee,
-            v3_pool_fee
-        );
+    let registry = PoolRegistry::load_or_new(Path::new(&path), chain_id)?;
+    let defaults = SpecDefaults {
+        uniswap_v2_fee_bps: raw.uniswap_v2_fee_bps,
+        uniswap_v3_quoter: raw.uniswap_v3_quoter.as_deref().map(parse_address).transpose()?,
+        slipstream_quoter: raw.slipstream_quoter.as_deref().map(parse_address).transpose()?,
+    };
+    let mut tokens: BTreeSet<Address> = raw
+        .tokens
+        .iter()
+        .map(|token| parse_address(token))
+        .collect::<Result<_>>()?;
+    tokens.extend(start_tokens.iter().copied());
+
+    let (mut loaded, mut skipped) = (0_usize, 0_usize);
+    for record in registry.pools_among(&tokens) {
+        if pools.contains(record.pool) {
+            continue;
+        }
+        let Some(spec) = record.spec(&defaults) else {
+            skipped += 1;
+            continue;
+        };
+        match pools.get_or_connect(provider, spec, options).await {
+            Ok(_) => loaded += 1,
+            Err(err) => {
+                skipped += 1;
+                eprintln!("Skipping registry pool {:#x}: {}", record.pool, sanitize_error(&err));
+            }
+        }
     }
+    eprintln!("Pool registry {path}: tokens={}, loaded={loaded}, skipped={skipped}", tokens.len());
+    Ok(())
+}
 
-    let _ = (
-        &raw.token_in_symbol,
-        &raw.token_mid_symbol,
-        &raw.token_out_symbol,
-        raw.token_in_decimals,
-        raw.token_mid_decimals,
-    );
+fn default_max_hops() -> usize {
+    3
+}
 
-    Ok(ParsedRoute {
-        name: raw.name.clone(),
-        token_in,
-        token_mid,
-        v2_pair,
-        v2_fee_bps: raw.v2_fee_bps.min(10_000),
-        v2_token0_to1,
-        v3_pool,
-        v3_pool_fee,
-        v3_quoter_v2,
-    })
+fn default_max_cycles_per_token() -> usize {
+    8
+}
+
+fn default_registry_v2_fee_bps() -> u64 {
+    30
+}
+
+fn default_fee_history_blocks() -> u64 {
+    10
+}
+
+fn default_priority_percentile() -> f64 {
+    50.0
+}
+
+fn default_base_fee_headroom_bps() -> u64 {
+    20_000
+}
+
+fn default_flash_loan_overhead() -> u64 {
+    150_000
+}
+
+fn default_per_leg_overhead() -> u64 {
+    30_000
+}
+
+fn default_gas_history_depth() -> usize {
+    32
+}
+
+fn default_max_selected() -> usize {
+    1
+}
+
+fn default_reverse() -> bool {
+    true
+}
+
+fn default_native_token() -> String {
+    "0x4200000000000000000000000000000000000006".to_string()
+}
+
+fn default_feed_max_age_secs() -> u64 {
+    3_600
+}
+
+fn default_v3_bitmap_word_radius() -> i16 {
+    2
 }
 
 fn parse_u256_dec(value: &str) -> Result<U256> {
@@ -755,128 +2454,110 @@ fn fee_from_bps(amount: U256, bps: u64) -> U256 {
         .unwrap_or_else(U256::zero)
 }
 
-fn selector(signature: &str) -> [u8; 4] {
-    let hash = id(signature);
-    [hash[0], hash[1], hash[2], hash[3]]
-}
-
-async fn eth_call(provider: &Provider<Http>, to: Address, data: Bytes, block: Option<BlockId>) -> Result<Bytes> {
-    let tx: TypedTransaction = TransactionRequest::new().to(to).data(data).into();
-    provider
-        .call(&tx, block)
-        .await
-        .with_context(|| format!("eth_call failed on {:#x}", to))
-}
-
-async fn get_address_view(provider: &Provider<Http>, contract: Address, signature: &str) -> Result<Address> {
-    let out = eth_call(provider, contract, Bytes::from(selector(signature).to_vec()), None).await?;
-    let tokens = decode(&[ParamType::Address], out.as_ref())
-        .with_context(|| format!("decode failed for {signature} on {:#x}", contract))?;
-    match tokens.first() {
-        Some(Token::Address(value)) => Ok(*value),
-        _ => anyhow::bail!("unexpected address response for {signature} on {:#x}", contract),
-    }
-}
-
-async fn get_u24_view(provider: &Provider<Http>, contract: Address, signature: &str) -> Result<u32> {
-    let out = eth_call(provider, contract, Bytes::from(selector(signature).to_vec()), None).await?;
-    let tokens = decode(&[ParamType::Uint(24)], out.as_ref())
-        .with_context(|| format!("decode failed for {signature} on {:#x}", contract))?;
-    match tokens.first() {
-        Some(Token::Uint(value)) => Ok(value.low_u32()),
-        _ => anyhow::bail!("unexpected uint24 response for {signature} on {:#x}", contract),
+/// Brings every pool's state up to `block`.
+///
+/// Short gaps are replayed into the pool state store from the adapters'
+/// state logs in one `eth_getLogs` call; a cold start, reorg or long gap
+/// forgets every pool instead. Each adapter is then refreshed, which loads
+/// whatever is missing, and synced from the store. Failures are logged and
+/// leave that pool without state, so its quotes fall back to the venue's
+/// on-chain quoter or fail per row.
+async fn sync_pools(
+    provider: &Provider<Http>,
+    pools: &mut PoolSet,
+    synced_through: &mut Option<u64>,
+    block: u64,
+    errors: &mut ErrorLogGate,
+) {
+    match *synced_through {
+        Some(synced) if synced >= block => {}
+        Some(synced) if block - synced <= MAX_STATE_CATCHUP_BLOCKS => {
+            if let Err(err) = replay_logs(provider, &pools.adapters, &mut pools.state, synced + 1, block).await {
+                errors.log("pool log replay failed (reloading)", &sanitize_error(&err));
+                pools.forget_all();
+            }
+        }
+        _ => pools.forget_all(),
     }
-}
+    *synced_through = Some(block);
 
-async fn get_v2_reserves(provider: &Provider<Http>, pair: Address, block: Option<BlockId>) -> Result<(U256, U256)> {
-    let out = eth_call(provider, pair, Bytes::from(selector("getReserves()").to_vec()), block).await?;
-    let tokens = decode(
-        &[ParamType::Uint(112), ParamType::Uint(112), ParamType::Uint(32)],
-        out.as_ref(),
-    )
-    .context("failed decoding getReserves response")?;
-    if tokens.len() != 3 {
-        anyhow::bail!("unexpected getReserves token length {}", tokens.len());
+    for pool in pools.adapters.iter_mut() {
+        if let Err(err) = refresh_pool(provider, pool.as_mut(), &mut pools.state, block).await {
+            errors.log(
+                &format!("{} state refresh failed", pool.venue().as_str()),
+                &sanitize_error(&err),
+            );
+        }
     }
-
-    let reserve0 = token_as_uint(&tokens[0])?;
-    let reserve1 = token_as_uint(&tokens[1])?;
-    Ok((reserve0, reserve1))
 }
 
-fn quote_v2_exact_in(
-    amount_in: U256,
-    reserve0: U256,
-    reserve1: U256,
-    fee_bps: u64,
-    token0_to1: bool,
-) -> U256 {
-    let (reserve_in, reserve_out) = if token0_to1 {
-        (reserve0, reserve1)
-    } else {
-        (reserve1, reserve0)
-    };
-
-    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
-        return U256::zero();
+/// Rolls pool state back to the reorg's common ancestor so the next sync
+/// replays only the new canonical blocks. A reorg deeper than the tracked
+/// window leaves nothing trustworthy to roll back to, so every pool is
+/// reloaded instead.
+fn roll_back_pools(pools: &mut PoolSet, synced_through: &mut Option<u64>, reorg: &ReorgEvent) {
+    if reorg.exceeded_tracked_depth {
+        pools.forget_all();
+        *synced_through = None;
+        return;
     }
-
-    let fee_numerator = U256::from(10_000_u64.saturating_sub(fee_bps.min(10_000)));
-    let fee_denominator = U256::from(10_000_u64);
-    let amount_in_with_fee = amount_in
-        .saturating_mul(fee_numerator)
-        .checked_div(fee_denominator)
-        .unwrap_or_else(U256::zero);
-
-    let numerator = amount_in_with_fee.saturating_mul(reserve_out);
-    let denominator = reserve_in.saturating_add(amount_in_with_fee);
-    if denominator.is_zero() {
-        return U256::zero();
-    }
-    numerator.checked_div(denominator).unwrap_or_else(U256::zero)
+    pools.state.rollback_to(reorg.common_ancestor);
+    *synced_through = synced_through.map(|synced| synced.min(reorg.common_ancestor));
 }
 
-async fn quote_v3_exact_input_single(
+/// Quotes one leg according to `mode`.
+async fn quote_leg(
     provider: &Provider<Http>,
-    quoter: Address,
+    leg: &dyn PoolAdapter,
+    mode: QuoteMode,
     token_in: Address,
     token_out: Address,
     amount_in: U256,
-    fee: u32,
-    block: Option<BlockId>,
-) -> Result<U256> {
-    let mut data = selector("quoteExactInputSingle((address,address,uint256,uint24,uint160))").to_vec();
-    let params = Token::Tuple(vec![
-        Token::Address(token_in),
-        Token::Address(token_out),
-        Token::Uint(amount_in),
-        Token::Uint(U256::from(fee)),
-        Token::Uint(U256::zero()),
-    ]);
-    data.extend(encode(&[params]));
-
-    let out = eth_call(provider, quoter, Bytes::from(data), block).await?;
-    let tokens = decode(
-        &[
-            ParamType::Uint(256),
-            ParamType::Uint(160),
-            ParamType::Uint(32),
-            ParamType::Uint(256),
-        ],
-        out.as_ref(),
-    )
-    .context("failed decoding quoter response")?;
-    if tokens.len() != 4 {
-        anyhow::bail!("unexpected quoter token length {}", tokens.len());
-    }
-    token_as_uint(&tokens[0])
-}
-
-fn token_as_uint(token: &Token) -> Result<U256> {
-    match token {
-        Token::Uint(value) => Ok(*value),
-        _ => anyhow::bail!("expected uint token, found {token:?}"),
+    block: u64,
+) -> Result<LegQuote> {
+    let local = match mode {
+        QuoteMode::Quoter => None,
+        _ => Some(leg.quote_exact_in(token_in, token_out, amount_in)),
+    };
+    if mode == QuoteMode::Local
+        && let Some(Ok(amount_out)) = local
+    {
+        return Ok(LegQuote {
+            amount_out,
+            source: "local",
+            mismatch: None,
+            gas_estimate: None,
+        });
     }
+
+    let Some(quoted) = leg.query_exact_in(provider, token_in, token_out, amount_in, block).await? else {
+        // No on-chain quoter: the local quote is all there is.
+        let amount_out = match local {
+            Some(result) => result?,
+            None => leg.quote_exact_in(token_in, token_out, amount_in)?,
+        };
+        return Ok(LegQuote {
+            amount_out,
+            source: "local",
+            mismatch: None,
+            gas_estimate: None,
+        });
+    };
+    let local_out = local.and_then(Result::ok);
+    let mismatch = local_out
+        .filter(|local| *local != quoted.amount_out)
+        .map(|local| format!("amount_in={amount_in} local_out={local} quoter_out={}", quoted.amount_out));
+    let source = match (mode, local_out) {
+        (QuoteMode::Verify, Some(_)) => "quoter_verified",
+        (QuoteMode::Quoter, _) => "quoter",
+        _ => "quoter_fallback",
+    };
+    Ok(LegQuote {
+        amount_out: quoted.amount_out,
+        source,
+        mismatch,
+        gas_estimate: quoted.gas_estimate,
+    })
 }
 
 fn parse_address(value: &str) -> Result<Address> {
@@ -897,73 +2578,181 @@ fn unix_now_millis() -> Result<u64> {
         .as_millis() as u64)
 }
 
-fn log_route_error(ctx: ErrorEmitContext<'_>, reason: &str, detail: String, stats: &mut ShadowStats) {
-    for input in ctx.input_sizes {
+/// Emits skip rows for every route when the block itself is unusable.
+#[allow(clippy::too_many_arguments)]
+fn log_block_error(
+    run: &RunContext<'_>,
+    pools: &[Box<dyn PoolAdapter>],
+    routes: &[ParsedRoute],
+    block: u64,
+    block_age_secs: u64,
+    reason: &str,
+    detail: &str,
+    stats: &mut ShadowStatsBook,
+) {
+    for route in routes {
+        for &direction in route.directions() {
+            let legs: Vec<LegLog> = direction
+                .hops(route)
+                .iter()
+                .map(|hop| LegLog::unquoted(pools, hop))
+                .collect();
+            log_route_error(
+                ErrorEmitContext {
+                    run_id: run.run_id,
+                    network: run.network,
+                    route: &route.name,
+                    direction,
+                    block,
+                    block_age_secs,
+                    input_sizes: &route.limits.input_sizes,
+                    legs: &legs,
+                },
+                reason,
+                detail.to_string(),
+                stats,
+            );
+        }
+    }
+}
+
+/// Skip rows for a route that could not be evaluated: one for the size the
+/// optimizer would have picked, then one per fixed size.
+fn log_route_error(ctx: ErrorEmitContext<'_>, reason: &str, detail: String, stats: &mut ShadowStatsBook) {
+    let inputs = std::iter::once(("optimal", U256::zero()))
+        .chain(ctx.input_sizes.iter().map(|input| ("ladder", *input)));
+    for (sizing, input) in inputs {
         let row_reason = format!("{reason}:{detail}");
         let row = ShadowDecisionLog {
             run_id: ctx.run_id.to_string(),
             ts_unix_ms: unix_now_millis().unwrap_or(0),
             network: ctx.network.to_string(),
             route: ctx.route.to_string(),
+            direction: ctx.direction.as_str().to_string(),
+            cycle: None,
             block: ctx.block,
             block_age_secs: ctx.block_age_secs,
+            sizing: sizing.to_string(),
+            sizing_method: "none".to_string(),
+            max_input_wei: "0".to_string(),
+            optimal_input_wei: "0".to_string(),
+            expected_profit_wei: "0".to_string(),
             input_wei: input.to_string(),
+            base_fee_wei: "0".to_string(),
+            priority_fee_wei: "0".to_string(),
             gas_price_wei: "0".to_string(),
-            gas_cost_wei: "0".to_string(),
+            max_fee_per_gas_wei: "0".to_string(),
+            gas_units: 0,
+            executor_gas_units: 0,
+            executor_gas_source: "none".to_string(),
+            l2_execution_cost_wei: "0".to_string(),
+            gas_cost_loan_wei: "0".to_string(),
+            gas_rate_source: "none".to_string(),
+            gas_rate_address: None,
+            gas_rate_loan_per_native: "0".to_string(),
+            l1_data_cost_wei: "0".to_string(),
+            l1_fee_method: "none".to_string(),
             flash_fee_wei: "0".to_string(),
+            execution: Execution::FlashLoan.as_str().to_string(),
+            flash_source: "none".to_string(),
+            flash_source_address: None,
+            hypothetical: false,
+            balancer_flash_fee_wei: None,
+            cost_vs_balancer_wei: None,
             total_cost_wei: "0".to_string(),
-            v2_out_mid_wei: "0".to_string(),
-            v3_out_wei: "0".to_string(),
+            marginal_edge_bps: None,
+            legs: ctx.legs.to_vec(),
+            output_wei: "0".to_string(),
             net_wei: "0".to_string(),
             edge_bps: "0".to_string(),
-            v3_quote_latency_ms: 0,
             decision: "would_skip".to_string(),
             reason: row_reason.clone(),
         };
         if let Ok(json) = serde_json::to_string(&row) {
             println!("{json}");
-            stats.record("would_skip", &row_reason);
+            stats.record(ctx.route, "would_skip", &row_reason);
         }
     }
 }
 
-fn emit_row(ctx: EmitContext<'_>, decision: &str, reason: &str, stats: &mut ShadowStats) -> Result<()> {
-    let total_cost = ctx
-        .input
-        .saturating_add(ctx.flash_fee)
-        .saturating_add(ctx.gas_cost);
-    let net = if ctx.v3_out > total_cost {
-        ctx.v3_out - total_cost
+fn emit_row(ctx: EmitContext<'_>, decision: &str, reason: &str, stats: &mut ShadowStatsBook) -> Result<()> {
+    let total_cost = ctx.input.saturating_add(ctx.flash_fee).saturating_add(ctx.gas_cost());
+    let net = if ctx.output > total_cost {
+        ctx.output - total_cost
     } else {
         U256::zero()
     };
-    let edge_bps = signed_edge_bps(ctx.v3_out, total_cost);
+    let edge_bps = signed_edge_bps(ctx.output, total_cost);
 
     let row = ShadowDecisionLog {
         run_id: ctx.run_id.to_string(),
         ts_unix_ms: unix_now_millis()?,
         network: ctx.network.to_string(),
         route: ctx.route.to_string(),
+        direction: ctx.direction.as_str().to_string(),
+        cycle: ctx.cycle.map(str::to_string),
         block: ctx.block,
         block_age_secs: ctx.block_age_secs,
+        sizing: ctx.sizing.to_string(),
+        sizing_method: ctx.size.method.to_string(),
+        max_input_wei: ctx.size.max_input.to_string(),
+        optimal_input_wei: ctx.size.optimal_input.to_string(),
+        expected_profit_wei: ctx.size.expected_profit.to_string(),
         input_wei: ctx.input.to_string(),
-        gas_price_wei: ctx.gas_price.to_string(),
-        gas_cost_wei: ctx.gas_cost.to_string(),
+        base_fee_wei: ctx.fees.base_fee.to_string(),
+        priority_fee_wei: ctx.fees.priority_fee.to_string(),
+        gas_price_wei: ctx.fees.effective_gas_price().to_string(),
+        max_fee_per_gas_wei: ctx.fees.max_fee_per_gas.to_string(),
+        gas_units: ctx.gas_units,
+        executor_gas_units: ctx.executor_gas.units,
+        executor_gas_source: ctx.executor_gas.source.to_string(),
+        l2_execution_cost_wei: ctx.l2_cost.to_string(),
+        gas_cost_loan_wei: ctx.gas_cost().to_string(),
+        gas_rate_source: ctx.gas_rate.map_or("none", |rate| rate.source).to_string(),
+        gas_rate_address: ctx.gas_rate.and_then(|rate| rate.address),
+        gas_rate_loan_per_native: ctx
+            .gas_rate
+            .map(|rate| rate.per_native_token())
+            .unwrap_or_default()
+            .to_string(),
+        l1_data_cost_wei: ctx.l1_fee.wei.to_string(),
+        l1_fee_method: ctx.l1_fee.method.to_string(),
         flash_fee_wei: ctx.flash_fee.to_string(),
+        execution: ctx.execution.as_str().to_string(),
+        flash_source: ctx.flash_source.to_string(),
+        flash_source_address: ctx.flash_source_address,
+        hypothetical: ctx.hypothetical,
+        balancer_flash_fee_wei: ctx.balancer_flash_fee.map(|fee| fee.to_string()),
+        cost_vs_balancer_wei: ctx
+            .balancer_flash_fee
+            .map(|fee| (-signed_net(fee, ctx.flash_fee)).to_string()),
         total_cost_wei: total_cost.to_string(),
-        v2_out_mid_wei: ctx.v2_out_mid.to_string(),
-        v3_out_wei: ctx.v3_out.to_string(),
+        marginal_edge_bps: ctx.marginal_edge_bps,
+        legs: ctx.legs.to_vec(),
+        output_wei: ctx.output.to_string(),
         net_wei: net.to_string(),
         edge_bps,
-        v3_quote_latency_ms: ctx.v3_quote_latency_ms,
         decision: decision.to_string(),
         reason: reason.to_string(),
     };
     println!("{}", serde_json::to_string(&row).context("failed to serialize shadow log row")?);
-    stats.record(decision, reason);
+    stats.record(ctx.route, decision, reason);
     Ok(())
 }
 
+fn emit_reorg(run_id: &str, network: &str, reorg: ReorgEvent) {
+    let row = ShadowReorgLog {
+        run_id: run_id.to_string(),
+        ts_unix_ms: unix_now_millis().unwrap_or(0),
+        network: network.to_string(),
+        reorg,
+    };
+    match serde_json::to_string(&row) {
+        Ok(json) => println!("{json}"),
+        Err(err) => eprintln!("reorg serialization failed: {}", sanitize_error(&err)),
+    }
+}
+
 fn normalized_reason(reason: &str) -> String {
     reason.split(':').next().unwrap_or(reason).to_string()
 }
@@ -1005,14 +2794,30 @@ fn top_reason_counts(stats: &ShadowStats, limit: usize) -> Vec<ReasonCount> {
         .collect()
 }
 
-fn emit_summary(run_id: &str, network: &str, route: &str, latest_block: u64, summary_kind: &str, stats: &ShadowStats) {
+/// One summary per route, then the aggregate across all routes.
+fn emit_summaries(run: &RunContext<'_>, latest_block: u64, summary_kind: &str, stats: &ShadowStatsBook) {
+    for (route, route_stats) in &stats.routes {
+        emit_summary(run, route, latest_block, summary_kind, &stats.aggregate, route_stats);
+    }
+    emit_summary(run, AGGREGATE_ROUTE, latest_block, summary_kind, &stats.aggregate, &stats.aggregate);
+}
+
+fn emit_summary(
+    run: &RunContext<'_>,
+    route: &str,
+    latest_block: u64,
+    summary_kind: &str,
+    aggregate: &ShadowStats,
+    stats: &ShadowStats,
+) {
     let summary = ShadowSummaryLog {
-        run_id: run_id.to_string(),
-        network: network.to_string(),
+        run_id: run.run_id.to_string(),
+        network: run.network.to_string(),
         route: route.to_string(),
         summary_kind: summary_kind.to_string(),
         latest_block,
-        blocks_seen: stats.blocks_seen,
+        blocks_seen: aggregate.blocks_seen,
+        reorgs_seen: aggregate.reorgs_seen,
         rows_emitted: stats.rows_emitted,
         would_trade: stats.would_trade,
         would_skip: stats.would_skip,
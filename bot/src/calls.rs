@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode, encode};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, BlockId, Bytes, I256, TransactionRequest, U256};
+use ethers::utils::id;
+
+/// Multicall3 is deployed at the same address on every chain we target.
+pub const MULTICALL3: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = id(signature);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Builds calldata for `signature` with ABI-encoded `args`.
+pub fn calldata(signature: &str, args: &[Token]) -> Bytes {
+    let mut data = selector(signature).to_vec();
+    data.extend(encode(args));
+    Bytes::from(data)
+}
+
+pub async fn eth_call(provider: &Provider<Http>, to: Address, data: Bytes, block: Option<BlockId>) -> Result<Bytes> {
+    let tx: TypedTransaction = TransactionRequest::new().to(to).data(data).into();
+    provider
+        .call(&tx, block)
+        .await
+        .with_context(|| format!("eth_call failed on {:#x}", to))
+}
+
+pub async fn get_address_view(provider: &Provider<Http>, contract: Address, signature: &str) -> Result<Address> {
+    let out = eth_call(provider, contract, Bytes::from(selector(signature).to_vec()), None).await?;
+    let tokens = decode(&[ParamType::Address], out.as_ref())
+        .with_context(|| format!("decode failed for {signature} on {:#x}", contract))?;
+    match tokens.first() {
+        Some(Token::Address(value)) => Ok(*value),
+        _ => anyhow::bail!("unexpected address response for {signature} on {:#x}", contract),
+    }
+}
+
+pub async fn get_u24_view(provider: &Provider<Http>, contract: Address, signature: &str) -> Result<u32> {
+    let out = eth_call(provider, contract, Bytes::from(selector(signature).to_vec()), None).await?;
+    let tokens = decode(&[ParamType::Uint(24)], out.as_ref())
+        .with_context(|| format!("decode failed for {signature} on {:#x}", contract))?;
+    match tokens.first() {
+        Some(Token::Uint(value)) => Ok(value.low_u32()),
+        _ => anyhow::bail!("unexpected uint24 response for {signature} on {:#x}", contract),
+    }
+}
+
+pub async fn get_i24_view(provider: &Provider<Http>, contract: Address, signature: &str) -> Result<i32> {
+    let out = eth_call(provider, contract, Bytes::from(selector(signature).to_vec()), None).await?;
+    let tokens = decode(&[ParamType::Int(24)], out.as_ref())
+        .with_context(|| format!("decode failed for {signature} on {:#x}", contract))?;
+    match tokens.first() {
+        Some(token) => token_as_i32(token),
+        None => anyhow::bail!("unexpected int24 response for {signature} on {:#x}", contract),
+    }
+}
+
+/// Runs `calls` through Multicall3 `aggregate3` with `allowFailure` set, in
+/// one `eth_call`. Each entry is `None` if that sub-call reverted.
+pub async fn multicall(
+    provider: &Provider<Http>,
+    calls: &[(Address, Bytes)],
+    block: Option<BlockId>,
+) -> Result<Vec<Option<Bytes>>> {
+    if calls.is_empty() {
+        return Ok(Vec::new());
+    }
+    let multicall: Address = MULTICALL3.parse().context("invalid Multicall3 address")?;
+    let encoded_calls = calls
+        .iter()
+        .map(|(target, data)| {
+            Token::Tuple(vec![
+                Token::Address(*target),
+                Token::Bool(true),
+                Token::Bytes(data.to_vec()),
+            ])
+        })
+        .collect();
+    let data = calldata(
+        "aggregate3((address,bool,bytes)[])",
+        &[Token::Array(encoded_calls)],
+    );
+
+    let out = eth_call(provider, multicall, data, block).await?;
+    let tokens = decode(
+        &[ParamType::Array(Box::new(ParamType::Tuple(vec![
+            ParamType::Bool,
+            ParamType::Bytes,
+        ])))],
+        out.as_ref(),
+    )
+    .context("failed decoding aggregate3 response")?;
+    let Some(Token::Array(results)) = tokens.into_iter().next() else {
+        anyhow::bail!("unexpected aggregate3 response shape");
+    };
+    if results.len() != calls.len() {
+        anyhow::bail!(
+            "aggregate3 returned {} results for {} calls",
+            results.len(),
+            calls.len()
+        );
+    }
+
+    results
+        .into_iter()
+        .map(|result| match result {
+            Token::Tuple(mut fields) if fields.len() == 2 => match (fields.remove(0), fields.remove(0)) {
+                (Token::Bool(true), Token::Bytes(bytes)) => Ok(Some(Bytes::from(bytes))),
+                (Token::Bool(false), _) => Ok(None),
+                other => anyhow::bail!("unexpected aggregate3 result {other:?}"),
+            },
+            other => anyhow::bail!("unexpected aggregate3 result {other:?}"),
+        })
+        .collect()
+}
+
+pub fn token_as_uint(token: &Token) -> Result<U256> {
+    match token {
+        Token::Uint(value) => Ok(*value),
+        _ => anyhow::bail!("expected uint token, found {token:?}"),
+    }
+}
+
+/// Reads a sign-extended ABI `int` of up to 128 bits.
+pub fn token_as_i128(token: &Token) -> Result<i128> {
+    match token {
+        Token::Int(value) => Ok(value.low_u128() as i128),
+        _ => anyhow::bail!("expected int token, found {token:?}"),
+    }
+}
+
+/// Reads a sign-extended ABI `int` of up to 32 bits (e.g. `int24`).
+pub fn token_as_i32(token: &Token) -> Result<i32> {
+    match token {
+        Token::Int(value) => Ok(value.low_u32() as i32),
+        _ => anyhow::bail!("expected int token, found {token:?}"),
+    }
+}
+
+pub fn int_token(value: i64) -> Token {
+    Token::Int(I256::from(value).into_raw())
+}
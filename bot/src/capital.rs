@@ -0,0 +1,350 @@
+//! Where a trade's loan comes from. Each flash-loan source charges its own
+//! fee and can lend at most the balance of the token it holds, so both are
+//! read every block for each loan token and a trade borrows from the
+//! cheapest source that covers its size.
+
+use crate::calls::{calldata, eth_call, get_address_view, token_as_uint};
+use crate::venues::{Venue, block_id};
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+
+/// Uniswap V3 fees are in hundredths of a basis point.
+const PIPS_DENOMINATOR: u64 = 1_000_000;
+
+/// Aave `PercentageMath` precision: basis points.
+const PERCENTAGE_FACTOR: u64 = 10_000;
+
+/// Word of Aave V3 `getReserveData` holding `aTokenAddress`.
+const AAVE_A_TOKEN_WORD: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// `Vault.flashLoan`; the fee is the protocol fee collector's
+    /// `getFlashLoanFeePercentage()`.
+    Balancer,
+    /// `Pool.flashLoanSimple`; the fee is `FLASHLOAN_PREMIUM_TOTAL()` and
+    /// the liquidity is the reserve's aToken balance.
+    AaveV3,
+    /// `UniswapV3Pool.flash` on one pool at that pool's swap fee.
+    UniswapV3Flash,
+    /// `Morpho.flashLoan`, which charges nothing.
+    MorphoBlue,
+}
+
+impl SourceKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SourceKind::Balancer => "balancer",
+            SourceKind::AaveV3 => "aave_v3",
+            SourceKind::UniswapV3Flash => "uniswap_v3_flash",
+            SourceKind::MorphoBlue => "morpho_blue",
+        }
+    }
+
+    /// Whether the executor contract can borrow from this source. It only
+    /// calls the Balancer Vault's `flashLoan`, so loans from the others are
+    /// priced but cannot be sent.
+    pub fn executable(self) -> bool {
+        self == SourceKind::Balancer
+    }
+}
+
+/// How a source computes its fee, rounding as its contract does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashFee {
+    /// Balancer `mulUp(amount, percentage)`, 18-decimal percentage.
+    Wad(U256),
+    /// Aave `percentMul(amount, premium)`, basis points rounded half up.
+    Bps(u64),
+    /// Uniswap V3 `mulDivRoundingUp(amount, fee, 1e6)`.
+    Pips(u32),
+    Free,
+}
+
+impl FlashFee {
+    pub fn fee(&self, amount: U256) -> U256 {
+        match *self {
+            FlashFee::Wad(percentage) => {
+                let product = amount.saturating_mul(percentage);
+                if product.is_zero() {
+                    U256::zero()
+                } else {
+                    (product - 1) / U256::exp10(18) + 1
+                }
+            }
+            FlashFee::Bps(premium) => {
+                let half = U256::from(PERCENTAGE_FACTOR / 2);
+                amount.saturating_mul(U256::from(premium)).saturating_add(half) / U256::from(PERCENTAGE_FACTOR)
+            }
+            FlashFee::Pips(fee) => {
+                let product = amount.saturating_mul(U256::from(fee));
+                let denominator = U256::from(PIPS_DENOMINATOR);
+                let fee = product / denominator;
+                if (product % denominator).is_zero() { fee } else { fee + 1 }
+            }
+            FlashFee::Free => U256::zero(),
+        }
+    }
+
+    /// Whether the executor contract accepts this fee on `amount`: it
+    /// reverts with `FeeTooHigh` above `amount * maxFeeBps / 10_000`.
+    pub fn within(&self, amount: U256, max_fee_bps: u16) -> bool {
+        let max_fee = amount.saturating_mul(U256::from(max_fee_bps)) / U256::from(PERCENTAGE_FACTOR);
+        self.fee(amount) <= max_fee
+    }
+
+    /// The rate rounded up to whole basis points, for sizing formulas that
+    /// take a basis-point fee.
+    pub fn ceil_bps(&self) -> u64 {
+        let ceil_div = |numerator: U256, denominator: U256| {
+            let quotient = numerator / denominator;
+            let quotient = if (numerator % denominator).is_zero() { quotient } else { quotient + 1 };
+            quotient.min(U256::from(u64::MAX)).as_u64()
+        };
+        match *self {
+            FlashFee::Wad(percentage) => ceil_div(percentage, U256::exp10(14)),
+            FlashFee::Bps(premium) => premium,
+            FlashFee::Pips(fee) => ceil_div(U256::from(fee), U256::from(100)),
+            FlashFee::Free => 0,
+        }
+    }
+}
+
+/// A lender: the Balancer Vault, the Aave V3 Pool, one Uniswap V3 pool or
+/// Morpho Blue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapitalSource {
+    pub kind: SourceKind,
+    pub address: Address,
+    /// Balancer's protocol fee collector.
+    fee_collector: Option<Address>,
+    /// A Uniswap V3 pool's tokens.
+    pool_tokens: Option<[Address; 2]>,
+}
+
+/// What one source offers for one token at one block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceOffer {
+    pub kind: SourceKind,
+    pub address: Address,
+    pub fee: FlashFee,
+    pub available: U256,
+}
+
+/// The source picked for a size and what it charges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Funding {
+    pub kind: SourceKind,
+    pub address: Address,
+    pub fee: U256,
+    pub available: U256,
+}
+
+impl CapitalSource {
+    /// Reads what does not change between blocks: Balancer's fee collector
+    /// and a Uniswap V3 pool's tokens.
+    pub async fn connect(provider: &Provider<Http>, kind: SourceKind, address: Address) -> Result<Self> {
+        let fee_collector = match kind {
+            SourceKind::Balancer => Some(get_address_view(provider, address, "getProtocolFeesCollector()").await?),
+            _ => None,
+        };
+        let pool_tokens = match kind {
+            SourceKind::UniswapV3Flash => Some([
+                get_address_view(provider, address, "token0()").await?,
+                get_address_view(provider, address, "token1()").await?,
+            ]),
+            _ => None,
+        };
+        Ok(Self {
+            kind,
+            address,
+            fee_collector,
+            pool_tokens,
+        })
+    }
+
+    /// The fee and the balance of `token` this source can lend at `block`,
+    /// or `None` if it does not lend `token` at all.
+    pub async fn offer(&self, provider: &Provider<Http>, token: Address, block: u64) -> Result<Option<SourceOffer>> {
+        let (fee, holder) = match self.kind {
+            SourceKind::Balancer => {
+                let collector = self.fee_collector.context("balancer source has no fee collector")?;
+                let percentage = uint_view(provider, collector, "getFlashLoanFeePercentage()", block).await?;
+                (FlashFee::Wad(percentage), self.address)
+            }
+            SourceKind::AaveV3 => {
+                let premium = uint_view(provider, self.address, "FLASHLOAN_PREMIUM_TOTAL()", block).await?;
+                let Some(a_token) = aave_a_token(provider, self.address, token, block).await? else {
+                    return Ok(None);
+                };
+                (FlashFee::Bps(premium.min(U256::from(PERCENTAGE_FACTOR)).as_u64()), a_token)
+            }
+            SourceKind::UniswapV3Flash => {
+                let tokens = self.pool_tokens.context("uniswap v3 flash source has no pool tokens")?;
+                if !tokens.contains(&token) {
+                    return Ok(None);
+                }
+                let fee = uint_view(provider, self.address, "fee()", block).await?;
+                (FlashFee::Pips(fee.min(U256::from(PIPS_DENOMINATOR)).as_u32()), self.address)
+            }
+            SourceKind::MorphoBlue => (FlashFee::Free, self.address),
+        };
+        let available = balance_of(provider, token, holder, block).await?;
+        Ok(Some(SourceOffer {
+            kind: self.kind,
+            address: self.address,
+            fee,
+            available,
+        }))
+    }
+}
+
+impl SourceOffer {
+    /// Whether borrowing here rules out trading `pool` in the same
+    /// transaction: a Uniswap V3 pool is locked during its own flash, and
+    /// the Balancer Vault guards swaps and flash loans with one lock.
+    pub fn locks(&self, venue: Venue, pool: Address) -> bool {
+        match self.kind {
+            SourceKind::UniswapV3Flash => pool == self.address,
+            SourceKind::Balancer => matches!(venue, Venue::BalancerWeighted | Venue::BalancerStable),
+            SourceKind::AaveV3 | SourceKind::MorphoBlue => false,
+        }
+    }
+}
+
+/// The cheapest offer that can lend `amount` for no more than the token's
+/// `maxFeeBps`, when the contract sets one; the first listed wins ties.
+pub fn cheapest(offers: &[SourceOffer], amount: U256, max_fee_bps: Option<u16>) -> Option<Funding> {
+    offers
+        .iter()
+        .filter(|offer| offer.available >= amount)
+        .filter(|offer| max_fee_bps.is_none_or(|max_fee_bps| offer.fee.within(amount, max_fee_bps)))
+        .map(|offer| Funding {
+            kind: offer.kind,
+            address: offer.address,
+            fee: offer.fee.fee(amount),
+            available: offer.available,
+        })
+        .min_by_key(|funding| funding.fee)
+}
+
+async fn uint_view(provider: &Provider<Http>, contract: Address, signature: &str, block: u64) -> Result<U256> {
+    let out = eth_call(provider, contract, calldata(signature, &[]), block_id(block)).await?;
+    let tokens = decode(&[ParamType::Uint(256)], out.as_ref())
+        .with_context(|| format!("decode failed for {signature} on {contract:#x}"))?;
+    token_as_uint(&tokens[0])
+}
+
+async fn balance_of(provider: &Provider<Http>, token: Address, holder: Address, block: u64) -> Result<U256> {
+    let data = calldata("balanceOf(address)", &[Token::Address(holder)]);
+    let out = eth_call(provider, token, data, block_id(block)).await?;
+    let tokens = decode(&[ParamType::Uint(256)], out.as_ref())
+        .with_context(|| format!("decode failed for balanceOf({holder:#x}) on {token:#x}"))?;
+    token_as_uint(&tokens[0])
+}
+
+/// `getReserveData(token).aTokenAddress`, or `None` for tokens the pool
+/// does not list. Only that word is decoded, so the struct's later fields
+/// may differ between Aave versions.
+async fn aave_a_token(provider: &Provider<Http>, pool: Address, token: Address, block: u64) -> Result<Option<Address>> {
+    let data = calldata("getReserveData(address)", &[Token::Address(token)]);
+    let out = eth_call(provider, pool, data, block_id(block)).await?;
+    let start = AAVE_A_TOKEN_WORD * 32;
+    let word = out
+        .get(start..start + 32)
+        .with_context(|| format!("short getReserveData({token:#x}) response from {pool:#x}"))?;
+    let a_token = Address::from_slice(&word[12..]);
+    Ok((!a_token.is_zero()).then_some(a_token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wad(value: u64) -> FlashFee {
+        FlashFee::Wad(U256::from(value))
+    }
+
+    fn offer(kind: SourceKind, fee: FlashFee, available: u64) -> SourceOffer {
+        SourceOffer {
+            kind,
+            address: Address::from_low_u64_be(kind as u64 + 1),
+            fee,
+            available: U256::from(available),
+        }
+    }
+
+    #[test]
+    fn fees_round_as_each_contract_does() {
+        let fee = |fee: FlashFee, amount: u64| fee.fee(U256::from(amount)).as_u64();
+        // Balancer `mulUp` at 0.05%: exact at 2_000, any remainder rounds up.
+        assert_eq!(fee(wad(500_000_000_000_000), 2_000), 1);
+        assert_eq!(fee(wad(500_000_000_000_000), 2_001), 2);
+        assert_eq!(fee(wad(500_000_000_000_000), 0), 0);
+        // Aave `percentMul` at 5 bps: half a unit rounds up, less rounds down.
+        assert_eq!(fee(FlashFee::Bps(5), 1_000), 1);
+        assert_eq!(fee(FlashFee::Bps(5), 999), 0);
+        // Uniswap V3 `mulDivRoundingUp` at 500 pips.
+        assert_eq!(fee(FlashFee::Pips(500), 2_000), 1);
+        assert_eq!(fee(FlashFee::Pips(500), 2_001), 2);
+        assert_eq!(fee(FlashFee::Free, 1_000_000), 0);
+    }
+
+    #[test]
+    fn within_applies_the_contracts_floored_max_fee() {
+        let amount = U256::from(10_000);
+        assert!(wad(500_000_000_000_000).within(amount, 5));
+        assert!(!wad(500_000_000_000_001).within(amount, 5));
+        // 5 bps of 1_000 floors to 0, so a fee that rounds up to 1 fails.
+        assert!(!wad(500_000_000_000_000).within(U256::from(1_000), 5));
+        assert!(FlashFee::Bps(5).within(U256::from(999), 5));
+        assert!(FlashFee::Free.within(amount, 0));
+    }
+
+    #[test]
+    fn ceil_bps_rounds_partial_basis_points_up() {
+        assert_eq!(wad(500_000_000_000_000).ceil_bps(), 5);
+        assert_eq!(wad(500_000_000_000_001).ceil_bps(), 6);
+        assert_eq!(wad(0).ceil_bps(), 0);
+        assert_eq!(FlashFee::Bps(9).ceil_bps(), 9);
+        assert_eq!(FlashFee::Pips(500).ceil_bps(), 5);
+        assert_eq!(FlashFee::Pips(501).ceil_bps(), 6);
+        assert_eq!(FlashFee::Free.ceil_bps(), 0);
+    }
+
+    #[test]
+    fn cheapest_covers_the_amount_and_keeps_the_first_of_equal_fees() {
+        let offers = [
+            offer(SourceKind::Balancer, wad(0), 100),
+            offer(SourceKind::AaveV3, FlashFee::Bps(5), 1_000),
+            offer(SourceKind::MorphoBlue, FlashFee::Free, 1_000),
+        ];
+        let kind = |offers: &[SourceOffer], amount: u64| {
+            cheapest(offers, U256::from(amount), None).map(|funding| funding.kind)
+        };
+        assert_eq!(kind(&offers, 50), Some(SourceKind::Balancer));
+        assert_eq!(kind(&[offers[2], offers[0]], 50), Some(SourceKind::MorphoBlue));
+        // Aave's 5 bps rounds to nothing on 500 and ties Morpho.
+        assert_eq!(kind(&offers, 500), Some(SourceKind::AaveV3));
+        assert_eq!(kind(&offers, 1_000), Some(SourceKind::MorphoBlue));
+        assert_eq!(kind(&offers, 5_000), None);
+    }
+
+    #[test]
+    fn cheapest_skips_offers_above_max_fee_bps() {
+        let offers = [
+            offer(SourceKind::AaveV3, FlashFee::Bps(9), 100_000),
+            offer(SourceKind::UniswapV3Flash, FlashFee::Pips(500), 100_000),
+        ];
+        let amount = U256::from(10_000);
+        let funding = cheapest(&offers, amount, Some(5)).unwrap();
+        assert_eq!((funding.kind, funding.fee), (SourceKind::UniswapV3Flash, U256::from(5)));
+        assert_eq!(cheapest(&offers[..1], amount, Some(5)), None);
+        assert_eq!(cheapest(&offers[..1], amount, None).map(|funding| funding.fee), Some(U256::from(9)));
+        assert_eq!(cheapest(&offers, amount, Some(4)), None);
+    }
+}
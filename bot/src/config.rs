@@ -1,8 +1,14 @@
-use crate::types::market::{Market, MarketKind};
+use crate::discovery::{FactoryKind, FactorySource};
+use crate::math::price::DEFAULT_PRICE_DECIMALS;
+use crate::tokens::{TokenResolver, default_cache_path};
+use crate::types::market::Market;
+use crate::venues::{self, AdapterOptions, PoolAdapter, PoolSpec, Venue, token_indices};
 use anyhow::{Context, Result};
 use dotenvy::from_filename_override;
+use ethers::providers::{Http, Provider};
 use ethers::types::Address;
 use std::env;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 #[derive(Clone, Debug)]
@@ -15,63 +21,263 @@ pub struct RuntimeConfig {
     pub http_poll_interval_secs: u64,
     pub ws_reconnect_initial_ms: u64,
     pub ws_reconnect_max_ms: u64,
+    pub reorg_track_depth: u64,
+    pub token_metadata_cache_path: Option<PathBuf>,
+}
+
+/// A market as configured in the environment under one prefix. Token
+/// symbols and decimals are optional; when set they must agree with what
+/// `resolve` reads from the chain.
+#[derive(Clone, Debug)]
+pub struct MarketConfig {
+    /// `{prefix}_POOL_KIND`, `{prefix}_POOL` and the optional
+    /// `{prefix}_FEE_BPS`, `_FEE_PIPS`, `_TICK_SPACING` and `_QUOTER`.
+    pub spec: PoolSpec,
+    pub token0: Address,
+    pub token1: Address,
+    pub token0_symbol: Option<String>,
+    pub token1_symbol: Option<String>,
+    pub token0_decimals: Option<u8>,
+    pub token1_decimals: Option<u8>,
+    /// `PRICE_DECIMALS`: fractional digits in exact price output.
+    pub price_decimals: u32,
 }
 
 #[derive(Clone, Debug)]
 pub struct PoolListenerConfig {
-    pub v2_market: Market,
-    pub v3_market: Market,
+    /// One per prefix in `POOL_LISTENER_MARKETS` (default `BASE_V2,BASE_V3`).
+    pub markets: Vec<MarketConfig>,
+    pub warm_start_blocks: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct BackfillConfig {
+    pub from_block: u64,
+    pub to_block: Option<u64>,
+    pub initial_chunk_blocks: u64,
+    pub min_chunk_blocks: u64,
+    pub max_chunk_blocks: u64,
+    pub checkpoint_path: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiscoveryConfig {
+    pub factories: Vec<FactorySource>,
+    pub registry_path: String,
+    pub to_block: Option<u64>,
+    pub initial_chunk_blocks: u64,
+    pub min_chunk_blocks: u64,
+    pub max_chunk_blocks: u64,
 }
 
 impl RuntimeConfig {
     pub fn from_env() -> Result<Self> {
         load_env_file();
+        let expected_chain_id = env_parse_or_default("CHAIN_ID", 8453_u64)?;
         Ok(Self {
             network_name: env_or_default("NETWORK_NAME", "base-mainnet"),
             rpc_https_url: env_required("BASE_RPC_HTTPS_URL")?,
             rpc_wss_url: env_required("BASE_RPC_WSS_URL")?,
-            expected_chain_id: env_parse_or_default("CHAIN_ID", 8453_u64)?,
+            expected_chain_id,
             ws_connect_timeout_secs: env_parse_or_default("WS_CONNECT_TIMEOUT_SECS", 15_u64)?,
             http_poll_interval_secs: env_parse_or_default("HTTP_POLL_INTERVAL_SECS", 2_u64)?,
             ws_reconnect_initial_ms: env_parse_or_default("WS_RECONNECT_INITIAL_MS", 1_000_u64)?,
             ws_reconnect_max_ms: env_parse_or_default("WS_RECONNECT_MAX_MS", 30_000_u64)?,
+            reorg_track_depth: env_parse_or_default("REORG_TRACK_DEPTH", 64_u64)?,
+            token_metadata_cache_path: token_metadata_cache_path(expected_chain_id),
         })
     }
+
+    pub fn token_resolver(&self) -> Result<TokenResolver> {
+        TokenResolver::open(self.token_metadata_cache_path.clone(), self.expected_chain_id)
+    }
+}
+
+/// `TOKEN_METADATA_CACHE_PATH`, defaulting to a per-chain file under `logs/`;
+/// `none` keeps the cache in memory only.
+pub fn token_metadata_cache_path(chain_id: u64) -> Option<PathBuf> {
+    match env::var("TOKEN_METADATA_CACHE_PATH") {
+        Ok(raw) if !raw.trim().is_empty() => {
+            let path = raw.trim().trim_matches('"').trim_matches('\'');
+            (path != "none").then(|| PathBuf::from(path))
+        }
+        _ => Some(default_cache_path(chain_id)),
+    }
+}
+
+/// `DISCOVERY_REGISTRY_PATH`, defaulting to a per-chain file under `logs/`.
+pub fn discovery_registry_path(chain_id: u64) -> String {
+    env_or_default("DISCOVERY_REGISTRY_PATH", &format!("logs/pool_registry.{chain_id}.json"))
+}
+
+impl MarketConfig {
+    /// `BASE_V2` and `BASE_V3` default to `uniswap_v2` and `uniswap_v3`;
+    /// other prefixes must set `{prefix}_POOL_KIND`.
+    fn from_env(prefix: &str) -> Result<Self> {
+        let default_venue = match prefix {
+            "BASE_V2" => Some(Venue::UniswapV2),
+            "BASE_V3" => Some(Venue::UniswapV3),
+            _ => None,
+        };
+        let venue = parse_venue(&format!("{prefix}_POOL_KIND"), default_venue)?;
+        let fee_bps = env_parse_optional(&format!("{prefix}_FEE_BPS"))?;
+        let quoter = env_optional(&format!("{prefix}_QUOTER"))
+            .map(|raw| Address::from_str(&raw).with_context(|| format!("Invalid address in {prefix}_QUOTER: {raw}")))
+            .transpose()?;
+        Ok(Self {
+            spec: PoolSpec {
+                venue,
+                pool: parse_address(&format!("{prefix}_POOL"))?,
+                // Uniswap V2 pairs do not expose their fee.
+                fee_bps: fee_bps.or((venue == Venue::UniswapV2).then_some(30)),
+                fee_pips: env_parse_optional(&format!("{prefix}_FEE_PIPS"))?,
+                tick_spacing: env_parse_optional(&format!("{prefix}_TICK_SPACING"))?,
+                quoter,
+            },
+            token0: parse_address(&format!("{prefix}_TOKEN0"))?,
+            token1: parse_address(&format!("{prefix}_TOKEN1"))?,
+            token0_symbol: env_optional(&format!("{prefix}_TOKEN0_SYMBOL")),
+            token1_symbol: env_optional(&format!("{prefix}_TOKEN1_SYMBOL")),
+            token0_decimals: env_parse_optional(&format!("{prefix}_TOKEN0_DECIMALS"))?,
+            token1_decimals: env_parse_optional(&format!("{prefix}_TOKEN1_DECIMALS"))?,
+            price_decimals: env_parse_or_default("PRICE_DECIMALS", DEFAULT_PRICE_DECIMALS)?,
+        })
+    }
+
+    /// Builds the `Market` with on-chain token metadata, failing if any
+    /// configured symbol or decimals disagree.
+    pub async fn resolve(&self, provider: &Provider<Http>, resolver: &mut TokenResolver) -> Result<Market> {
+        let token0 = resolver
+            .resolve_checked(provider, self.token0, self.token0_symbol.as_deref(), self.token0_decimals)
+            .await
+            .with_context(|| format!("token0 of pool {:#x}", self.spec.pool))?;
+        let token1 = resolver
+            .resolve_checked(provider, self.token1, self.token1_symbol.as_deref(), self.token1_decimals)
+            .await
+            .with_context(|| format!("token1 of pool {:#x}", self.spec.pool))?;
+        Ok(Market::new(
+            self.spec.pool,
+            self.token0,
+            self.token1,
+            token0.symbol,
+            token1.symbol,
+            token0.decimals,
+            token1.decimals,
+        )
+        .with_price_decimals(self.price_decimals))
+    }
+
+    /// Resolves the market and connects the pool's adapter, failing unless
+    /// the pool holds token0 ahead of token1.
+    pub async fn connect(
+        &self,
+        provider: &Provider<Http>,
+        resolver: &mut TokenResolver,
+        options: &AdapterOptions,
+    ) -> Result<(Market, Box<dyn PoolAdapter>)> {
+        let market = self.resolve(provider, resolver).await?;
+        let adapter = venues::connect(provider, &self.spec, options)
+            .await
+            .with_context(|| format!("failed connecting {} pool {:#x}", self.spec.venue.as_str(), self.spec.pool))?;
+        let (index0, index1) = token_indices(adapter.tokens(), self.token0, self.token1)?;
+        if index0 > index1 {
+            anyhow::bail!(
+                "pool {:#x} holds token1 {:#x} ahead of token0 {:#x}; swap them",
+                self.spec.pool,
+                self.token1,
+                self.token0
+            );
+        }
+        Ok((market, adapter))
+    }
 }
 
 impl PoolListenerConfig {
     pub fn from_env() -> Result<Self> {
         load_env_file();
 
-        let v2_market = Market::new(
-            MarketKind::V2Sync,
-            parse_address("BASE_V2_POOL")?,
-            parse_address("BASE_V2_TOKEN0")?,
-            parse_address("BASE_V2_TOKEN1")?,
-            env_or_default("BASE_V2_TOKEN0_SYMBOL", "TOKEN0"),
-            env_or_default("BASE_V2_TOKEN1_SYMBOL", "TOKEN1"),
-            env_parse_or_default("BASE_V2_TOKEN0_DECIMALS", 18_u8)?,
-            env_parse_or_default("BASE_V2_TOKEN1_DECIMALS", 18_u8)?,
-        );
-
-        let v3_market = Market::new(
-            MarketKind::V3Swap,
-            parse_address("BASE_V3_POOL")?,
-            parse_address("BASE_V3_TOKEN0")?,
-            parse_address("BASE_V3_TOKEN1")?,
-            env_or_default("BASE_V3_TOKEN0_SYMBOL", "TOKEN0"),
-            env_or_default("BASE_V3_TOKEN1_SYMBOL", "TOKEN1"),
-            env_parse_or_default("BASE_V3_TOKEN0_DECIMALS", 18_u8)?,
-            env_parse_or_default("BASE_V3_TOKEN1_DECIMALS", 18_u8)?,
-        );
+        let markets = env_or_default("POOL_LISTENER_MARKETS", "BASE_V2,BASE_V3")
+            .split(',')
+            .map(str::trim)
+            .filter(|prefix| !prefix.is_empty())
+            .map(MarketConfig::from_env)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            markets,
+            warm_start_blocks: env_parse_or_default("POOL_LISTENER_WARM_START_BLOCKS", 0_u64)?,
+        })
+    }
+}
+
+impl BackfillConfig {
+    pub fn from_env() -> Result<Self> {
+        load_env_file();
+        let to_block = match env::var("BACKFILL_TO_BLOCK") {
+            Ok(raw) if !raw.trim().is_empty() => Some(env_parse_or_default("BACKFILL_TO_BLOCK", 0_u64)?),
+            _ => None,
+        };
+        let checkpoint_path = env_or_default("BACKFILL_CHECKPOINT_PATH", "logs/backfill.checkpoint.json");
+
+        Ok(Self {
+            from_block: env_required("BACKFILL_FROM_BLOCK")?
+                .parse::<u64>()
+                .context("Failed parsing BACKFILL_FROM_BLOCK")?,
+            to_block,
+            initial_chunk_blocks: env_parse_or_default("BACKFILL_CHUNK_BLOCKS", 2_000_u64)?,
+            min_chunk_blocks: env_parse_or_default("BACKFILL_MIN_CHUNK_BLOCKS", 1_u64)?,
+            max_chunk_blocks: env_parse_or_default("BACKFILL_MAX_CHUNK_BLOCKS", 10_000_u64)?,
+            checkpoint_path: Some(checkpoint_path).filter(|path| path != "none"),
+        })
+    }
+}
 
+impl DiscoveryConfig {
+    pub fn from_env(chain_id: u64) -> Result<Self> {
+        load_env_file();
+        let factories = env_required("DISCOVERY_FACTORIES")?
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(parse_factory_source)
+            .collect::<Result<Vec<_>>>()?;
+        let to_block = match env::var("DISCOVERY_TO_BLOCK") {
+            Ok(raw) if !raw.trim().is_empty() => Some(env_parse_or_default("DISCOVERY_TO_BLOCK", 0_u64)?),
+            _ => None,
+        };
         Ok(Self {
-            v2_market,
-            v3_market,
+            factories,
+            registry_path: discovery_registry_path(chain_id),
+            to_block,
+            initial_chunk_blocks: env_parse_or_default("DISCOVERY_CHUNK_BLOCKS", 10_000_u64)?,
+            min_chunk_blocks: env_parse_or_default("DISCOVERY_MIN_CHUNK_BLOCKS", 1_u64)?,
+            max_chunk_blocks: env_parse_or_default("DISCOVERY_MAX_CHUNK_BLOCKS", 100_000_u64)?,
         })
     }
 }
 
+/// Parses `kind:factory` or `kind:factory@from_block`.
+fn parse_factory_source(entry: &str) -> Result<FactorySource> {
+    let entry = entry.trim().trim_matches('"');
+    let (kind, rest) = entry
+        .split_once(':')
+        .with_context(|| format!("factory entry {entry} must be kind:address[@from_block]"))?;
+    let (factory, from_block) = match rest.split_once('@') {
+        Some((factory, block)) => (
+            factory,
+            block
+                .trim()
+                .parse::<u64>()
+                .with_context(|| format!("invalid from_block in factory entry {entry}"))?,
+        ),
+        None => (rest, 0),
+    };
+    Ok(FactorySource {
+        kind: FactoryKind::parse(kind)?,
+        factory: Address::from_str(factory.trim()).with_context(|| format!("invalid factory address in {entry}"))?,
+        from_block,
+    })
+}
+
 fn load_env_file() {
     from_filename_override(".env").ok();
 }
@@ -93,6 +299,26 @@ fn env_or_default(key: &str, default: &str) -> String {
         .unwrap_or_else(|| default.to_string())
 }
 
+fn env_optional(key: &str) -> Option<String> {
+    env::var(key)
+        .map(|value| value.trim().trim_matches('"').trim_matches('\'').to_string())
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+fn env_parse_optional<T>(key: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    env_optional(key)
+        .map(|raw| {
+            raw.parse::<T>()
+                .map_err(|err| anyhow::anyhow!("Failed parsing {key}: {err}"))
+        })
+        .transpose()
+}
+
 fn env_parse_or_default<T>(key: &str, default: T) -> Result<T>
 where
     T: FromStr + Copy,
@@ -112,6 +338,22 @@ where
     }
 }
 
+/// A venue name as route configs spell it; `solidly` and `velodrome` are
+/// accepted for `aerodrome`.
+fn parse_venue(key: &str, default: Option<Venue>) -> Result<Venue> {
+    let raw = match (env_optional(key), default) {
+        (Some(raw), _) => raw.to_ascii_lowercase(),
+        (None, Some(venue)) => return Ok(venue),
+        (None, None) => anyhow::bail!("{key} is not set. Add it to your .env file."),
+    };
+    let name = match raw.as_str() {
+        "solidly" | "velodrome" => "aerodrome",
+        other => other,
+    };
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+        .with_context(|| format!("{key} must name a venue such as uniswap_v2 or balancer_weighted, got {raw}"))
+}
+
 fn parse_address(key: &str) -> Result<Address> {
     let raw = env_required(key)?;
     Address::from_str(&raw).with_context(|| format!("Invalid address in {key}: {raw}"))
@@ -0,0 +1,109 @@
+//! What a transaction costs on an OP Stack chain beyond its L2 execution
+//! gas: the L1 data fee for posting it, which the `GasPriceOracle`
+//! predeploy computes from the serialized transaction.
+
+use crate::calls::{calldata, eth_call, token_as_uint};
+use crate::venues::block_id;
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, Bytes, U256};
+
+/// `GasPriceOracle` is predeployed at the same address on every OP Stack
+/// chain.
+pub const GAS_PRICE_ORACLE: &str = "0x420000000000000000000000000000000000000F";
+
+/// The oracle's fee formula. Each upgrade keeps the earlier methods:
+/// Ecotone prices calldata from the blob and L1 base fees, and Fjord
+/// prices a FastLZ estimate of the compressed size and adds
+/// `getL1FeeUpperBound`, which prices the largest size FastLZ can produce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OracleUpgrade {
+    Bedrock,
+    Ecotone,
+    Fjord,
+}
+
+impl OracleUpgrade {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OracleUpgrade::Bedrock => "bedrock",
+            OracleUpgrade::Ecotone => "ecotone",
+            OracleUpgrade::Fjord => "fjord",
+        }
+    }
+}
+
+/// An L1 data fee and the oracle method that produced it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct L1Fee {
+    pub wei: U256,
+    pub method: &'static str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct L1FeeOracle {
+    pub address: Address,
+    pub upgrade: OracleUpgrade,
+}
+
+impl L1FeeOracle {
+    /// Reads which upgrade the oracle is on. `isFjord()` and `isEcotone()`
+    /// do not exist before their upgrade, so a failed read counts as not
+    /// upgraded; the flags only change at a hard fork, so they are read
+    /// once.
+    pub async fn connect(provider: &Provider<Http>) -> Result<Self> {
+        let address: Address = GAS_PRICE_ORACLE.parse().context("invalid GasPriceOracle address")?;
+        let upgrade = if bool_view(provider, address, "isFjord()").await.unwrap_or(false) {
+            OracleUpgrade::Fjord
+        } else if bool_view(provider, address, "isEcotone()").await.unwrap_or(false) {
+            OracleUpgrade::Ecotone
+        } else {
+            OracleUpgrade::Bedrock
+        };
+        Ok(Self { address, upgrade })
+    }
+
+    /// `getL1Fee(unsigned_tx)` at `block`: the fee for exactly these bytes.
+    /// `unsigned_tx` is the RLP-encoded transaction without its signature,
+    /// which the oracle accounts for itself.
+    pub async fn l1_fee(&self, provider: &Provider<Http>, unsigned_tx: &Bytes, block: u64) -> Result<L1Fee> {
+        let data = calldata("getL1Fee(bytes)", &[Token::Bytes(unsigned_tx.to_vec())]);
+        Ok(L1Fee {
+            wei: uint_call(provider, self.address, data, "getL1Fee", block).await?,
+            method: "get_l1_fee",
+        })
+    }
+
+    /// The most an `unsigned_tx`-sized transaction can be charged, whatever
+    /// its bytes. From Fjord this is `getL1FeeUpperBound`, which needs only
+    /// the length; earlier oracles have no bound, so they price the bytes
+    /// themselves.
+    pub async fn l1_fee_bound(&self, provider: &Provider<Http>, unsigned_tx: &Bytes, block: u64) -> Result<L1Fee> {
+        if self.upgrade < OracleUpgrade::Fjord {
+            return self.l1_fee(provider, unsigned_tx, block).await;
+        }
+        let data = calldata("getL1FeeUpperBound(uint256)", &[Token::Uint(U256::from(unsigned_tx.len()))]);
+        Ok(L1Fee {
+            wei: uint_call(provider, self.address, data, "getL1FeeUpperBound", block).await?,
+            method: "get_l1_fee_upper_bound",
+        })
+    }
+}
+
+async fn uint_call(provider: &Provider<Http>, oracle: Address, data: Bytes, method: &str, block: u64) -> Result<U256> {
+    let out = eth_call(provider, oracle, data, block_id(block)).await?;
+    let tokens = decode(&[ParamType::Uint(256)], out.as_ref())
+        .with_context(|| format!("decode failed for {method} on {oracle:#x}"))?;
+    token_as_uint(&tokens[0])
+}
+
+async fn bool_view(provider: &Provider<Http>, contract: Address, signature: &str) -> Result<bool> {
+    let out = eth_call(provider, contract, calldata(signature, &[]), None).await?;
+    let tokens = decode(&[ParamType::Bool], out.as_ref())
+        .with_context(|| format!("decode failed for {signature} on {contract:#x}"))?;
+    match tokens.first() {
+        Some(Token::Bool(value)) => Ok(*value),
+        _ => anyhow::bail!("unexpected bool response for {signature} on {contract:#x}"),
+    }
+}
@@ -0,0 +1,358 @@
+use crate::persist::write_json_atomic;
+use crate::venues::{PoolSpec, Venue};
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, decode};
+use ethers::types::{Address, H256, Log};
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Factory families whose pool-creation events we understand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FactoryKind {
+    /// Uniswap V2 and forks emitting `PairCreated`.
+    UniswapV2,
+    /// Uniswap V3 and forks emitting `PoolCreated` with a fee tier.
+    UniswapV3,
+    /// Aerodrome/Velodrome V2 `PoolFactory`.
+    Solidly,
+    /// Aerodrome Slipstream `CLFactory`, keyed by tick spacing.
+    Slipstream,
+}
+
+impl FactoryKind {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "uniswap_v2" => Ok(Self::UniswapV2),
+            "uniswap_v3" => Ok(Self::UniswapV3),
+            "solidly" | "aerodrome" | "velodrome" => Ok(Self::Solidly),
+            "slipstream" => Ok(Self::Slipstream),
+            other => anyhow::bail!("unknown factory kind {other}"),
+        }
+    }
+
+    pub fn creation_topic(self) -> H256 {
+        let signature = match self {
+            Self::UniswapV2 => "PairCreated(address,address,address,uint256)",
+            Self::UniswapV3 => "PoolCreated(address,address,uint24,int24,address)",
+            Self::Solidly => "PoolCreated(address,address,bool,address,uint256)",
+            Self::Slipstream => "PoolCreated(address,address,int24,address)",
+        };
+        H256::from(keccak256(signature))
+    }
+
+    pub fn venue(self) -> Venue {
+        match self {
+            Self::UniswapV2 => Venue::UniswapV2,
+            Self::UniswapV3 => Venue::UniswapV3,
+            Self::Solidly => Venue::Aerodrome,
+            Self::Slipstream => Venue::Slipstream,
+        }
+    }
+}
+
+/// A factory to scan, starting at `from_block` (usually its deployment).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FactorySource {
+    pub kind: FactoryKind,
+    pub factory: Address,
+    pub from_block: u64,
+}
+
+/// One discovered pool. Fields that only some venues have are `None`
+/// elsewhere: `fee` (Uniswap V3 fee tier, pips), `tick_spacing` (V3 and
+/// Slipstream) and `stable` (Solidly).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolRecord {
+    pub pool: Address,
+    pub kind: FactoryKind,
+    pub factory: Address,
+    pub token0: Address,
+    pub token1: Address,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tick_spacing: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stable: Option<bool>,
+    pub created_block: u64,
+}
+
+/// Decodes a pool-creation log from a factory of `kind`. Logs with another
+/// topic yield `None`.
+pub fn decode_pool_created(kind: FactoryKind, log: &Log) -> Result<Option<PoolRecord>> {
+    if log.topics.first() != Some(&kind.creation_topic()) {
+        return Ok(None);
+    }
+    if log.topics.len() < 3 {
+        anyhow::bail!("unexpected topic count {} for {kind:?} creation event", log.topics.len());
+    }
+    let created_block = log
+        .block_number
+        .context("pool creation log missing block number")?
+        .as_u64();
+    let mut record = PoolRecord {
+        pool: Address::zero(),
+        kind,
+        factory: log.address,
+        token0: topic_address(&log.topics[1]),
+        token1: topic_address(&log.topics[2]),
+        fee: None,
+        tick_spacing: None,
+        stable: None,
+        created_block,
+    };
+
+    match kind {
+        FactoryKind::UniswapV2 => {
+            let tokens = decode(&[ParamType::Address, ParamType::Uint(256)], log.data.as_ref())
+                .context("failed decoding PairCreated data")?;
+            record.pool = tokens[0].clone().into_address().context("PairCreated pair is not an address")?;
+        }
+        FactoryKind::UniswapV3 => {
+            let fee = log.topics.get(3).context("PoolCreated missing fee topic")?;
+            let tokens = decode(&[ParamType::Int(24), ParamType::Address], log.data.as_ref())
+                .context("failed decoding PoolCreated data")?;
+            record.fee = Some(topic_u32(fee));
+            record.tick_spacing = Some(tokens[0].clone().into_int().context("tickSpacing is not an int")?.low_u32() as i32);
+            record.pool = tokens[1].clone().into_address().context("PoolCreated pool is not an address")?;
+        }
+        FactoryKind::Solidly => {
+            let stable = log.topics.get(3).context("PoolCreated missing stable topic")?;
+            let tokens = decode(&[ParamType::Address, ParamType::Uint(256)], log.data.as_ref())
+                .context("failed decoding PoolCreated data")?;
+            record.stable = Some(!stable.is_zero());
+            record.pool = tokens[0].clone().into_address().context("PoolCreated pool is not an address")?;
+        }
+        FactoryKind::Slipstream => {
+            let tick_spacing = log.topics.get(3).context("PoolCreated missing tickSpacing topic")?;
+            let tokens =
+                decode(&[ParamType::Address], log.data.as_ref()).context("failed decoding PoolCreated data")?;
+            record.tick_spacing = Some(topic_u32(tick_spacing) as i32);
+            record.pool = tokens[0].clone().into_address().context("PoolCreated pool is not an address")?;
+        }
+    }
+    Ok(Some(record))
+}
+
+fn topic_address(topic: &H256) -> Address {
+    Address::from_slice(&topic.as_bytes()[12..])
+}
+
+/// Low 32 bits of an indexed word; sign-extended `int24` values come out as
+/// their two's complement, so `as i32` recovers them.
+fn topic_u32(topic: &H256) -> u32 {
+    let bytes = topic.as_bytes();
+    u32::from_be_bytes([bytes[28], bytes[29], bytes[30], bytes[31]])
+}
+
+/// What connecting a discovered pool needs beyond its creation event, which
+/// carries no Uniswap V2 fee and no quoter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpecDefaults {
+    pub uniswap_v2_fee_bps: u64,
+    pub uniswap_v3_quoter: Option<Address>,
+    pub slipstream_quoter: Option<Address>,
+}
+
+impl PoolRecord {
+    /// The pool as a route leg would configure it, or `None` for a
+    /// concentrated-liquidity pool whose venue has no quoter in `defaults`.
+    pub fn spec(&self, defaults: &SpecDefaults) -> Option<PoolSpec> {
+        let venue = self.kind.venue();
+        let (fee_bps, quoter) = match self.kind {
+            FactoryKind::UniswapV2 => (Some(defaults.uniswap_v2_fee_bps), None),
+            FactoryKind::Solidly => (None, None),
+            FactoryKind::UniswapV3 => (None, Some(defaults.uniswap_v3_quoter?)),
+            FactoryKind::Slipstream => (None, Some(defaults.slipstream_quoter?)),
+        };
+        Some(PoolSpec {
+            venue,
+            pool: self.pool,
+            fee_bps,
+            fee_pips: self.fee,
+            tick_spacing: self.tick_spacing,
+            quoter,
+        })
+    }
+}
+
+/// Every pool discovered on one chain, persisted as JSON between runs with
+/// the last block scanned for each factory.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PoolRegistry {
+    pub chain_id: u64,
+    #[serde(default)]
+    pub scanned_through: BTreeMap<Address, u64>,
+    #[serde(default)]
+    pub pools: BTreeMap<Address, PoolRecord>,
+}
+
+impl PoolRegistry {
+    pub fn new(chain_id: u64) -> Self {
+        Self {
+            chain_id,
+            ..Self::default()
+        }
+    }
+
+    /// Loads the registry at `path`, or starts an empty one. A file written
+    /// for another chain is an error rather than silently mixed in.
+    pub fn load_or_new(path: &Path, chain_id: u64) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::new(chain_id));
+        }
+        let content =
+            fs::read_to_string(path).with_context(|| format!("failed reading pool registry at {}", path.display()))?;
+        let registry: Self = serde_json::from_str(&content)
+            .with_context(|| format!("failed parsing pool registry at {}", path.display()))?;
+        if registry.chain_id != chain_id {
+            anyhow::bail!(
+                "pool registry at {} is for chain {}, expected {chain_id}",
+                path.display(),
+                registry.chain_id
+            );
+        }
+        Ok(registry)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_json_atomic(path, self, "pool registry")
+    }
+
+    /// First block still to scan for `source`.
+    pub fn next_block(&self, source: &FactorySource) -> u64 {
+        self.scanned_through
+            .get(&source.factory)
+            .map_or(source.from_block, |scanned| scanned.saturating_add(1).max(source.from_block))
+    }
+
+    pub fn mark_scanned(&mut self, factory: Address, through_block: u64) {
+        let entry = self.scanned_through.entry(factory).or_insert(through_block);
+        *entry = (*entry).max(through_block);
+    }
+
+    /// Adds `record`, returning whether the pool was new.
+    pub fn insert(&mut self, record: PoolRecord) -> bool {
+        self.pools.insert(record.pool, record).is_none()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+
+    /// Pools whose two tokens are both in `tokens`.
+    pub fn pools_among<'a>(&'a self, tokens: &'a BTreeSet<Address>) -> impl Iterator<Item = &'a PoolRecord> {
+        self.pools
+            .values()
+            .filter(move |record| tokens.contains(&record.token0) && tokens.contains(&record.token1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{Token, encode};
+    use ethers::types::{I256, U256, U64};
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    fn word(token: Token) -> H256 {
+        H256::from_slice(&encode(&[token]))
+    }
+
+    fn creation_log(kind: FactoryKind, extra_topic: Option<H256>, data: &[Token]) -> Log {
+        let mut topics = vec![
+            kind.creation_topic(),
+            word(Token::Address(address(1))),
+            word(Token::Address(address(2))),
+        ];
+        topics.extend(extra_topic);
+        Log {
+            address: address(100),
+            topics,
+            data: encode(data).into(),
+            block_number: Some(U64::from(1_234)),
+            ..Log::default()
+        }
+    }
+
+    fn int24(value: i32) -> Token {
+        Token::Int(I256::from(value).into_raw())
+    }
+
+    fn decoded(kind: FactoryKind, log: &Log) -> PoolRecord {
+        let record = decode_pool_created(kind, log).unwrap().unwrap();
+        assert_eq!((record.kind, record.factory), (kind, address(100)));
+        assert_eq!((record.token0, record.token1), (address(1), address(2)));
+        assert_eq!(record.created_block, 1_234);
+        record
+    }
+
+    #[test]
+    fn decodes_uniswap_v2_pair_created() {
+        let log = creation_log(
+            FactoryKind::UniswapV2,
+            None,
+            &[Token::Address(address(7)), Token::Uint(U256::from(42))],
+        );
+        let record = decoded(FactoryKind::UniswapV2, &log);
+        assert_eq!(record.pool, address(7));
+        assert_eq!((record.fee, record.tick_spacing, record.stable), (None, None, None));
+    }
+
+    #[test]
+    fn decodes_uniswap_v3_pool_created() {
+        let fee = word(Token::Uint(U256::from(500)));
+        let log = creation_log(FactoryKind::UniswapV3, Some(fee), &[int24(10), Token::Address(address(8))]);
+        let record = decoded(FactoryKind::UniswapV3, &log);
+        assert_eq!(record.pool, address(8));
+        assert_eq!((record.fee, record.tick_spacing, record.stable), (Some(500), Some(10), None));
+    }
+
+    #[test]
+    fn decodes_solidly_pool_created() {
+        for stable in [true, false] {
+            let log = creation_log(
+                FactoryKind::Solidly,
+                Some(word(Token::Bool(stable))),
+                &[Token::Address(address(9)), Token::Uint(U256::from(3))],
+            );
+            let record = decoded(FactoryKind::Solidly, &log);
+            assert_eq!(record.pool, address(9));
+            assert_eq!((record.fee, record.tick_spacing, record.stable), (None, None, Some(stable)));
+        }
+    }
+
+    #[test]
+    fn decodes_slipstream_pool_created_with_negative_tick_spacing() {
+        for tick_spacing in [200, -1, -8_388_608] {
+            let log = creation_log(
+                FactoryKind::Slipstream,
+                Some(word(int24(tick_spacing))),
+                &[Token::Address(address(10))],
+            );
+            let record = decoded(FactoryKind::Slipstream, &log);
+            assert_eq!(record.pool, address(10));
+            assert_eq!(record.tick_spacing, Some(tick_spacing));
+        }
+    }
+
+    #[test]
+    fn skips_other_events_and_rejects_missing_topics() {
+        let log = creation_log(FactoryKind::UniswapV2, None, &[Token::Address(address(7)), Token::Uint(U256::one())]);
+        assert!(decode_pool_created(FactoryKind::Solidly, &log).unwrap().is_none());
+
+        let log = creation_log(FactoryKind::Slipstream, None, &[Token::Address(address(10))]);
+        assert!(decode_pool_created(FactoryKind::Slipstream, &log).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! Read-only views of the deployed `BalancerFlashLoanSimple` contract that
+//! bound what the bot may ask it to do, and the `executeFlashLoan`
+//! transaction it would be sent.
+
+use crate::calls::{calldata, eth_call, get_address_view, token_as_uint};
+use crate::venues::{SwapCall, block_id};
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode, encode};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, Eip1559TransactionRequest, U256};
+
+/// `tokenRiskConfig(token)`. `executeFlashLoan` reverts for tokens that are
+/// not `enabled` and for amounts above `max_loan_amount`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenRiskConfig {
+    pub enabled: bool,
+    pub max_loan_amount: U256,
+    pub max_fee_bps: u16,
+}
+
+impl TokenRiskConfig {
+    /// Largest amount the contract will borrow, or `None` when the token is
+    /// disabled.
+    pub fn loan_cap(&self) -> Option<U256> {
+        self.enabled.then_some(self.max_loan_amount)
+    }
+}
+
+pub async fn token_risk_config(
+    provider: &Provider<Http>,
+    contract: Address,
+    token: Address,
+    block: u64,
+) -> Result<TokenRiskConfig> {
+    let data = calldata("tokenRiskConfig(address)", &[Token::Address(token)]);
+    let out = eth_call(provider, contract, data, block_id(block)).await?;
+    let tokens = decode(&[ParamType::Bool, ParamType::Uint(256), ParamType::Uint(16)], out.as_ref())
+        .with_context(|| format!("decode failed for tokenRiskConfig({token:#x}) on {contract:#x}"))?;
+    let enabled = match tokens[0] {
+        Token::Bool(value) => value,
+        _ => anyhow::bail!("unexpected enabled flag in tokenRiskConfig on {contract:#x}"),
+    };
+    Ok(TokenRiskConfig {
+        enabled,
+        max_loan_amount: token_as_uint(&tokens[1])?,
+        max_fee_bps: token_as_uint(&tokens[2])?.low_u32() as u16,
+    })
+}
+
+/// Everything about an `executeFlashLoan` transaction except its calldata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlashLoanTx {
+    pub chain_id: u64,
+    pub contract: Address,
+    pub gas_limit: u64,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+}
+
+impl FlashLoanTx {
+    /// The type-2 transaction borrowing `amount` of `token` to run `swaps`,
+    /// RLP-encoded with its type byte and without a signature. The nonce is
+    /// left at zero, which the bot cannot know before sending.
+    pub fn unsigned_rlp(&self, token: Address, amount: U256, swaps: &[SwapCall]) -> Bytes {
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .chain_id(self.chain_id)
+            .to(self.contract)
+            .nonce(U256::zero())
+            .gas(self.gas_limit)
+            .max_fee_per_gas(self.max_fee_per_gas)
+            .max_priority_fee_per_gas(self.max_priority_fee_per_gas)
+            .data(execute_flash_loan_calldata(token, amount, swaps))
+            .into();
+        tx.rlp()
+    }
+
+    /// `eth_estimateGas` of the same transaction sent by `operator` at
+    /// `block`. It reverts whenever a send would, e.g. for a disabled token
+    /// or a loan the contract cannot repay. Fees are left unset so the
+    /// estimate does not depend on the operator's balance.
+    pub async fn estimate_gas(
+        &self,
+        provider: &Provider<Http>,
+        operator: Address,
+        token: Address,
+        amount: U256,
+        swaps: &[SwapCall],
+        block: u64,
+    ) -> Result<u64> {
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .from(operator)
+            .chain_id(self.chain_id)
+            .to(self.contract)
+            .data(execute_flash_loan_calldata(token, amount, swaps))
+            .into();
+        let units = provider
+            .estimate_gas(&tx, block_id(block))
+            .await
+            .with_context(|| format!("eth_estimateGas of executeFlashLoan failed on {:#x}", self.contract))?;
+        Ok(units.min(U256::from(u64::MAX)).as_u64())
+    }
+}
+
+/// The only address `executeFlashLoan` accepts calls from.
+pub async fn operator(provider: &Provider<Http>, contract: Address) -> Result<Address> {
+    get_address_view(provider, contract, "operator()").await
+}
+
+/// `executeFlashLoan(token, amount, userData)`. The contract only checks
+/// `userData` against the hash it stored, so the swaps go in it as
+/// `(address target, bytes data)[]` in trading order.
+pub fn execute_flash_loan_calldata(token: Address, amount: U256, swaps: &[SwapCall]) -> Bytes {
+    let plan = swaps
+        .iter()
+        .map(|swap| Token::Tuple(vec![Token::Address(swap.target), Token::Bytes(swap.data.to_vec())]))
+        .collect();
+    calldata(
+        "executeFlashLoan(address,uint256,bytes)",
+        &[
+            Token::Address(token),
+            Token::Uint(amount),
+            Token::Bytes(encode(&[Token::Array(plan)])),
+        ],
+    )
+}
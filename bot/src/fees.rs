@@ -0,0 +1,75 @@
+//! EIP-1559 fees for a type-2 send: the block's `baseFeePerGas` plus a
+//! priority fee taken from what recent blocks' transactions tipped, read
+//! through `eth_feeHistory`.
+
+use anyhow::{Context, Result};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::{Block, BlockNumber, TxHash, U256};
+
+/// Basis-point denominator for the base-fee headroom.
+const BPS: u64 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeeModel {
+    /// How many blocks up to the current one to sample tips from.
+    pub history_blocks: u64,
+    /// Which percentile of each block's tips to sample, 0 to 100.
+    pub priority_percentile: f64,
+    /// `maxFeePerGas` covers the base fee times this, in basis points, so a
+    /// send stays valid while the base fee rises over the next blocks.
+    pub base_fee_headroom_bps: u64,
+}
+
+/// What one block's fee market asks of a send.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Eip1559Fees {
+    pub base_fee: U256,
+    /// Median across the sampled blocks of each block's percentile tip.
+    pub priority_fee: U256,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+}
+
+impl Eip1559Fees {
+    /// What each unit of gas costs if the send lands at this base fee.
+    pub fn effective_gas_price(&self) -> U256 {
+        self.base_fee
+            .saturating_add(self.priority_fee)
+            .min(self.max_fee_per_gas)
+    }
+}
+
+impl FeeModel {
+    /// Fees for a send built on `block`. Fails if the block has no base fee
+    /// or the node does not serve the tip history.
+    pub async fn quote(&self, provider: &Provider<Http>, block: &Block<TxHash>) -> Result<Eip1559Fees> {
+        let base_fee = block.base_fee_per_gas.context("block has no baseFeePerGas")?;
+        let number = block.number.context("block has no number")?;
+        let history = provider
+            .fee_history(self.history_blocks.max(1), BlockNumber::Number(number), &[self.priority_percentile])
+            .await
+            .context("eth_feeHistory failed")?;
+        let mut tips: Vec<U256> = history
+            .reward
+            .iter()
+            .filter_map(|rewards| rewards.first().copied())
+            .collect();
+        if tips.is_empty() {
+            anyhow::bail!("eth_feeHistory returned no rewards for block {number}");
+        }
+        tips.sort();
+        let priority_fee = tips[tips.len() / 2];
+        let max_fee_per_gas = base_fee
+            .saturating_mul(U256::from(self.base_fee_headroom_bps))
+            .checked_div(U256::from(BPS))
+            .unwrap_or_default()
+            .max(base_fee)
+            .saturating_add(priority_fee);
+        Ok(Eip1559Fees {
+            base_fee,
+            priority_fee,
+            max_fee_per_gas,
+            max_priority_fee_per_gas: priority_fee,
+        })
+    }
+}
@@ -0,0 +1,129 @@
+//! Gas a cycle is expected to use, from what each piece of it is known to
+//! cost. A cycle's gas is the executor's share, the flash loan and its
+//! callback, plus each leg's swap and the transfer around it.
+//!
+//! The executor's share comes from `eth_estimateGas` of the real
+//! `executeFlashLoan` calldata when that succeeds, else from the route's
+//! past estimates, else from the configured overhead. The deployed contract
+//! checks `userData` without running the swaps in it, so the estimate
+//! covers only that share. A leg's swap gas is its on-chain quoter's
+//! `gasEstimate` when the quote came from one, else the last estimate seen
+//! for that pool, else a per-venue default.
+
+use crate::venues::Venue;
+use ethers::types::Address;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Swap gas per venue for pools no quoter has estimated yet.
+pub fn default_swap_gas(venue: Venue) -> u64 {
+    match venue {
+        Venue::UniswapV2 | Venue::Aerodrome => 70_000,
+        Venue::UniswapV3 | Venue::Slipstream => 110_000,
+        Venue::BalancerWeighted | Venue::BalancerStable => 120_000,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasModel {
+    /// `executeFlashLoan` and the loan callback, until a route has been
+    /// estimated.
+    pub flash_loan_overhead: u64,
+    /// Per leg: moving the input in and checking the output around a swap.
+    pub per_leg_overhead: u64,
+}
+
+/// Gas units and where they came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasUnits {
+    pub units: u64,
+    pub source: &'static str,
+}
+
+impl GasModel {
+    /// The executor's share plus every leg's swap gas and overhead.
+    pub fn total(&self, executor: u64, swaps: impl IntoIterator<Item = u64>) -> u64 {
+        swaps.into_iter().fold(executor, |total, swap| {
+            total
+                .saturating_add(swap)
+                .saturating_add(self.per_leg_overhead)
+        })
+    }
+}
+
+/// Gas seen in past estimates, kept across blocks: the last `depth`
+/// executor estimates per route and the last quoter estimate per pool and
+/// input token.
+#[derive(Clone, Debug)]
+pub struct GasHistory {
+    depth: usize,
+    executor: BTreeMap<String, VecDeque<u64>>,
+    swaps: BTreeMap<(Address, Address), u64>,
+}
+
+impl GasHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+            executor: BTreeMap::new(),
+            swaps: BTreeMap::new(),
+        }
+    }
+
+    /// The executor's share for `route`: `simulated` if there is one, else
+    /// the median of the route's past estimates, else the model's overhead.
+    pub fn executor(&self, route: &str, simulated: Option<u64>, model: &GasModel) -> GasUnits {
+        if let Some(units) = simulated {
+            return GasUnits {
+                units,
+                source: "simulated",
+            };
+        }
+        match self.executor.get(route).filter(|samples| !samples.is_empty()) {
+            Some(samples) => {
+                let mut sorted: Vec<u64> = samples.iter().copied().collect();
+                sorted.sort_unstable();
+                GasUnits {
+                    units: sorted[sorted.len() / 2],
+                    source: "learned",
+                }
+            }
+            None => GasUnits {
+                units: model.flash_loan_overhead,
+                source: "model",
+            },
+        }
+    }
+
+    pub fn record_executor(&mut self, route: &str, units: u64) {
+        let samples = self.executor.entry(route.to_string()).or_default();
+        if samples.len() == self.depth {
+            samples.pop_front();
+        }
+        samples.push_back(units);
+    }
+
+    /// A leg's swap gas: the quoter's estimate if this quote had one, else
+    /// the last one seen for the pool and direction, else the venue default.
+    pub fn swap(&self, venue: Venue, pool: Address, token_in: Address, quoted: Option<u64>) -> GasUnits {
+        if let Some(units) = quoted {
+            return GasUnits {
+                units,
+                source: "quoter",
+            };
+        }
+        match self.swaps.get(&(pool, token_in)) {
+            Some(units) => GasUnits {
+                units: *units,
+                source: "learned",
+            },
+            None => GasUnits {
+                units: default_swap_gas(venue),
+                source: "default",
+            },
+        }
+    }
+
+    pub fn record_swap(&mut self, pool: Address, token_in: Address, units: u64) {
+        self.swaps.insert((pool, token_in), units);
+    }
+}
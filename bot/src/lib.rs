@@ -1,3 +1,21 @@
+pub mod backfill;
+pub mod calls;
+pub mod capital;
 pub mod config;
+pub mod costs;
+pub mod discovery;
+pub mod executor;
+pub mod fees;
+pub mod gas;
+pub mod loaders;
+pub mod math;
+pub mod pathfinding;
+pub mod persist;
+pub mod pricing;
 pub mod providers;
+pub mod reorg;
+pub mod selection;
+pub mod sizing;
+pub mod tokens;
 pub mod types;
+pub mod venues;
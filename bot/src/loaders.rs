@@ -0,0 +1,271 @@
+use crate::calls::{calldata, int_token, multicall, token_as_i32, token_as_i128, token_as_uint};
+use crate::math::balancer::{BalancerPoolKind, BalancerPoolState};
+use crate::math::v3::{TickInfo, V3PoolState, bitmap_position, compress_tick};
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, BlockId, Bytes, U256};
+use std::collections::BTreeMap;
+
+/// The Balancer V2 Vault has the same address on every chain we target.
+pub const BALANCER_VAULT: &str = "0xBA12222222228d8Ba445958a75a0704d566BF2C8";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalancerPoolType {
+    Weighted,
+    ComposableStable,
+}
+
+/// Loads everything `V3PoolState::swap` needs for `pool` at `block`: slot0,
+/// active liquidity, fee, tick spacing and the initialized ticks inside
+/// `word_radius` bitmap words on either side of the current tick.
+///
+/// Uses two Multicall3 round-trips: one for the pool header and bitmap
+/// words, one for the `ticks()` of every initialized tick found.
+///
+/// Also works for Aerodrome Slipstream pools: their `slot0()` and `ticks()`
+/// return extra trailing fields, which are ignored, and `fee()` reports the
+/// current dynamic fee.
+pub async fn load_v3_pool_state(
+    provider: &Provider<Http>,
+    pool: Address,
+    block: Option<BlockId>,
+    word_radius: i16,
+) -> Result<V3PoolState> {
+    let header = multicall(
+        provider,
+        &[
+            (pool, calldata("slot0()", &[])),
+            (pool, calldata("liquidity()", &[])),
+            (pool, calldata("fee()", &[])),
+            (pool, calldata("tickSpacing()", &[])),
+        ],
+        block,
+    )
+    .await?;
+    let [slot0, liquidity, fee, tick_spacing] = header
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("unexpected v3 header result count"))?;
+
+    let slot0 = decode(
+        &[ParamType::Uint(160), ParamType::Int(24)],
+        require_success(slot0, "slot0()", pool)?.as_ref(),
+    )
+    .context("failed decoding slot0")?;
+    let sqrt_price_x96 = token_as_uint(&slot0[0])?;
+    let tick = token_as_i32(&slot0[1])?;
+    let liquidity = decode_single(&ParamType::Uint(128), liquidity, "liquidity()", pool)?;
+    let liquidity = token_as_uint(&liquidity)?.as_u128();
+    let fee_pips = token_as_uint(&decode_single(&ParamType::Uint(24), fee, "fee()", pool)?)?.low_u32();
+    let tick_spacing = token_as_i32(&decode_single(&ParamType::Int(24), tick_spacing, "tickSpacing()", pool)?)?;
+    if tick_spacing <= 0 {
+        anyhow::bail!("bad_pool_state: non-positive tick spacing {tick_spacing} on {pool:#x}");
+    }
+
+    let (center_word, _) = bitmap_position(compress_tick(tick, tick_spacing));
+    let word_range = (
+        center_word.saturating_sub(word_radius.max(0)),
+        center_word.saturating_add(word_radius.max(0)),
+    );
+    let ticks = load_initialized_ticks(provider, pool, block, tick_spacing, word_range).await?;
+
+    Ok(V3PoolState {
+        sqrt_price_x96,
+        tick,
+        liquidity,
+        fee_pips,
+        tick_spacing,
+        ticks,
+        word_range,
+    })
+}
+
+/// Reads `tickBitmap` for every word in `word_range`, then `ticks()` for
+/// each initialized tick it reports.
+pub async fn load_initialized_ticks(
+    provider: &Provider<Http>,
+    pool: Address,
+    block: Option<BlockId>,
+    tick_spacing: i32,
+    word_range: (i16, i16),
+) -> Result<BTreeMap<i32, TickInfo>> {
+    let words: Vec<i16> = (word_range.0..=word_range.1).collect();
+    let bitmap_calls: Vec<(Address, Bytes)> = words
+        .iter()
+        .map(|word| (pool, calldata("tickBitmap(int16)", &[int_token(i64::from(*word))])))
+        .collect();
+    let bitmaps = multicall(provider, &bitmap_calls, block).await?;
+
+    let mut initialized = Vec::new();
+    for (word, bitmap) in words.iter().zip(bitmaps) {
+        let bitmap = token_as_uint(&decode_single(&ParamType::Uint(256), bitmap, "tickBitmap(int16)", pool)?)?;
+        for bit in 0..256_usize {
+            if bitmap.bit(bit) {
+                let compressed = (i32::from(*word) << 8) + bit as i32;
+                initialized.push(compressed * tick_spacing);
+            }
+        }
+    }
+
+    let tick_calls: Vec<(Address, Bytes)> = initialized
+        .iter()
+        .map(|tick| (pool, calldata("ticks(int24)", &[int_token(i64::from(*tick))])))
+        .collect();
+    let tick_results = multicall(provider, &tick_calls, block).await?;
+
+    let mut ticks = BTreeMap::new();
+    for (tick, result) in initialized.into_iter().zip(tick_results) {
+        let tokens = decode(
+            &[ParamType::Uint(128), ParamType::Int(128)],
+            require_success(result, "ticks(int24)", pool)?.as_ref(),
+        )
+        .with_context(|| format!("failed decoding ticks({tick})"))?;
+        ticks.insert(
+            tick,
+            TickInfo {
+                liquidity_gross: token_as_uint(&tokens[0])?.as_u128(),
+                liquidity_net: token_as_i128(&tokens[1])?,
+            },
+        );
+    }
+    Ok(ticks)
+}
+
+/// Loads a Balancer V2 pool's swap state at `block` in one Multicall3 call:
+/// Vault `getPoolTokens`, the pool's swap fee and scaling factors, and its
+/// weights or amplification. Composable stable pools have their BPT entry
+/// dropped, so the returned token list lines up with the state's indices.
+pub async fn load_balancer_pool_state(
+    provider: &Provider<Http>,
+    vault: Address,
+    pool: Address,
+    pool_id: [u8; 32],
+    pool_type: BalancerPoolType,
+    block: Option<BlockId>,
+) -> Result<(Vec<Address>, BalancerPoolState)> {
+    let mut calls = vec![
+        (
+            vault,
+            calldata("getPoolTokens(bytes32)", &[Token::FixedBytes(pool_id.to_vec())]),
+        ),
+        (pool, calldata("getSwapFeePercentage()", &[])),
+        (pool, calldata("getScalingFactors()", &[])),
+    ];
+    match pool_type {
+        BalancerPoolType::Weighted => calls.push((pool, calldata("getNormalizedWeights()", &[]))),
+        BalancerPoolType::ComposableStable => {
+            calls.push((pool, calldata("getAmplificationParameter()", &[])));
+            calls.push((pool, calldata("getBptIndex()", &[])));
+        }
+    }
+    let mut results = multicall(provider, &calls, block).await?.into_iter();
+    let mut next = |signature: &str, target: Address| {
+        require_success(results.next().flatten(), signature, target)
+    };
+
+    let pool_tokens = decode(
+        &[
+            ParamType::Array(Box::new(ParamType::Address)),
+            ParamType::Array(Box::new(ParamType::Uint(256))),
+            ParamType::Uint(256),
+        ],
+        next("getPoolTokens(bytes32)", vault)?.as_ref(),
+    )
+    .context("failed decoding getPoolTokens")?;
+    let mut tokens = address_array(&pool_tokens[0])?;
+    let mut balances = uint_array(&pool_tokens[1])?;
+    let swap_fee = token_as_uint(&decode_single(
+        &ParamType::Uint(256),
+        Some(next("getSwapFeePercentage()", pool)?),
+        "getSwapFeePercentage()",
+        pool,
+    )?)?;
+    let mut scaling_factors = uint_array(&decode_single(
+        &ParamType::Array(Box::new(ParamType::Uint(256))),
+        Some(next("getScalingFactors()", pool)?),
+        "getScalingFactors()",
+        pool,
+    )?)?;
+
+    let kind = match pool_type {
+        BalancerPoolType::Weighted => BalancerPoolKind::Weighted {
+            weights: uint_array(&decode_single(
+                &ParamType::Array(Box::new(ParamType::Uint(256))),
+                Some(next("getNormalizedWeights()", pool)?),
+                "getNormalizedWeights()",
+                pool,
+            )?)?,
+        },
+        BalancerPoolType::ComposableStable => {
+            let amp = decode(
+                &[ParamType::Uint(256), ParamType::Bool, ParamType::Uint(256)],
+                next("getAmplificationParameter()", pool)?.as_ref(),
+            )
+            .context("failed decoding getAmplificationParameter")?;
+            let bpt_index = token_as_uint(&decode_single(
+                &ParamType::Uint(256),
+                Some(next("getBptIndex()", pool)?),
+                "getBptIndex()",
+                pool,
+            )?)?
+            .as_usize();
+            if bpt_index >= tokens.len() || bpt_index >= scaling_factors.len() {
+                anyhow::bail!("bad_pool_state: bpt index {bpt_index} out of range on {pool:#x}");
+            }
+            tokens.remove(bpt_index);
+            balances.remove(bpt_index);
+            scaling_factors.remove(bpt_index);
+            BalancerPoolKind::Stable {
+                amp: token_as_uint(&amp[0])?,
+            }
+        }
+    };
+
+    Ok((
+        tokens,
+        BalancerPoolState {
+            kind,
+            balances,
+            scaling_factors,
+            swap_fee,
+        },
+    ))
+}
+
+fn address_array(token: &Token) -> Result<Vec<Address>> {
+    match token {
+        Token::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Token::Address(value) => Ok(*value),
+                other => anyhow::bail!("expected address token, found {other:?}"),
+            })
+            .collect(),
+        other => anyhow::bail!("expected address array, found {other:?}"),
+    }
+}
+
+fn uint_array(token: &Token) -> Result<Vec<U256>> {
+    match token {
+        Token::Array(items) => items.iter().map(token_as_uint).collect(),
+        other => anyhow::bail!("expected uint array, found {other:?}"),
+    }
+}
+
+fn require_success(result: Option<Bytes>, signature: &str, contract: Address) -> Result<Bytes> {
+    result.with_context(|| format!("{signature} reverted on {contract:#x}"))
+}
+
+fn decode_single(
+    param: &ParamType,
+    result: Option<Bytes>,
+    signature: &str,
+    contract: Address,
+) -> Result<ethers::abi::Token> {
+    let bytes = require_success(result, signature, contract)?;
+    decode(std::slice::from_ref(param), bytes.as_ref())
+        .with_context(|| format!("decode failed for {signature} on {contract:#x}"))?
+        .into_iter()
+        .next()
+        .with_context(|| format!("empty response for {signature} on {contract:#x}"))
+}
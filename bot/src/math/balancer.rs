@@ -0,0 +1,615 @@
+use anyhow::{Context, Result};
+use ethers::types::U256;
+
+/// Balancer `FixedPoint.ONE`: values are 18-decimal fixed point.
+pub fn one() -> U256 {
+    U256::exp10(18)
+}
+
+/// `StableMath._AMP_PRECISION`: amplification values carry three decimals.
+pub const AMP_PRECISION: u64 = 1_000;
+
+/// `WeightedMath._MAX_IN_RATIO`: a swap may not add more than 30% of the
+/// input balance.
+fn max_in_ratio() -> U256 {
+    U256::from(300_000_000_000_000_000_u64)
+}
+
+/// `FixedPoint.MAX_POW_RELATIVE_ERROR` (1e-14).
+const MAX_POW_RELATIVE_ERROR: u64 = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BalancerPoolKind {
+    /// Normalized weights, one per token, summing to `ONE`.
+    Weighted { weights: Vec<U256> },
+    /// Composable stable pool. `amp` already includes `AMP_PRECISION`.
+    Stable { amp: U256 },
+}
+
+/// A Balancer V2 pool as the Vault sees it. For composable stable pools the
+/// BPT entry is already removed from `balances` and `scaling_factors`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalancerPoolState {
+    pub kind: BalancerPoolKind,
+    pub balances: Vec<U256>,
+    /// `getScalingFactors()` as returned, i.e. already 1e18-scaled: a token
+    /// without a rate provider has `10**(36 - decimals)`, and a rate (itself
+    /// 1e18-scaled) multiplies that with `mulDown`.
+    pub scaling_factors: Vec<U256>,
+    pub swap_fee: U256,
+}
+
+impl BalancerPoolState {
+    /// Port of `BasePool._swapGivenIn`: the fee comes off the raw amount,
+    /// balances and amount are upscaled, the pool curve is applied, and the
+    /// result is downscaled rounding down, as `queryBatchSwap` reports it.
+    pub fn exact_input(&self, index_in: usize, index_out: usize, amount_in: U256) -> Result<U256> {
+        let count = self.balances.len();
+        if index_in >= count || index_out >= count || index_in == index_out {
+            anyhow::bail!("invalid balancer token indices {index_in}->{index_out} for {count} tokens");
+        }
+        if self.scaling_factors.len() != count {
+            anyhow::bail!("balancer scaling factor count {} != {count}", self.scaling_factors.len());
+        }
+        if amount_in.is_zero() {
+            return Ok(U256::zero());
+        }
+
+        let amount_in = sub(amount_in, mul_up(amount_in, self.swap_fee)?)?;
+        let balances = self
+            .balances
+            .iter()
+            .zip(&self.scaling_factors)
+            .map(|(balance, factor)| mul_down(*balance, *factor))
+            .collect::<Result<Vec<_>>>()?;
+        let amount_in = mul_down(amount_in, self.scaling_factors[index_in])?;
+
+        let amount_out = match &self.kind {
+            BalancerPoolKind::Weighted { weights } => {
+                if weights.len() != count {
+                    anyhow::bail!("balancer weight count {} != {count}", weights.len());
+                }
+                weighted_out_given_in(
+                    balances[index_in],
+                    weights[index_in],
+                    balances[index_out],
+                    weights[index_out],
+                    amount_in,
+                )?
+            }
+            BalancerPoolKind::Stable { amp } => {
+                let invariant = stable_invariant(*amp, &balances)?;
+                stable_out_given_in(*amp, balances, index_in, index_out, amount_in, invariant)?
+            }
+        };
+        div_down(amount_out, self.scaling_factors[index_out])
+    }
+}
+
+/// `WeightedMath._calcOutGivenIn`.
+pub fn weighted_out_given_in(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_in: U256,
+) -> Result<U256> {
+    if amount_in > mul_down(balance_in, max_in_ratio())? {
+        anyhow::bail!("balancer MAX_IN_RATIO exceeded");
+    }
+    let denominator = add(balance_in, amount_in)?;
+    let base = div_up(balance_in, denominator)?;
+    let exponent = div_down(weight_in, weight_out)?;
+    let power = pow_up(base, exponent)?;
+    mul_down(balance_out, complement(power))
+}
+
+/// `StableMath._calculateInvariant` (Newton iteration, 255 rounds max).
+pub fn stable_invariant(amp: U256, balances: &[U256]) -> Result<U256> {
+    let num_tokens = U256::from(balances.len());
+    let sum = balances.iter().try_fold(U256::zero(), |acc, balance| add(acc, *balance))?;
+    if sum.is_zero() {
+        return Ok(U256::zero());
+    }
+    let amp_precision = U256::from(AMP_PRECISION);
+    let amp_times_total = mul(amp, num_tokens)?;
+    let mut invariant = sum;
+    for _ in 0..255 {
+        let mut d_p = invariant;
+        for balance in balances {
+            d_p = div_down_raw(mul(d_p, invariant)?, mul(*balance, num_tokens)?)?;
+        }
+        let prev_invariant = invariant;
+        let numerator = mul(
+            add(div_down_raw(mul(amp_times_total, sum)?, amp_precision)?, mul(d_p, num_tokens)?)?,
+            invariant,
+        )?;
+        let denominator = add(
+            div_down_raw(mul(sub(amp_times_total, amp_precision)?, invariant)?, amp_precision)?,
+            mul(add(num_tokens, U256::one())?, d_p)?,
+        )?;
+        invariant = div_down_raw(numerator, denominator)?;
+        if abs_diff(invariant, prev_invariant) <= U256::one() {
+            return Ok(invariant);
+        }
+    }
+    anyhow::bail!("balancer stable invariant did not converge")
+}
+
+/// `StableMath._calcOutGivenIn`.
+pub fn stable_out_given_in(
+    amp: U256,
+    mut balances: Vec<U256>,
+    index_in: usize,
+    index_out: usize,
+    amount_in: U256,
+    invariant: U256,
+) -> Result<U256> {
+    balances[index_in] = add(balances[index_in], amount_in)?;
+    let final_balance_out = stable_balance_given_invariant(amp, &balances, invariant, index_out)?;
+    sub(sub(balances[index_out], final_balance_out)?, U256::one())
+}
+
+/// `StableMath._getTokenBalanceGivenInvariantAndAllOtherBalances`.
+fn stable_balance_given_invariant(amp: U256, balances: &[U256], invariant: U256, index: usize) -> Result<U256> {
+    let num_tokens = U256::from(balances.len());
+    let amp_precision = U256::from(AMP_PRECISION);
+    let amp_times_total = mul(amp, num_tokens)?;
+    let mut sum = balances[0];
+    let mut p_d = mul(balances[0], num_tokens)?;
+    for balance in &balances[1..] {
+        p_d = div_down_raw(mul(mul(p_d, *balance)?, num_tokens)?, invariant)?;
+        sum = add(sum, *balance)?;
+    }
+    sum = sub(sum, balances[index])?;
+
+    let inv2 = mul(invariant, invariant)?;
+    let c = mul(
+        mul(div_up_raw(inv2, mul(amp_times_total, p_d)?)?, amp_precision)?,
+        balances[index],
+    )?;
+    let b = add(sum, mul(div_down_raw(invariant, amp_times_total)?, amp_precision)?)?;
+
+    let mut token_balance = div_up_raw(add(inv2, c)?, add(invariant, b)?)?;
+    for _ in 0..255 {
+        let prev = token_balance;
+        token_balance = div_up_raw(
+            add(mul(token_balance, token_balance)?, c)?,
+            sub(add(mul(token_balance, U256::from(2))?, b)?, invariant)?,
+        )?;
+        if abs_diff(token_balance, prev) <= U256::one() {
+            return Ok(token_balance);
+        }
+    }
+    anyhow::bail!("balancer stable balance did not converge")
+}
+
+/// `FixedPoint.powUp`, with the exact shortcuts for exponents 1, 2 and 4.
+pub fn pow_up(x: U256, y: U256) -> Result<U256> {
+    if y == one() {
+        return Ok(x);
+    }
+    if y == one() * 2 {
+        return mul_up(x, x);
+    }
+    if y == one() * 4 {
+        let square = mul_up(x, x)?;
+        return mul_up(square, square);
+    }
+    let raw = log_exp::pow(x, y)?;
+    let max_error = add(mul_up(raw, U256::from(MAX_POW_RELATIVE_ERROR))?, U256::one())?;
+    add(raw, max_error)
+}
+
+pub fn mul_down(a: U256, b: U256) -> Result<U256> {
+    Ok(mul(a, b)? / one())
+}
+
+pub fn mul_up(a: U256, b: U256) -> Result<U256> {
+    let product = mul(a, b)?;
+    if product.is_zero() {
+        return Ok(U256::zero());
+    }
+    Ok((product - 1) / one() + 1)
+}
+
+pub fn div_down(a: U256, b: U256) -> Result<U256> {
+    if b.is_zero() {
+        anyhow::bail!("balancer fixed point division by zero");
+    }
+    Ok(mul(a, one())? / b)
+}
+
+pub fn div_up(a: U256, b: U256) -> Result<U256> {
+    if b.is_zero() {
+        anyhow::bail!("balancer fixed point division by zero");
+    }
+    if a.is_zero() {
+        return Ok(U256::zero());
+    }
+    Ok((mul(a, one())? - 1) / b + 1)
+}
+
+fn complement(x: U256) -> U256 {
+    one().saturating_sub(x)
+}
+
+fn div_down_raw(a: U256, b: U256) -> Result<U256> {
+    a.checked_div(b).context("balancer division by zero")
+}
+
+fn div_up_raw(a: U256, b: U256) -> Result<U256> {
+    if b.is_zero() {
+        anyhow::bail!("balancer division by zero");
+    }
+    if a.is_zero() {
+        return Ok(U256::zero());
+    }
+    Ok((a - 1) / b + 1)
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b { a - b } else { b - a }
+}
+
+fn mul(a: U256, b: U256) -> Result<U256> {
+    a.checked_mul(b).context("uint256 overflow")
+}
+
+fn add(a: U256, b: U256) -> Result<U256> {
+    a.checked_add(b).context("uint256 overflow")
+}
+
+fn sub(a: U256, b: U256) -> Result<U256> {
+    a.checked_sub(b).context("uint256 underflow")
+}
+
+/// Port of Balancer's `LogExpMath`: `x^y` as `exp(y * ln(x))` with 18 and
+/// 20 (and, near 1, 36) decimal intermediate precision.
+mod log_exp {
+    use anyhow::Result;
+    use ethers::types::{I256, U256};
+
+    fn int(value: &str) -> I256 {
+        I256::from_dec_str(value).expect("valid LogExpMath constant")
+    }
+
+    fn one_18() -> I256 {
+        int("1000000000000000000")
+    }
+
+    fn one_20() -> I256 {
+        int("100000000000000000000")
+    }
+
+    fn one_36() -> I256 {
+        int("1000000000000000000000000000000000000")
+    }
+
+    /// `(x_n, a_n = e^x_n)` pairs. The first two are 18-decimal exponents
+    /// with integer powers; the rest are 20-decimal fixed point.
+    fn terms() -> [(I256, I256); 12] {
+        [
+            (int("128000000000000000000"), int("38877084059945950922200000000000000000000000000000000000")),
+            (int("64000000000000000000"), int("6235149080811616882910000000")),
+            (int("3200000000000000000000"), int("7896296018268069516100000000000000")),
+            (int("1600000000000000000000"), int("888611052050787263676000000")),
+            (int("800000000000000000000"), int("298095798704172827474000")),
+            (int("400000000000000000000"), int("5459815003314423907810")),
+            (int("200000000000000000000"), int("738905609893065022723")),
+            (int("100000000000000000000"), int("271828182845904523536")),
+            (int("50000000000000000000"), int("164872127070012814685")),
+            (int("25000000000000000000"), int("128402541668774148407")),
+            (int("12500000000000000000"), int("113314845306682631683")),
+            (int("6250000000000000000"), int("106449445891785942956")),
+        ]
+    }
+
+    pub fn pow(x: U256, y: U256) -> Result<U256> {
+        if y.is_zero() {
+            return Ok(U256::exp10(18));
+        }
+        if x.is_zero() {
+            return Ok(U256::zero());
+        }
+        if x.bit(255) {
+            anyhow::bail!("LogExpMath X_OUT_OF_BOUNDS");
+        }
+        let mild_exponent_bound = (U256::one() << 254) / U256::exp10(20);
+        if y >= mild_exponent_bound {
+            anyhow::bail!("LogExpMath Y_OUT_OF_BOUNDS");
+        }
+        let x = I256::from_raw(x);
+        let y = I256::from_raw(y);
+
+        let ln_36_lower_bound = one_18() - int("100000000000000000");
+        let ln_36_upper_bound = one_18() + int("100000000000000000");
+        let mut logx_times_y = if ln_36_lower_bound < x && x < ln_36_upper_bound {
+            let ln_36_x = ln_36(x);
+            (ln_36_x / one_18()) * y + ((ln_36_x % one_18()) * y) / one_18()
+        } else {
+            ln(x) * y
+        };
+        logx_times_y /= one_18();
+
+        if logx_times_y < int("-41000000000000000000") || logx_times_y > int("130000000000000000000") {
+            anyhow::bail!("LogExpMath PRODUCT_OUT_OF_BOUNDS");
+        }
+        Ok(exp(logx_times_y).into_raw())
+    }
+
+    fn exp(x: I256) -> I256 {
+        if x.is_negative() {
+            return (one_18() * one_18()) / exp(-x);
+        }
+        let terms = terms();
+        let mut x = x;
+        let first_an = if x >= terms[0].0 {
+            x -= terms[0].0;
+            terms[0].1
+        } else if x >= terms[1].0 {
+            x -= terms[1].0;
+            terms[1].1
+        } else {
+            I256::one()
+        };
+
+        x *= I256::from(100);
+        let mut product = one_20();
+        for (x_n, a_n) in &terms[2..10] {
+            if x >= *x_n {
+                x -= *x_n;
+                product = (product * *a_n) / one_20();
+            }
+        }
+
+        let mut series_sum = one_20();
+        let mut term = x;
+        series_sum += term;
+        for divisor in 2..=12 {
+            term = ((term * x) / one_20()) / I256::from(divisor);
+            series_sum += term;
+        }
+        (((product * series_sum) / one_20()) * first_an) / I256::from(100)
+    }
+
+    fn ln(a: I256) -> I256 {
+        if a < one_18() {
+            return -ln((one_18() * one_18()) / a);
+        }
+        let terms = terms();
+        let mut a = a;
+        let mut sum = I256::zero();
+        for (x_n, a_n) in &terms[..2] {
+            if a >= *a_n * one_18() {
+                a /= *a_n;
+                sum += *x_n;
+            }
+        }
+
+        sum *= I256::from(100);
+        a *= I256::from(100);
+        for (x_n, a_n) in &terms[2..] {
+            if a >= *a_n {
+                a = (a * one_20()) / *a_n;
+                sum += *x_n;
+            }
+        }
+
+        let z = ((a - one_20()) * one_20()) / (a + one_20());
+        let z_squared = (z * z) / one_20();
+        let mut num = z;
+        let mut series_sum = num;
+        for divisor in [3, 5, 7, 9, 11] {
+            num = (num * z_squared) / one_20();
+            series_sum += num / I256::from(divisor);
+        }
+        series_sum *= I256::from(2);
+        (sum + series_sum) / I256::from(100)
+    }
+
+    fn ln_36(x: I256) -> I256 {
+        let x = x * one_18();
+        let z = ((x - one_36()) * one_36()) / (x + one_36());
+        let z_squared = (z * z) / one_36();
+        let mut num = z;
+        let mut series_sum = num;
+        for divisor in [3, 5, 7, 9, 11, 13, 15] {
+            num = (num * z_squared) / one_36();
+            series_sum += num / I256::from(divisor);
+        }
+        series_sum * I256::from(2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    fn e(n: u64, decimals: usize) -> U256 {
+        U256::from(n) * U256::exp10(decimals)
+    }
+
+    /// `getScalingFactors()` entry for a token of `decimals` with no rate.
+    fn scaling(decimals: usize) -> U256 {
+        U256::exp10(36 - decimals)
+    }
+
+    /// Reads `value` as a float in units of `10**decimals`.
+    fn units(value: U256, decimals: i32) -> f64 {
+        value.to_string().parse::<f64>().unwrap() / 10_f64.powi(decimals)
+    }
+
+    fn assert_close(actual: f64, expected: f64, relative: f64) {
+        let error = ((actual - expected) / expected).abs();
+        assert!(error <= relative, "{actual} vs {expected}: relative error {error:e}");
+    }
+
+    // There are no published per-swap vectors to pin against, so the ports
+    // are checked the way Balancer's own math tests check the contracts:
+    // against the real-valued curves, within a relative error bound. Each
+    // curve is written out below in f64 straight from its defining equation.
+
+    /// `StableMath`'s invariant with `a = amp * n / AMP_PRECISION` (the
+    /// contract folds `n^(n-1)` into `amp`): `a*S + D = a*D + D^(n+1) / (n^n * P)`.
+    fn stable_invariant_f64(amp: f64, balances: &[f64]) -> f64 {
+        let n = balances.len() as f64;
+        let a = amp * n;
+        let sum: f64 = balances.iter().sum();
+        let product: f64 = balances.iter().product();
+        let g = |d: f64| a * d + d.powf(n + 1.0) / (n.powf(n) * product) - a * sum - d;
+        let (mut low, mut high) = (0.0, sum);
+        for _ in 0..200 {
+            let mid = (low + high) / 2.0;
+            if g(mid) < 0.0 { low = mid } else { high = mid }
+        }
+        low
+    }
+
+    /// Balance of `index` that keeps `stable_invariant_f64` at `invariant`.
+    fn stable_balance_f64(amp: f64, balances: &[f64], invariant: f64, index: usize) -> f64 {
+        let mut balances = balances.to_vec();
+        let (mut low, mut high) = (0.0, invariant);
+        for _ in 0..200 {
+            balances[index] = (low + high) / 2.0;
+            if stable_invariant_f64(amp, &balances) < invariant {
+                low = balances[index];
+            } else {
+                high = balances[index];
+            }
+        }
+        low
+    }
+
+    fn stable_out_f64(amp: f64, balances: &[f64], index_in: usize, index_out: usize, amount_in: f64) -> f64 {
+        let invariant = stable_invariant_f64(amp, balances);
+        let mut after = balances.to_vec();
+        after[index_in] += amount_in;
+        balances[index_out] - stable_balance_f64(amp, &after, invariant, index_out)
+    }
+
+    /// `WeightedMath`'s out-given-in: `Bo * (1 - (Bi / (Bi + Ai))^(Wi / Wo))`.
+    fn weighted_out_f64(balance_in: f64, weight_in: f64, balance_out: f64, weight_out: f64, amount_in: f64) -> f64 {
+        balance_out * (1.0 - (balance_in / (balance_in + amount_in)).powf(weight_in / weight_out))
+    }
+
+    #[test]
+    fn log_exp_pow_is_within_max_relative_error() {
+        for (x, y) in [(2.0, 0.5), (0.5, 3.0), (1.05, 0.25), (1.5, 1.3), (12_345.0, 0.2)] {
+            let fixed = |value: f64| U256::from((value * 1e6).round() as u64) * U256::exp10(12);
+            let actual = log_exp::pow(fixed(x), fixed(y)).unwrap();
+            assert_close(units(actual, 18), f64::powf(x, y), 1e-14);
+        }
+        assert_eq!(log_exp::pow(u("123"), U256::zero()).unwrap(), one());
+        assert_eq!(log_exp::pow(U256::zero(), one()).unwrap(), U256::zero());
+        assert!(log_exp::pow(U256::one() << 255, one()).is_err());
+    }
+
+    #[test]
+    fn pow_up_adds_max_relative_error() {
+        let raw = log_exp::pow(e(2, 18), e(5, 17)).unwrap();
+        let expected = raw + mul_up(raw, U256::from(MAX_POW_RELATIVE_ERROR)).unwrap() + 1;
+        assert_eq!(pow_up(e(2, 18), e(5, 17)).unwrap(), expected);
+        assert_eq!(pow_up(e(3, 17), one()).unwrap(), e(3, 17));
+        assert_eq!(pow_up(e(3, 17), e(2, 18)).unwrap(), e(9, 16));
+        assert_eq!(pow_up(e(3, 17), e(4, 18)).unwrap(), u("8100000000000000"));
+    }
+
+    /// 80/20 pool of an 18-decimal token and a 6-decimal one, 0.3% fee.
+    fn weighted_pool() -> BalancerPoolState {
+        BalancerPoolState {
+            kind: BalancerPoolKind::Weighted {
+                weights: vec![e(8, 17), e(2, 17)],
+            },
+            balances: vec![e(2_000_000, 18), e(3_000_000, 6)],
+            scaling_factors: vec![scaling(18), scaling(6)],
+            swap_fee: e(3, 15),
+        }
+    }
+
+    #[test]
+    fn weighted_exact_input_matches_curve() {
+        let pool = weighted_pool();
+        // weightIn / weightOut = 4 takes the exact `powUp` shortcut.
+        let actual = pool.exact_input(0, 1, e(10_000, 18)).unwrap();
+        let expected = weighted_out_f64(2e6, 0.8, 3e6, 0.2, 10_000.0 * 0.997);
+        // Downscaling to 6 decimals drops up to one unit.
+        assert!(units(actual, 6) <= expected && expected - units(actual, 6) < 1e-6);
+        // 0.25 goes through `LogExpMath.pow`, whose error `powUp` adds back
+        // in the pool's favour.
+        let actual = pool.exact_input(1, 0, e(25_000, 6)).unwrap();
+        let expected = weighted_out_f64(3e6, 0.2, 2e6, 0.8, 25_000.0 * 0.997);
+        assert!(units(actual, 18) <= expected);
+        assert_close(units(actual, 18), expected, 1e-10);
+
+        let even = BalancerPoolState {
+            kind: BalancerPoolKind::Weighted {
+                weights: vec![e(5, 17), e(5, 17)],
+            },
+            balances: vec![e(1_000, 18), e(1_200, 18)],
+            scaling_factors: vec![scaling(18), scaling(18)],
+            swap_fee: e(1, 15),
+        };
+        // Equal weights reduce to `Bo * Ai / (Bi + Ai)`.
+        let actual = even.exact_input(0, 1, e(7, 18)).unwrap();
+        assert_close(units(actual, 18), 1_200.0 * 6.993 / 1_006.993, 1e-15);
+    }
+
+    #[test]
+    fn weighted_exact_input_enforces_max_in_ratio() {
+        let pool = weighted_pool();
+        assert!(pool.exact_input(1, 0, e(1_000_000, 6)).is_err());
+    }
+
+    #[test]
+    fn stable_invariant_matches_curve() {
+        let amp = U256::from(200 * AMP_PRECISION);
+        // A balanced pool's invariant is the sum of its balances.
+        let balanced = [e(10_000_000, 18); 3];
+        let invariant = stable_invariant(amp, &balanced).unwrap();
+        assert!(abs_diff(invariant, e(30_000_000, 18)) <= U256::one());
+
+        let balances = [e(12_000_000, 18), e(11_500_000, 18), e(9_000_000, 18)];
+        let invariant = stable_invariant(amp, &balances).unwrap();
+        assert_close(units(invariant, 18), stable_invariant_f64(200.0, &[12e6, 11.5e6, 9e6]), 1e-14);
+        assert_eq!(stable_invariant(amp, &[U256::zero(); 3]).unwrap(), U256::zero());
+    }
+
+    #[test]
+    fn stable_exact_input_matches_curve() {
+        let pool = BalancerPoolState {
+            kind: BalancerPoolKind::Stable {
+                amp: U256::from(200 * AMP_PRECISION),
+            },
+            balances: vec![e(12_000_000, 18), e(11_500_000, 6), e(9_000_000, 6)],
+            scaling_factors: vec![scaling(18), scaling(6), scaling(6)],
+            swap_fee: e(1, 14),
+        };
+        let balances = [12e6, 11.5e6, 9e6];
+        let actual = pool.exact_input(1, 2, e(1_000_000, 6)).unwrap();
+        assert_close(units(actual, 6), stable_out_f64(200.0, &balances, 1, 2, 999_900.0), 1e-12);
+        let actual = pool.exact_input(0, 1, e(250_000, 18)).unwrap();
+        assert_close(units(actual, 6), stable_out_f64(200.0, &balances, 0, 1, 249_975.0), 1e-12);
+    }
+
+    #[test]
+    fn stable_exact_input_applies_token_rates() {
+        // The first token's scaling factor carries a 1.15 rate provider.
+        let pool = BalancerPoolState {
+            kind: BalancerPoolKind::Stable {
+                amp: U256::from(50 * AMP_PRECISION),
+            },
+            balances: vec![e(5_000, 18), e(5_600, 18)],
+            scaling_factors: vec![e(115, 16), scaling(18)],
+            swap_fee: e(4, 14),
+        };
+        // The curve runs on rate-scaled balances; the output token has no rate.
+        let actual = pool.exact_input(0, 1, e(100, 18)).unwrap();
+        let expected = stable_out_f64(50.0, &[5_000.0 * 1.15, 5_600.0], 0, 1, 100.0 * 0.9996 * 1.15);
+        assert_close(units(actual, 18), expected, 1e-12);
+    }
+}
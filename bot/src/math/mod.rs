@@ -0,0 +1,5 @@
+pub mod balancer;
+pub mod price;
+pub mod solidly;
+pub mod v2;
+pub mod v3;
@@ -0,0 +1,219 @@
+use ethers::types::{U256, U512};
+use std::cmp::Ordering;
+
+/// Fractional digits printed when no precision is configured.
+pub const DEFAULT_PRICE_DECIMALS: u32 = 18;
+
+/// Denominators are capped so `remainder * 10` always fits while printing.
+const MAX_DENOMINATOR_BITS: usize = 508;
+
+/// An exact price as a ratio of integers, in whole-token units (decimals
+/// already applied). Threshold checks compare `Price`s directly; the
+/// decimal string and `f64` forms are for output only.
+#[derive(Clone, Copy, Debug)]
+pub struct Price {
+    numerator: U512,
+    denominator: U512,
+}
+
+impl Price {
+    /// `None` for a zero or oversized denominator.
+    pub fn new(numerator: U512, denominator: U512) -> Option<Self> {
+        if denominator.is_zero() || denominator.bits() > MAX_DENOMINATOR_BITS {
+            return None;
+        }
+        Some(Self {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// Token1 per token0 implied by constant-product reserves:
+    /// `(reserve1 / 10^decimals1) / (reserve0 / 10^decimals0)`.
+    pub fn from_reserves(reserve0: U256, reserve1: U256, decimals0: u8, decimals1: u8) -> Option<Self> {
+        let numerator = U512::from(reserve1).checked_mul(pow10(decimals0)?)?;
+        let denominator = U512::from(reserve0).checked_mul(pow10(decimals1)?)?;
+        Self::new(numerator, denominator)
+    }
+
+    /// Token1 per token0 at a V3 `sqrtPriceX96`: `sqrtPriceX96² / 2^192`,
+    /// decimal-adjusted. The square is kept in full 512-bit precision.
+    pub fn from_sqrt_price_x96(sqrt_price_x96: U256, decimals0: u8, decimals1: u8) -> Option<Self> {
+        let numerator = sqrt_price_x96.full_mul(sqrt_price_x96).checked_mul(pow10(decimals0)?)?;
+        let denominator = (U512::one() << 192).checked_mul(pow10(decimals1)?)?;
+        Self::new(numerator, denominator)
+    }
+
+    pub fn numerator(&self) -> U512 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> U512 {
+        self.denominator
+    }
+
+    /// The opposite orientation (token0 per token1). `None` for a zero price.
+    pub fn inverse(&self) -> Option<Self> {
+        Self::new(self.denominator, self.numerator)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator.is_zero()
+    }
+
+    /// `floor(price * 10^decimals)`, i.e. the price as a fixed-point integer.
+    pub fn to_fixed(&self, decimals: u32) -> Option<U512> {
+        let scale = U512::from(10).checked_pow(U512::from(decimals))?;
+        Some(self.numerator.checked_mul(scale)? / self.denominator)
+    }
+
+    /// The price with exactly `decimals` fractional digits, truncated
+    /// towards zero (never rounded up past the true value).
+    pub fn to_decimal_string(&self, decimals: u32) -> String {
+        let integer = self.numerator / self.denominator;
+        let mut remainder = self.numerator % self.denominator;
+        if decimals == 0 {
+            return integer.to_string();
+        }
+        let mut text = integer.to_string();
+        text.push('.');
+        let ten = U512::from(10);
+        for _ in 0..decimals {
+            remainder *= ten;
+            let digit = (remainder / self.denominator).low_u32();
+            remainder %= self.denominator;
+            text.push(char::from_digit(digit, 10).unwrap_or('0'));
+        }
+        text
+    }
+
+    /// Nearest `f64`, for display only.
+    pub fn to_f64(&self) -> f64 {
+        u512_to_f64(self.numerator) / u512_to_f64(self.denominator)
+    }
+}
+
+/// Equal values are equal however they are written, e.g. `1/2 == 2/4`.
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Price {}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Exact comparison without cross-multiplying (which could overflow 512
+/// bits): compares integer parts, then the inverted remainders, as in a
+/// continued-fraction expansion.
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (mut a, mut b) = (self.numerator, self.denominator);
+        let (mut c, mut d) = (other.numerator, other.denominator);
+        let mut flipped = false;
+        loop {
+            let ordering = (a / b).cmp(&(c / d));
+            let (rest_a, rest_c) = (a % b, c % d);
+            let ordering = match ordering {
+                Ordering::Equal => match (rest_a.is_zero(), rest_c.is_zero()) {
+                    (true, true) => Ordering::Equal,
+                    (true, false) => Ordering::Less,
+                    (false, true) => Ordering::Greater,
+                    (false, false) => {
+                        // rest_a/b vs rest_c/d orders the same way as d/rest_c vs b/rest_a.
+                        (a, b, c, d) = (b, rest_a, d, rest_c);
+                        flipped = !flipped;
+                        continue;
+                    }
+                },
+                other => other,
+            };
+            return if flipped { ordering.reverse() } else { ordering };
+        }
+    }
+}
+
+fn pow10(decimals: u8) -> Option<U512> {
+    U512::from(10).checked_pow(U512::from(decimals))
+}
+
+fn u512_to_f64(value: U512) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 18_446_744_073_709_551_616.0 + *limb as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(numerator: u64, denominator: u64) -> Price {
+        Price::new(U512::from(numerator), U512::from(denominator)).unwrap()
+    }
+
+    #[test]
+    fn equal_values_compare_equal_in_any_form() {
+        assert_eq!(price(1, 2), price(2, 4));
+        assert_eq!(price(1, 2).cmp(&price(3, 6)), Ordering::Equal);
+        assert_eq!(price(0, 7), price(0, 1));
+        assert_ne!(price(1, 2), price(1, 3));
+    }
+
+    #[test]
+    fn cmp_orders_close_fractions() {
+        // Both have integer part 3; they differ deep in the expansion.
+        assert!(price(22, 7) > price(355, 113));
+        assert!(price(355, 113) < price(22, 7));
+        assert!(price(1, 3) < price(1, 2));
+        assert!(price(5, 1) > price(9, 2));
+        // One terminates, the other does not.
+        assert!(price(3, 1) < price(301, 100));
+        // Consecutive Fibonacci ratios alternate around the golden ratio,
+        // so the expansions agree for dozens of steps.
+        assert!(price(fib(40), fib(39)) < price(fib(42), fib(41)));
+        assert!(price(fib(41), fib(40)) > price(fib(42), fib(41)));
+    }
+
+    #[test]
+    fn cmp_handles_values_too_large_to_cross_multiply() {
+        let max = U512::MAX >> 4;
+        let big = Price::new(max, max - 1).unwrap();
+        let bigger = Price::new(max - 1, max - 2).unwrap();
+        assert!(big < bigger);
+        assert_eq!(Price::new(max - 1, max - 1).unwrap(), price(1, 1));
+    }
+
+    #[test]
+    fn decimal_string_truncates_towards_zero() {
+        assert_eq!(price(2, 3).to_decimal_string(3), "0.666");
+        assert_eq!(price(1, 8).to_decimal_string(2), "0.12");
+        assert_eq!(price(7, 2).to_decimal_string(0), "3");
+        assert_eq!(price(10, 4).to_decimal_string(4), "2.5000");
+        assert_eq!(price(1, 3).to_fixed(6), Some(U512::from(333_333)));
+    }
+
+    #[test]
+    fn sqrt_price_x96_applies_decimals() {
+        let q96 = U256::one() << 96;
+        assert_eq!(Price::from_sqrt_price_x96(q96, 18, 18), Some(price(1, 1)));
+        assert_eq!(Price::from_sqrt_price_x96(q96 << 1, 6, 6), Some(price(4, 1)));
+        // 1 raw unit of an 18-decimal token0 per raw unit of a 6-decimal
+        // token1 is 10^12 token1 per token0.
+        assert_eq!(Price::from_sqrt_price_x96(q96, 18, 6), Some(price(1_000_000_000_000, 1)));
+        assert_eq!(
+            Price::from_sqrt_price_x96(q96 >> 1, 6, 6).unwrap().to_decimal_string(4),
+            "0.2500"
+        );
+    }
+
+    fn fib(n: u32) -> u64 {
+        (0..n).fold((0_u64, 1_u64), |(a, b), _| (b, a + b)).0
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use ethers::types::U256;
+
+/// Aerodrome and Velodrome V2 pool fees are in basis points.
+pub const FEE_DENOMINATOR: u64 = 10_000;
+
+fn e18() -> U256 {
+    U256::exp10(18)
+}
+
+/// Static parameters of a Solidly-style (Aerodrome/Velodrome V2) pool.
+/// `decimals0`/`decimals1` are `10**decimals`, as returned by `metadata()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SolidlyPool {
+    pub stable: bool,
+    pub decimals0: U256,
+    pub decimals1: U256,
+    pub fee_bps: u64,
+}
+
+impl SolidlyPool {
+    /// Port of `Pool.getAmountOut`: the fee is taken from the input first,
+    /// then the volatile (`x*y=k`) or stable (`x³y+y³x=k`) curve is solved
+    /// with the same integer rounding as the contract.
+    pub fn get_amount_out(&self, amount_in: U256, reserve0: U256, reserve1: U256, token0_in: bool) -> Result<U256> {
+        if amount_in.is_zero() || reserve0.is_zero() || reserve1.is_zero() {
+            return Ok(U256::zero());
+        }
+        let fee = mul(amount_in, U256::from(self.fee_bps))? / U256::from(FEE_DENOMINATOR);
+        let amount_in = amount_in.saturating_sub(fee);
+
+        if !self.stable {
+            let (reserve_a, reserve_b) = if token0_in {
+                (reserve0, reserve1)
+            } else {
+                (reserve1, reserve0)
+            };
+            return Ok(mul(amount_in, reserve_b)? / add(reserve_a, amount_in)?);
+        }
+
+        let xy = self.k(reserve0, reserve1)?;
+        let reserve0 = mul(reserve0, e18())? / self.decimals0;
+        let reserve1 = mul(reserve1, e18())? / self.decimals1;
+        let (reserve_a, reserve_b, decimals_in, decimals_out) = if token0_in {
+            (reserve0, reserve1, self.decimals0, self.decimals1)
+        } else {
+            (reserve1, reserve0, self.decimals1, self.decimals0)
+        };
+        let amount_in = mul(amount_in, e18())? / decimals_in;
+        let y = reserve_b
+            .checked_sub(self.get_y(add(amount_in, reserve_a)?, xy, reserve_b)?)
+            .context("stable curve returned y above reserve")?;
+        Ok(mul(y, decimals_out)? / e18())
+    }
+
+    /// `Pool._k`: the invariant on raw reserves. For stable pools reserves
+    /// are first normalized to 18 decimals.
+    pub fn k(&self, x: U256, y: U256) -> Result<U256> {
+        if !self.stable {
+            return mul(x, y);
+        }
+        let x = mul(x, e18())? / self.decimals0;
+        let y = mul(y, e18())? / self.decimals1;
+        let a = mul(x, y)? / e18();
+        let b = add(mul(x, x)? / e18(), mul(y, y)? / e18())?;
+        Ok(mul(a, b)? / e18())
+    }
+
+    /// `Pool._get_y`: Newton's method for `y` such that `f(x0, y) == xy`,
+    /// capped at 255 iterations like the contract (which reverts `!y`).
+    fn get_y(&self, x0: U256, xy: U256, mut y: U256) -> Result<U256> {
+        for _ in 0..255 {
+            let k = f(x0, y)?;
+            if k < xy {
+                let mut dy = mul(xy - k, e18())? / d(x0, y)?;
+                if dy.is_zero() {
+                    if k == xy {
+                        return Ok(y);
+                    }
+                    // The contract calls `_k` here on already-normalized
+                    // values, so they are normalized a second time.
+                    if self.k(x0, add(y, U256::one())?)? > xy {
+                        return Ok(y + 1);
+                    }
+                    dy = U256::one();
+                }
+                y = add(y, dy)?;
+            } else {
+                let mut dy = mul(k - xy, e18())? / d(x0, y)?;
+                if dy.is_zero() {
+                    if k == xy || f(x0, y.checked_sub(U256::one()).context("stable curve underflow")?)? < xy {
+                        return Ok(y);
+                    }
+                    dy = U256::one();
+                }
+                y = y.checked_sub(dy).context("stable curve underflow")?;
+            }
+        }
+        anyhow::bail!("stable curve did not converge (!y)")
+    }
+}
+
+fn f(x0: U256, y: U256) -> Result<U256> {
+    let a = mul(x0, y)? / e18();
+    let b = add(mul(x0, x0)? / e18(), mul(y, y)? / e18())?;
+    Ok(mul(a, b)? / e18())
+}
+
+fn d(x0: U256, y: U256) -> Result<U256> {
+    let left = mul(mul(U256::from(3), x0)?, mul(y, y)? / e18())? / e18();
+    let right = mul(mul(x0, x0)? / e18(), x0)? / e18();
+    let value = add(left, right)?;
+    if value.is_zero() {
+        anyhow::bail!("stable curve derivative is zero");
+    }
+    Ok(value)
+}
+
+fn mul(a: U256, b: U256) -> Result<U256> {
+    a.checked_mul(b).context("uint256 overflow")
+}
+
+fn add(a: U256, b: U256) -> Result<U256> {
+    a.checked_add(b).context("uint256 overflow")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    fn e(n: u64, decimals: usize) -> U256 {
+        U256::from(n) * U256::exp10(decimals)
+    }
+
+    fn pool(stable: bool, decimals0: usize, decimals1: usize, fee_bps: u64) -> SolidlyPool {
+        SolidlyPool {
+            stable,
+            decimals0: U256::exp10(decimals0),
+            decimals1: U256::exp10(decimals1),
+            fee_bps,
+        }
+    }
+
+    // Aerodrome publishes no `getAmountOut` vectors, so expectations are
+    // derived from `Pool.sol` itself: the volatile formula is written out by
+    // hand, and stable outputs are checked against the invariant `_get_y`
+    // solves, which the pool's `swap` enforces with its `K` check.
+
+    #[test]
+    fn volatile_amount_out_matches_formula() {
+        let pool = pool(false, 18, 6, 30);
+        let (reserve0, reserve1) = (e(2_000, 18), e(5_000_000, 6));
+
+        // 0.3% of 5e18 is taken first; 4.985e18 reaches `x*y=k`.
+        let amount_in = e(4_985, 15);
+        let expected = amount_in * reserve1 / (reserve0 + amount_in);
+        assert_eq!(pool.get_amount_out(e(5, 18), reserve0, reserve1, true).unwrap(), expected);
+
+        let amount_in = e(9_970, 6);
+        let expected = amount_in * reserve0 / (reserve1 + amount_in);
+        assert_eq!(pool.get_amount_out(e(10_000, 6), reserve0, reserve1, false).unwrap(), expected);
+
+        // The fee rounds down: 30 bps of 3333 wei is 9, not 10.
+        let expected = U256::from(3_324) * reserve1 / (reserve0 + 3_324);
+        assert_eq!(pool.get_amount_out(U256::from(3_333), reserve0, reserve1, true).unwrap(), expected);
+    }
+
+    /// Whether `amount_out` keeps the stable invariant after the fee is taken
+    /// from `amount_in`, and one more unit of output would break it.
+    fn settles_on_curve(
+        pool: &SolidlyPool,
+        amount_in: U256,
+        amount_out: U256,
+        reserves: (U256, U256),
+        token0_in: bool,
+    ) -> (bool, bool) {
+        let amount_in = amount_in - amount_in * pool.fee_bps / FEE_DENOMINATOR;
+        let k = pool.k(reserves.0, reserves.1).unwrap();
+        let k_after = |out: U256| {
+            if token0_in {
+                pool.k(reserves.0 + amount_in, reserves.1 - out).unwrap()
+            } else {
+                pool.k(reserves.0 - out, reserves.1 + amount_in).unwrap()
+            }
+        };
+        (k_after(amount_out) >= k, k_after(amount_out + 1) < k)
+    }
+
+    #[test]
+    fn stable_amount_out_settles_on_curve() {
+        let cases = [
+            (pool(true, 6, 6, 5), e(100_000, 6), (e(4_000_000, 6), e(3_500_000, 6)), true),
+            (pool(true, 6, 6, 5), e(250_000, 6), (e(4_000_000, 6), e(3_500_000, 6)), false),
+            (pool(true, 6, 18, 1), e(50_000, 6), (e(2_000_000, 6), e(2_100_000, 18)), true),
+            (pool(true, 6, 18, 1), e(50_000, 18), (e(2_000_000, 6), e(2_100_000, 18)), false),
+            (pool(true, 18, 18, 5), e(1_000, 18), (e(30_000, 18), e(28_000, 18)), true),
+        ];
+        for (pool, amount_in, reserves, token0_in) in cases {
+            let amount_out = pool.get_amount_out(amount_in, reserves.0, reserves.1, token0_in).unwrap();
+            assert_eq!(settles_on_curve(&pool, amount_in, amount_out, reserves, token0_in), (true, true));
+        }
+    }
+
+    #[test]
+    fn stable_amount_out_near_peg_is_close_to_input() {
+        let pool = pool(true, 18, 18, 0);
+        let amount_out = pool.get_amount_out(e(1, 18), e(1_000_000, 18), e(1_000_000, 18), true).unwrap();
+        assert!(amount_out < e(1, 18));
+        assert!(amount_out > e(1, 18) - e(1, 9));
+    }
+
+    /// Pools this lopsided reach the branch of `_get_y` that calls `_k` on
+    /// already-normalized values. The quote then lands one wei past the
+    /// curve: solving with `_f` there instead would return one wei less.
+    #[test]
+    fn stable_amount_out_keeps_double_normalization() {
+        let cases = [
+            (pool(true, 6, 18, 5), U256::from(25_790), (U256::from(70_127), u("2140544619597643560")), true),
+            (pool(true, 18, 6, 5), U256::from(53_453), (u("5580563111335358234"), U256::from(41_890)), false),
+        ];
+        for (pool, amount_in, reserves, token0_in) in cases {
+            let amount_out = pool.get_amount_out(amount_in, reserves.0, reserves.1, token0_in).unwrap();
+            assert_eq!(settles_on_curve(&pool, amount_in, amount_out, reserves, token0_in), (false, true));
+            assert_eq!(
+                settles_on_curve(&pool, amount_in, amount_out - 1, reserves, token0_in),
+                (true, true)
+            );
+        }
+    }
+
+    #[test]
+    fn stable_swap_does_not_decrease_k() {
+        let pool = pool(true, 6, 18, 0);
+        let (reserve0, reserve1) = (e(2_000_000, 6), e(2_100_000, 18));
+        let amount_in = e(50_000, 6);
+        let amount_out = pool.get_amount_out(amount_in, reserve0, reserve1, true).unwrap();
+        assert!(pool.k(reserve0 + amount_in, reserve1 - amount_out).unwrap() >= pool.k(reserve0, reserve1).unwrap());
+    }
+}
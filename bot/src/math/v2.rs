@@ -0,0 +1,76 @@
+use anyhow::{Context, Result};
+use ethers::types::U256;
+
+/// Fees on constant-product pools are configured in basis points.
+pub const FEE_DENOMINATOR: u64 = 10_000;
+
+/// `UniswapV2Library.getAmountOut` generalized to a fee in basis points
+/// (30 reproduces the pair's `997/1000`).
+pub fn get_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, fee_bps: u64) -> Result<U256> {
+    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return Ok(U256::zero());
+    }
+    let amount_in_with_fee = mul(amount_in, fee_complement(fee_bps))?;
+    let numerator = mul(amount_in_with_fee, reserve_out)?;
+    let denominator = mul(reserve_in, U256::from(FEE_DENOMINATOR))?
+        .checked_add(amount_in_with_fee)
+        .context("uint256 overflow")?;
+    Ok(numerator / denominator)
+}
+
+/// `UniswapV2Library.getAmountIn`: the smallest input that yields
+/// `amount_out`, rounded up by one as the library does.
+pub fn get_amount_in(amount_out: U256, reserve_in: U256, reserve_out: U256, fee_bps: u64) -> Result<U256> {
+    if amount_out.is_zero() {
+        return Ok(U256::zero());
+    }
+    if reserve_in.is_zero() || amount_out >= reserve_out {
+        anyhow::bail!("insufficient liquidity for exact output: wanted {amount_out}, reserve {reserve_out}");
+    }
+    let numerator = mul(mul(reserve_in, amount_out)?, U256::from(FEE_DENOMINATOR))?;
+    let denominator = mul(reserve_out - amount_out, fee_complement(fee_bps))?;
+    if denominator.is_zero() {
+        anyhow::bail!("fee of {fee_bps} bps leaves no input");
+    }
+    Ok(numerator / denominator + 1)
+}
+
+fn fee_complement(fee_bps: u64) -> U256 {
+    U256::from(FEE_DENOMINATOR.saturating_sub(fee_bps.min(FEE_DENOMINATOR)))
+}
+
+fn mul(a: U256, b: U256) -> Result<U256> {
+    a.checked_mul(b).context("uint256 overflow")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn e18(n: u64) -> U256 {
+        U256::from(n) * U256::exp10(18)
+    }
+
+    /// `UniswapV2Pair.swap`'s `K` check after a flash swap that sent
+    /// `amount_out` and was repaid `repayment`, with the fee in basis points.
+    fn k_check_passes(repayment: U256, amount_out: U256, reserve_in: U256, reserve_out: U256, fee_bps: u64) -> bool {
+        let denominator = U256::from(FEE_DENOMINATOR);
+        let balance_in = (reserve_in + repayment) * denominator - repayment * U256::from(fee_bps);
+        let balance_out = (reserve_out - amount_out) * denominator;
+        balance_in * balance_out >= reserve_in * reserve_out * denominator * denominator
+    }
+
+    #[test]
+    fn flash_swap_repayment_is_the_first_legs_amount_in() {
+        let (reserve_in, reserve_out) = (e18(1_000), U256::from(2_500_000_000_000_u64));
+        for input in [U256::exp10(15), e18(1), e18(37), e18(250)] {
+            let amount_out = get_amount_out(input, reserve_in, reserve_out, 30).unwrap();
+            let repayment = get_amount_in(amount_out, reserve_in, reserve_out, 30).unwrap();
+            // The least the pair accepts is `getAmountIn` of what it sent,
+            // and repaying the quoted input never falls short of it.
+            assert!(k_check_passes(repayment, amount_out, reserve_in, reserve_out, 30));
+            assert!(!k_check_passes(repayment - 1, amount_out, reserve_in, reserve_out, 30));
+            assert!(repayment <= input);
+        }
+    }
+}
@@ -0,0 +1,927 @@
+//! Integer ports of Uniswap V3 `FullMath`, `TickMath`, `SqrtPriceMath`,
+//! `SwapMath` and the `UniswapV3Pool.swap` step loop.
+//!
+//! Every rounding direction follows the Solidity sources so simulated swaps
+//! match `QuoterV2` to the wei, provided the pool state (slot0, active
+//! liquidity and the initialized ticks in the loaded bitmap words) is current.
+
+use anyhow::{Context, Result};
+use ethers::types::{U256, U512};
+use std::collections::BTreeMap;
+
+pub const MIN_TICK: i32 = -887_272;
+pub const MAX_TICK: i32 = 887_272;
+
+/// `TickMath` multipliers for bits 0..=19 of `|tick|`, as Q128.128.
+const TICK_RATIO_MULTIPLIERS: [u128; 20] = [
+    0xfffcb933bd6fad37aa2d162d1a594001,
+    0xfff97272373d413259a46990580e213a,
+    0xfff2e50f5f656932ef12357cf3c7fdcc,
+    0xffe5caca7e10e4e61c3624eaa0941cd0,
+    0xffcb9843d60f6159c9db58835c926644,
+    0xff973b41fa98c081472e6896dfb254c0,
+    0xff2ea16466c96a3843ec78b326b52861,
+    0xfe5dee046a99a2a811c461f1969c3053,
+    0xfcbe86c7900a88aedcffc83b479aa3a4,
+    0xf987a7253ac413176f2b074cf7815e54,
+    0xf3392b0822b70005940c7a398e4b70f3,
+    0xe7159475a2c29b7443b29c7fa6e889d9,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e5,
+    0x70d869a156d2a1b890bb3df62baf32f7,
+    0x31be135f97d08fd981231505542fcfa6,
+    0x9aa508b5b7a84e1c677de54f3e99bc9,
+    0x5d6af8dedb81196699c329225ee604,
+    0x2216e584f5fa1ea926041bedfe98,
+    0x48a170391f7dc42444e8fa2,
+];
+
+pub const FEE_DENOMINATOR: u32 = 1_000_000;
+
+pub fn q96() -> U256 {
+    U256::one() << 96
+}
+
+pub fn min_sqrt_ratio() -> U256 {
+    U256::from(4_295_128_739_u64)
+}
+
+pub fn max_sqrt_ratio() -> U256 {
+    U256([0x5d951d5263988d26, 0xefd1fc6a50648849, 0xfffd8963, 0])
+}
+
+fn max_uint160() -> U256 {
+    (U256::one() << 160) - 1
+}
+
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Result<U256> {
+    if denominator.is_zero() {
+        anyhow::bail!("mul_div by zero");
+    }
+    let result = a.full_mul(b) / U512::from(denominator);
+    U256::try_from(result).map_err(|_| anyhow::anyhow!("mul_div overflow"))
+}
+
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Result<U256> {
+    if denominator.is_zero() {
+        anyhow::bail!("mul_div_rounding_up by zero");
+    }
+    let product = a.full_mul(b);
+    let denominator = U512::from(denominator);
+    let quotient =
+        U256::try_from(product / denominator).map_err(|_| anyhow::anyhow!("mul_div_rounding_up overflow"))?;
+    if (product % denominator).is_zero() {
+        Ok(quotient)
+    } else {
+        quotient
+            .checked_add(U256::one())
+            .context("mul_div_rounding_up overflow")
+    }
+}
+
+fn div_rounding_up(a: U256, b: U256) -> U256 {
+    let quotient = a / b;
+    if (a % b).is_zero() {
+        quotient
+    } else {
+        quotient + 1
+    }
+}
+
+pub fn sqrt_ratio_at_tick(tick: i32) -> Result<U256> {
+    let abs_tick = tick.unsigned_abs();
+    if abs_tick > MAX_TICK as u32 {
+        anyhow::bail!("tick {tick} out of range");
+    }
+
+    let mut ratio = if abs_tick & 0x1 != 0 {
+        U256::from(TICK_RATIO_MULTIPLIERS[0])
+    } else {
+        U256::one() << 128
+    };
+    for (bit, multiplier) in TICK_RATIO_MULTIPLIERS.iter().enumerate().skip(1) {
+        if abs_tick & (1 << bit) != 0 {
+            ratio = (ratio * U256::from(*multiplier)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    let rounded_up = if (ratio & U256::from(u32::MAX)).is_zero() {
+        U256::zero()
+    } else {
+        U256::one()
+    };
+    Ok((ratio >> 32) + rounded_up)
+}
+
+/// Greatest tick whose sqrt ratio is at or below `sqrt_price_x96`, i.e. the
+/// same answer as `TickMath.getTickAtSqrtRatio`, found by binary search over
+/// `sqrt_ratio_at_tick`.
+pub fn tick_at_sqrt_ratio(sqrt_price_x96: U256) -> Result<i32> {
+    if sqrt_price_x96 < min_sqrt_ratio() || sqrt_price_x96 >= max_sqrt_ratio() {
+        anyhow::bail!("sqrt price {sqrt_price_x96} out of range");
+    }
+    let (mut low, mut high) = (MIN_TICK, MAX_TICK);
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if sqrt_ratio_at_tick(mid)? <= sqrt_price_x96 {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Ok(low)
+}
+
+pub fn amount0_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> Result<U256> {
+    let (lower, upper) = if sqrt_a > sqrt_b { (sqrt_b, sqrt_a) } else { (sqrt_a, sqrt_b) };
+    if lower.is_zero() {
+        anyhow::bail!("amount0_delta with zero sqrt price");
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let numerator2 = upper - lower;
+    if round_up {
+        Ok(div_rounding_up(mul_div_rounding_up(numerator1, numerator2, upper)?, lower))
+    } else {
+        Ok(mul_div(numerator1, numerator2, upper)? / lower)
+    }
+}
+
+pub fn amount1_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> Result<U256> {
+    let (lower, upper) = if sqrt_a > sqrt_b { (sqrt_b, sqrt_a) } else { (sqrt_a, sqrt_b) };
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), upper - lower, q96())
+    } else {
+        mul_div(U256::from(liquidity), upper - lower, q96())
+    }
+}
+
+fn next_sqrt_price_from_amount0_rounding_up(sqrt_price: U256, liquidity: u128, amount: U256, add: bool) -> Result<U256> {
+    if amount.is_zero() {
+        return Ok(sqrt_price);
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let (product, product_overflowed) = amount.overflowing_mul(sqrt_price);
+
+    if add {
+        if !product_overflowed {
+            let (denominator, sum_overflowed) = numerator1.overflowing_add(product);
+            if !sum_overflowed {
+                return mul_div_rounding_up(numerator1, sqrt_price, denominator);
+            }
+        }
+        let denominator = (numerator1 / sqrt_price)
+            .checked_add(amount)
+            .context("sqrt price denominator overflow")?;
+        Ok(div_rounding_up(numerator1, denominator))
+    } else {
+        if product_overflowed || numerator1 <= product {
+            anyhow::bail!("insufficient token0 liquidity for requested output");
+        }
+        let next = mul_div_rounding_up(numerator1, sqrt_price, numerator1 - product)?;
+        if next > max_uint160() {
+            anyhow::bail!("sqrt price overflow");
+        }
+        Ok(next)
+    }
+}
+
+fn next_sqrt_price_from_amount1_rounding_down(
+    sqrt_price: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256> {
+    if liquidity == 0 {
+        anyhow::bail!("zero liquidity");
+    }
+    let liquidity = U256::from(liquidity);
+    if add {
+        let quotient = if amount <= max_uint160() {
+            (amount << 96) / liquidity
+        } else {
+            mul_div(amount, q96(), liquidity)?
+        };
+        let next = sqrt_price.checked_add(quotient).context("sqrt price overflow")?;
+        if next > max_uint160() {
+            anyhow::bail!("sqrt price overflow");
+        }
+        Ok(next)
+    } else {
+        let quotient = if amount <= max_uint160() {
+            div_rounding_up(amount << 96, liquidity)
+        } else {
+            mul_div_rounding_up(amount, q96(), liquidity)?
+        };
+        if sqrt_price <= quotient {
+            anyhow::bail!("insufficient token1 liquidity for requested output");
+        }
+        Ok(sqrt_price - quotient)
+    }
+}
+
+fn next_sqrt_price_from_input(sqrt_price: U256, liquidity: u128, amount_in: U256, zero_for_one: bool) -> Result<U256> {
+    if zero_for_one {
+        next_sqrt_price_from_amount0_rounding_up(sqrt_price, liquidity, amount_in, true)
+    } else {
+        next_sqrt_price_from_amount1_rounding_down(sqrt_price, liquidity, amount_in, true)
+    }
+}
+
+fn next_sqrt_price_from_output(sqrt_price: U256, liquidity: u128, amount_out: U256, zero_for_one: bool) -> Result<U256> {
+    if zero_for_one {
+        next_sqrt_price_from_amount1_rounding_down(sqrt_price, liquidity, amount_out, false)
+    } else {
+        next_sqrt_price_from_amount0_rounding_up(sqrt_price, liquidity, amount_out, false)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapStep {
+    pub sqrt_price_next_x96: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+}
+
+/// `SwapMath.computeSwapStep`. `amount_remaining` is the unsigned magnitude;
+/// `exact_in` carries the sign the Solidity version encodes in `int256`.
+pub fn compute_swap_step(
+    sqrt_price_current: U256,
+    sqrt_price_target: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    exact_in: bool,
+    fee_pips: u32,
+) -> Result<SwapStep> {
+    let zero_for_one = sqrt_price_current >= sqrt_price_target;
+    let fee = U256::from(fee_pips);
+    let fee_complement = U256::from(FEE_DENOMINATOR - fee_pips);
+    let mut amount_in = U256::zero();
+    let mut amount_out = U256::zero();
+
+    let sqrt_price_next = if exact_in {
+        let remaining_less_fee = mul_div(amount_remaining, fee_complement, U256::from(FEE_DENOMINATOR))?;
+        amount_in = if zero_for_one {
+            amount0_delta(sqrt_price_target, sqrt_price_current, liquidity, true)?
+        } else {
+            amount1_delta(sqrt_price_current, sqrt_price_target, liquidity, true)?
+        };
+        if remaining_less_fee >= amount_in {
+            sqrt_price_target
+        } else {
+            next_sqrt_price_from_input(sqrt_price_current, liquidity, remaining_less_fee, zero_for_one)?
+        }
+    } else {
+        amount_out = if zero_for_one {
+            amount1_delta(sqrt_price_target, sqrt_price_current, liquidity, false)?
+        } else {
+            amount0_delta(sqrt_price_current, sqrt_price_target, liquidity, false)?
+        };
+        if amount_remaining >= amount_out {
+            sqrt_price_target
+        } else {
+            next_sqrt_price_from_output(sqrt_price_current, liquidity, amount_remaining, zero_for_one)?
+        }
+    };
+
+    let reached_target = sqrt_price_target == sqrt_price_next;
+    let keep_amount_in = reached_target && exact_in;
+    let keep_amount_out = reached_target && !exact_in;
+    if zero_for_one {
+        if !keep_amount_in {
+            amount_in = amount0_delta(sqrt_price_next, sqrt_price_current, liquidity, true)?;
+        }
+        if !keep_amount_out {
+            amount_out = amount1_delta(sqrt_price_next, sqrt_price_current, liquidity, false)?;
+        }
+    } else {
+        if !keep_amount_in {
+            amount_in = amount1_delta(sqrt_price_current, sqrt_price_next, liquidity, true)?;
+        }
+        if !keep_amount_out {
+            amount_out = amount0_delta(sqrt_price_current, sqrt_price_next, liquidity, false)?;
+        }
+    }
+
+    if !exact_in && amount_out > amount_remaining {
+        amount_out = amount_remaining;
+    }
+
+    let fee_amount = if exact_in && sqrt_price_next != sqrt_price_target {
+        amount_remaining - amount_in
+    } else {
+        mul_div_rounding_up(amount_in, fee, fee_complement)?
+    };
+
+    Ok(SwapStep {
+        sqrt_price_next_x96: sqrt_price_next,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+fn add_liquidity_delta(liquidity: u128, delta: i128) -> Result<u128> {
+    if delta < 0 {
+        liquidity
+            .checked_sub(delta.unsigned_abs())
+            .context("liquidity underflow")
+    } else {
+        liquidity
+            .checked_add(delta as u128)
+            .context("liquidity overflow")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TickInfo {
+    pub liquidity_gross: u128,
+    pub liquidity_net: i128,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct V3SwapResult {
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub sqrt_price_x96_after: U256,
+    pub tick_after: i32,
+    pub liquidity_after: u128,
+    pub initialized_ticks_crossed: u32,
+}
+
+/// Local copy of the pool state `swap` reads: slot0, active liquidity and
+/// the initialized ticks inside a window of tick bitmap words.
+///
+/// Swaps that would need a bitmap word outside `word_range` fail instead of
+/// silently treating unknown ticks as uninitialized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct V3PoolState {
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    pub liquidity: u128,
+    pub fee_pips: u32,
+    pub tick_spacing: i32,
+    pub ticks: BTreeMap<i32, TickInfo>,
+    pub word_range: (i16, i16),
+}
+
+/// Tick divided by spacing, rounded towards negative infinity.
+pub fn compress_tick(tick: i32, tick_spacing: i32) -> i32 {
+    let compressed = tick / tick_spacing;
+    if tick < 0 && tick % tick_spacing != 0 {
+        compressed - 1
+    } else {
+        compressed
+    }
+}
+
+/// `(wordPos, bitPos)` for a compressed tick, as in `TickBitmap.position`.
+pub fn bitmap_position(compressed: i32) -> (i16, u8) {
+    ((compressed >> 8) as i16, (compressed & 0xff) as u8)
+}
+
+impl V3PoolState {
+    fn require_word(&self, word: i16) -> Result<()> {
+        if word < self.word_range.0 || word > self.word_range.1 {
+            anyhow::bail!(
+                "tick bitmap word {word} outside loaded range {}..={}",
+                self.word_range.0,
+                self.word_range.1
+            );
+        }
+        Ok(())
+    }
+
+    /// `TickBitmap.nextInitializedTickWithinOneWord` over the loaded ticks.
+    fn next_initialized_tick_within_one_word(&self, tick: i32, lte: bool) -> Result<(i32, bool)> {
+        let spacing = self.tick_spacing;
+        let compressed = compress_tick(tick, spacing);
+        if lte {
+            let (word, bit) = bitmap_position(compressed);
+            self.require_word(word)?;
+            let word_start = compressed - i32::from(bit);
+            let found = self
+                .ticks
+                .range(word_start * spacing..=compressed * spacing)
+                .next_back()
+                .map(|(tick, _)| *tick);
+            Ok(match found {
+                Some(next) => (next, true),
+                None => (word_start * spacing, false),
+            })
+        } else {
+            let next_compressed = compressed + 1;
+            let (word, bit) = bitmap_position(next_compressed);
+            self.require_word(word)?;
+            let word_end = next_compressed + (255 - i32::from(bit));
+            let found = self
+                .ticks
+                .range(next_compressed * spacing..=word_end * spacing)
+                .next()
+                .map(|(tick, _)| *tick);
+            Ok(match found {
+                Some(next) => (next, true),
+                None => (word_end * spacing, false),
+            })
+        }
+    }
+
+    /// Sets slot0 and active liquidity from a `Swap` event, which reports the
+    /// post-swap values directly.
+    pub fn apply_swap(&mut self, sqrt_price_x96: U256, liquidity: u128, tick: i32) {
+        self.sqrt_price_x96 = sqrt_price_x96;
+        self.liquidity = liquidity;
+        self.tick = tick;
+    }
+
+    /// Applies a `Mint` (positive delta) or `Burn` (negative delta) to the
+    /// boundary ticks and, if the position spans the current tick, to active
+    /// liquidity. Ticks outside the loaded bitmap words are not tracked.
+    pub fn apply_liquidity_delta(&mut self, tick_lower: i32, tick_upper: i32, delta: i128) -> Result<()> {
+        if delta == 0 {
+            return Ok(());
+        }
+        if tick_lower >= tick_upper {
+            anyhow::bail!("invalid position range {tick_lower}..{tick_upper}");
+        }
+        self.update_tick(tick_lower, delta, false)?;
+        self.update_tick(tick_upper, delta, true)?;
+        if self.tick >= tick_lower && self.tick < tick_upper {
+            self.liquidity = add_liquidity_delta(self.liquidity, delta)?;
+        }
+        Ok(())
+    }
+
+    fn update_tick(&mut self, tick: i32, delta: i128, upper: bool) -> Result<()> {
+        if !self.tracks_tick(tick) {
+            return Ok(());
+        }
+        let info = self.ticks.entry(tick).or_default();
+        info.liquidity_gross = add_liquidity_delta(info.liquidity_gross, delta)?;
+        info.liquidity_net = if upper {
+            info.liquidity_net.checked_sub(delta)
+        } else {
+            info.liquidity_net.checked_add(delta)
+        }
+        .context("liquidity_net overflow")?;
+        if info.liquidity_gross == 0 {
+            self.ticks.remove(&tick);
+        }
+        Ok(())
+    }
+
+    pub fn tracks_tick(&self, tick: i32) -> bool {
+        let (word, _) = bitmap_position(compress_tick(tick, self.tick_spacing));
+        word >= self.word_range.0 && word <= self.word_range.1
+    }
+
+    /// Whether the current tick sits in the outermost loaded bitmap word, so
+    /// the next swap may need words we do not have.
+    pub fn near_window_edge(&self) -> bool {
+        let (word, _) = bitmap_position(compress_tick(self.tick, self.tick_spacing));
+        word <= self.word_range.0 || word >= self.word_range.1
+    }
+
+    pub fn exact_input(&self, zero_for_one: bool, amount_in: U256) -> Result<V3SwapResult> {
+        self.swap(zero_for_one, amount_in, true, None)
+    }
+
+    /// Mirrors `QuoterV2.quoteExactOutputSingle` without a price limit: the
+    /// full output must be reachable or the quote fails.
+    pub fn exact_output(&self, zero_for_one: bool, amount_out: U256) -> Result<V3SwapResult> {
+        let result = self.swap(zero_for_one, amount_out, false, None)?;
+        if result.amount_out != amount_out {
+            anyhow::bail!(
+                "insufficient liquidity for exact output: wanted {amount_out}, reachable {}",
+                result.amount_out
+            );
+        }
+        Ok(result)
+    }
+
+    /// The `UniswapV3Pool.swap` step loop without state writes. Protocol
+    /// fees only affect fee growth, so they do not change amounts.
+    pub fn swap(
+        &self,
+        zero_for_one: bool,
+        amount: U256,
+        exact_in: bool,
+        sqrt_price_limit_x96: Option<U256>,
+    ) -> Result<V3SwapResult> {
+        if amount.is_zero() {
+            anyhow::bail!("swap amount is zero");
+        }
+        let limit = sqrt_price_limit_x96.unwrap_or_else(|| {
+            if zero_for_one {
+                min_sqrt_ratio() + 1
+            } else {
+                max_sqrt_ratio() - 1
+            }
+        });
+        if zero_for_one {
+            if limit >= self.sqrt_price_x96 || limit <= min_sqrt_ratio() {
+                anyhow::bail!("invalid sqrt price limit");
+            }
+        } else if limit <= self.sqrt_price_x96 || limit >= max_sqrt_ratio() {
+            anyhow::bail!("invalid sqrt price limit");
+        }
+
+        let mut remaining = amount;
+        let mut amount_in_total = U256::zero();
+        let mut amount_out_total = U256::zero();
+        let mut sqrt_price = self.sqrt_price_x96;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+        let mut initialized_ticks_crossed = 0_u32;
+
+        while !remaining.is_zero() && sqrt_price != limit {
+            let sqrt_price_start = sqrt_price;
+            let (tick_next, initialized) = self.next_initialized_tick_within_one_word(tick, zero_for_one)?;
+            let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+            let sqrt_price_next_tick = sqrt_ratio_at_tick(tick_next)?;
+            let target = if zero_for_one {
+                sqrt_price_next_tick.max(limit)
+            } else {
+                sqrt_price_next_tick.min(limit)
+            };
+
+            let step = compute_swap_step(sqrt_price, target, liquidity, remaining, exact_in, self.fee_pips)?;
+            sqrt_price = step.sqrt_price_next_x96;
+            let step_in = step.amount_in + step.fee_amount;
+            if exact_in {
+                remaining = remaining.checked_sub(step_in).context("swap input underflow")?;
+            } else {
+                remaining = remaining
+                    .checked_sub(step.amount_out)
+                    .context("swap output underflow")?;
+            }
+            amount_in_total = amount_in_total.checked_add(step_in).context("swap input overflow")?;
+            amount_out_total = amount_out_total
+                .checked_add(step.amount_out)
+                .context("swap output overflow")?;
+
+            if sqrt_price == sqrt_price_next_tick {
+                if initialized {
+                    let mut liquidity_net = self
+                        .ticks
+                        .get(&tick_next)
+                        .map(|info| info.liquidity_net)
+                        .unwrap_or(0);
+                    if zero_for_one {
+                        liquidity_net = -liquidity_net;
+                    }
+                    liquidity = add_liquidity_delta(liquidity, liquidity_net)?;
+                    initialized_ticks_crossed = initialized_ticks_crossed.saturating_add(1);
+                }
+                tick = if zero_for_one { tick_next - 1 } else { tick_next };
+            } else if sqrt_price != sqrt_price_start {
+                tick = tick_at_sqrt_ratio(sqrt_price)?;
+            }
+        }
+
+        Ok(V3SwapResult {
+            amount_in: amount_in_total,
+            amount_out: amount_out_total,
+            sqrt_price_x96_after: sqrt_price,
+            tick_after: tick,
+            liquidity_after: liquidity,
+            initialized_ticks_crossed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `encodePriceSqrt` from the v3-core test utilities.
+    fn encode_price_sqrt(reserve1: u64, reserve0: u64) -> U256 {
+        ((U256::from(reserve1) << 192) / U256::from(reserve0)).integer_sqrt()
+    }
+
+    fn expand_to_18_decimals(n: u64) -> U256 {
+        U256::from(n) * U256::exp10(18)
+    }
+
+    fn u(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    const E18: u128 = 1_000_000_000_000_000_000;
+
+    // Single-step vectors below are from the v3-core test suite:
+    // `TickMath.spec.ts`, `SqrtPriceMath.spec.ts` and `SwapMath.spec.ts`.
+
+    #[test]
+    fn sqrt_ratio_at_tick_matches_tick_math() {
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK).unwrap(), u("4295128739"));
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK + 1).unwrap(), u("4295343490"));
+        assert_eq!(sqrt_ratio_at_tick(0).unwrap(), q96());
+        assert_eq!(
+            sqrt_ratio_at_tick(MAX_TICK - 1).unwrap(),
+            u("1461373636630004318706518188784493106690254656249")
+        );
+        assert_eq!(
+            sqrt_ratio_at_tick(MAX_TICK).unwrap(),
+            u("1461446703485210103287273052203988822378723970342")
+        );
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK).unwrap(), min_sqrt_ratio());
+        assert_eq!(sqrt_ratio_at_tick(MAX_TICK).unwrap(), max_sqrt_ratio());
+        assert!(sqrt_ratio_at_tick(MIN_TICK - 1).is_err());
+        assert!(sqrt_ratio_at_tick(MAX_TICK + 1).is_err());
+    }
+
+    #[test]
+    fn tick_at_sqrt_ratio_matches_tick_math() {
+        assert_eq!(tick_at_sqrt_ratio(min_sqrt_ratio()).unwrap(), MIN_TICK);
+        assert_eq!(tick_at_sqrt_ratio(u("4295343490")).unwrap(), MIN_TICK + 1);
+        assert_eq!(
+            tick_at_sqrt_ratio(u("1461373636630004318706518188784493106690254656249")).unwrap(),
+            MAX_TICK - 1
+        );
+        assert_eq!(tick_at_sqrt_ratio(max_sqrt_ratio() - 1).unwrap(), MAX_TICK - 1);
+        assert!(tick_at_sqrt_ratio(min_sqrt_ratio() - 1).is_err());
+        assert!(tick_at_sqrt_ratio(max_sqrt_ratio()).is_err());
+    }
+
+    #[test]
+    fn tick_at_sqrt_ratio_round_trips() {
+        for tick in [MIN_TICK + 1, -600_000, -50_000, -887, -60, -1, 0, 1, 60, 887, 50_000, 600_000, MAX_TICK - 1] {
+            let sqrt_ratio = sqrt_ratio_at_tick(tick).unwrap();
+            assert_eq!(tick_at_sqrt_ratio(sqrt_ratio).unwrap(), tick);
+            assert_eq!(tick_at_sqrt_ratio(sqrt_ratio - 1).unwrap(), tick - 1);
+            assert_eq!(tick_at_sqrt_ratio(sqrt_ratio + 1).unwrap(), tick);
+        }
+    }
+
+    #[test]
+    fn swap_step_exact_in_capped_at_price_target() {
+        let price = encode_price_sqrt(1, 1);
+        let target = encode_price_sqrt(101, 100);
+        let step = compute_swap_step(price, target, 2_000_000_000_000_000_000, expand_to_18_decimals(1), true, 600)
+            .unwrap();
+        assert_eq!(step.amount_in, u("9975124224178055"));
+        assert_eq!(step.fee_amount, u("5988667735148"));
+        assert_eq!(step.amount_out, u("9925619580021728"));
+        assert_eq!(step.sqrt_price_next_x96, target);
+    }
+
+    #[test]
+    fn swap_step_exact_out_capped_at_price_target() {
+        let price = encode_price_sqrt(1, 1);
+        let target = encode_price_sqrt(101, 100);
+        let step = compute_swap_step(price, target, 2_000_000_000_000_000_000, expand_to_18_decimals(1), false, 600)
+            .unwrap();
+        assert_eq!(step.amount_in, u("9975124224178055"));
+        assert_eq!(step.fee_amount, u("5988667735148"));
+        assert_eq!(step.amount_out, u("9925619580021728"));
+        assert_eq!(step.sqrt_price_next_x96, target);
+    }
+
+    #[test]
+    fn swap_step_exact_in_fully_spent() {
+        let price = encode_price_sqrt(1, 1);
+        let target = encode_price_sqrt(1000, 100);
+        let liquidity = 2_000_000_000_000_000_000;
+        let amount = expand_to_18_decimals(1);
+        let step = compute_swap_step(price, target, liquidity, amount, true, 600).unwrap();
+        assert_eq!(step.amount_in, u("999400000000000000"));
+        assert_eq!(step.fee_amount, u("600000000000000"));
+        assert_eq!(step.amount_out, u("666399946655997866"));
+        assert_eq!(step.amount_in + step.fee_amount, amount);
+        assert!(step.sqrt_price_next_x96 < target);
+        assert_eq!(
+            step.sqrt_price_next_x96,
+            next_sqrt_price_from_input(price, liquidity, amount - step.fee_amount, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn swap_step_exact_out_fully_received() {
+        let price = encode_price_sqrt(1, 1);
+        let target = encode_price_sqrt(10000, 100);
+        let liquidity = 2_000_000_000_000_000_000;
+        let amount = expand_to_18_decimals(1);
+        let step = compute_swap_step(price, target, liquidity, amount, false, 600).unwrap();
+        assert_eq!(step.amount_in, u("2000000000000000000"));
+        assert_eq!(step.fee_amount, u("1200720432259356"));
+        assert_eq!(step.amount_out, amount);
+        assert!(step.sqrt_price_next_x96 < target);
+        assert_eq!(
+            step.sqrt_price_next_x96,
+            next_sqrt_price_from_output(price, liquidity, amount, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn swap_step_amount_out_capped_at_desired_amount() {
+        let step = compute_swap_step(
+            u("417332158212080721273783715441582"),
+            u("1452870262520218020823638996"),
+            159_344_665_391_607_089_467_575_320_103,
+            U256::one(),
+            false,
+            1,
+        )
+        .unwrap();
+        assert_eq!(step.amount_in, U256::one());
+        assert_eq!(step.fee_amount, U256::one());
+        assert_eq!(step.amount_out, U256::one());
+        assert_eq!(step.sqrt_price_next_x96, u("417332158212080721273783715441581"));
+    }
+
+    #[test]
+    fn swap_step_target_price_of_one_uses_partial_input() {
+        let step = compute_swap_step(
+            U256::from(2),
+            U256::one(),
+            1,
+            u("3915081100057732413702495386755767"),
+            true,
+            1,
+        )
+        .unwrap();
+        assert_eq!(step.amount_in, u("39614081257132168796771975168"));
+        assert_eq!(step.fee_amount, u("39614120871253040049813"));
+        assert!(step.amount_in + step.fee_amount <= u("3915081100057732413702495386755767"));
+        assert_eq!(step.amount_out, U256::zero());
+        assert_eq!(step.sqrt_price_next_x96, U256::one());
+    }
+
+    #[test]
+    fn swap_step_entire_input_taken_as_fee() {
+        let step = compute_swap_step(
+            U256::from(2413),
+            u("79887613182836312"),
+            1_985_041_575_832_132_834_610_021_537_970,
+            U256::from(10),
+            true,
+            1872,
+        )
+        .unwrap();
+        assert_eq!(step.amount_in, U256::zero());
+        assert_eq!(step.fee_amount, U256::from(10));
+        assert_eq!(step.amount_out, U256::zero());
+        assert_eq!(step.sqrt_price_next_x96, U256::from(2413));
+    }
+
+    #[test]
+    fn swap_step_intermediate_insufficient_liquidity_exact_out() {
+        let sqrt_price = u("20282409603651670423947251286016");
+
+        let target = sqrt_price * 11 / 10;
+        let step = compute_swap_step(sqrt_price, target, 1024, U256::from(4), false, 3000).unwrap();
+        assert_eq!(step.amount_out, U256::zero());
+        assert_eq!(step.sqrt_price_next_x96, target);
+        assert_eq!(step.amount_in, U256::from(26215));
+        assert_eq!(step.fee_amount, U256::from(79));
+
+        let target = sqrt_price * 9 / 10;
+        let step = compute_swap_step(sqrt_price, target, 1024, U256::from(263_000), false, 3000).unwrap();
+        assert_eq!(step.amount_out, U256::from(26214));
+        assert_eq!(step.sqrt_price_next_x96, target);
+        assert_eq!(step.amount_in, U256::one());
+        assert_eq!(step.fee_amount, U256::one());
+    }
+
+    #[test]
+    fn next_sqrt_price_from_input_matches_sqrt_price_math() {
+        let price = encode_price_sqrt(1, 1);
+        let liquidity = 1_000_000_000_000_000_000;
+        let amount = expand_to_18_decimals(1) / 10;
+        assert_eq!(
+            next_sqrt_price_from_input(price, liquidity, amount, false).unwrap(),
+            u("87150978765690771352898345369")
+        );
+        assert_eq!(
+            next_sqrt_price_from_input(price, liquidity, amount, true).unwrap(),
+            u("72025602285694852357767227579")
+        );
+    }
+
+    #[test]
+    fn amount_deltas_match_sqrt_price_math() {
+        let price = encode_price_sqrt(1, 1);
+        let next = encode_price_sqrt(121, 100);
+        let liquidity = 1_000_000_000_000_000_000;
+        assert_eq!(amount0_delta(price, next, liquidity, true).unwrap(), u("90909090909090910"));
+        assert_eq!(amount0_delta(price, next, liquidity, false).unwrap(), u("90909090909090909"));
+        assert_eq!(amount1_delta(price, next, liquidity, true).unwrap(), u("100000000000000000"));
+        assert_eq!(amount1_delta(price, next, liquidity, false).unwrap(), u("99999999999999999"));
+    }
+
+    /// Price 1 at tick 0, spacing 60 and a 0.3% fee, with positions either
+    /// side so swaps cross several initialized ticks.
+    fn multi_tick_pool() -> V3PoolState {
+        let mut state = V3PoolState {
+            sqrt_price_x96: encode_price_sqrt(1, 1),
+            tick: 0,
+            liquidity: 0,
+            fee_pips: 3000,
+            tick_spacing: 60,
+            ticks: BTreeMap::new(),
+            word_range: (-2, 1),
+        };
+        for (lower, upper, liquidity) in [
+            (-600, 600, 2_000_000_000_000_000_000_i128),
+            (-120, 120, 1_000_000_000_000_000_000),
+            (60, 240, 500_000_000_000_000_000),
+            (-1200, -300, 4_000_000_000_000_000_000),
+        ] {
+            state.apply_liquidity_delta(lower, upper, liquidity).unwrap();
+        }
+        assert_eq!(state.liquidity, 3_000_000_000_000_000_000);
+        state
+    }
+
+    /// Expected result of a multi-tick swap, built without the bitmap: one
+    /// `compute_swap_step` per stretch of constant liquidity up to each tick
+    /// in `crossings` (with the liquidity in force before it), then a last
+    /// step at `final_liquidity`. The step and tick math are pinned to the
+    /// v3-core spec vectors above, so this checks the tick walk and the
+    /// liquidity carried across each crossing.
+    fn walk_ticks(
+        state: &V3PoolState,
+        crossings: &[(i32, u128)],
+        final_liquidity: u128,
+        amount: U256,
+        zero_for_one: bool,
+        exact_in: bool,
+    ) -> V3SwapResult {
+        let mut sqrt_price = state.sqrt_price_x96;
+        let mut remaining = amount;
+        let mut amount_in = U256::zero();
+        let mut amount_out = U256::zero();
+        let last_target = if zero_for_one { min_sqrt_ratio() + 1 } else { max_sqrt_ratio() - 1 };
+        let mut targets = crossings
+            .iter()
+            .map(|&(tick, liquidity)| (sqrt_ratio_at_tick(tick).unwrap(), liquidity))
+            .collect::<Vec<_>>();
+        targets.push((last_target, final_liquidity));
+        for (index, (target, liquidity)) in targets.into_iter().enumerate() {
+            let step = compute_swap_step(sqrt_price, target, liquidity, remaining, exact_in, state.fee_pips).unwrap();
+            if index < crossings.len() {
+                assert_eq!(step.sqrt_price_next_x96, target, "swap must cross tick {}", crossings[index].0);
+            }
+            sqrt_price = step.sqrt_price_next_x96;
+            amount_in += step.amount_in + step.fee_amount;
+            amount_out += step.amount_out;
+            remaining -= if exact_in { step.amount_in + step.fee_amount } else { step.amount_out };
+        }
+        assert!(remaining.is_zero());
+        V3SwapResult {
+            amount_in,
+            amount_out,
+            sqrt_price_x96_after: sqrt_price,
+            tick_after: tick_at_sqrt_ratio(sqrt_price).unwrap(),
+            liquidity_after: final_liquidity,
+            initialized_ticks_crossed: crossings.len() as u32,
+        }
+    }
+
+    #[test]
+    fn exact_input_crosses_initialized_ticks() {
+        let state = multi_tick_pool();
+
+        let amount = u("100000000000000000");
+        let result = state.exact_input(true, amount).unwrap();
+        let expected = walk_ticks(&state, &[(-120, 3 * E18), (-300, 2 * E18)], 6 * E18, amount, true, true);
+        assert_eq!(result, expected);
+        assert_eq!(result.amount_in, amount);
+
+        let amount = u("50000000000000000");
+        let result = state.exact_input(false, amount).unwrap();
+        let crossings = [(60, 3 * E18), (120, 7 * E18 / 2), (240, 5 * E18 / 2)];
+        let expected = walk_ticks(&state, &crossings, 2 * E18, amount, false, true);
+        assert_eq!(result, expected);
+        assert_eq!(result.amount_in, amount);
+    }
+
+    #[test]
+    fn exact_output_crosses_initialized_ticks() {
+        let state = multi_tick_pool();
+
+        let amount = u("60000000000000000");
+        let result = state.exact_output(true, amount).unwrap();
+        let expected = walk_ticks(&state, &[(-120, 3 * E18), (-300, 2 * E18)], 6 * E18, amount, true, false);
+        assert_eq!(result, expected);
+        assert_eq!(result.amount_out, amount);
+
+        let amount = u("50000000000000000");
+        let result = state.exact_output(false, amount).unwrap();
+        let crossings = [(60, 3 * E18), (120, 7 * E18 / 2), (240, 5 * E18 / 2)];
+        let expected = walk_ticks(&state, &crossings, 2 * E18, amount, false, false);
+        assert_eq!(result, expected);
+        assert_eq!(result.amount_out, amount);
+    }
+
+    #[test]
+    fn swap_past_loaded_words_fails() {
+        let mut state = multi_tick_pool();
+        state.word_range = (0, 0);
+        assert!(state.exact_input(true, u("100000000000000000")).is_err());
+    }
+}
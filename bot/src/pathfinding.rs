@@ -0,0 +1,213 @@
+//! Cycle search over the pools a run tracks. Tokens are nodes and every pool
+//! contributes one directed edge per ordered token pair, weighted by
+//! `-ln(marginal rate net of fee)`, so a cycle whose weights sum below zero
+//! returns more than it takes at the margin. Weights only rank candidates;
+//! whether a cycle is worth trading is decided from full integer quotes.
+
+use crate::venues::PoolAdapter;
+use ethers::types::Address;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// One direction through one pool. `pool` indexes the adapter slice the
+/// graph was built from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hop {
+    pub pool: usize,
+    pub token_in: Address,
+    pub token_out: Address,
+}
+
+/// A closed path starting and ending in its first hop's `token_in`.
+#[derive(Clone, Debug)]
+pub struct Cycle {
+    pub hops: Vec<Hop>,
+    /// Sum of the hops' edge weights; negative for a marginally profitable
+    /// cycle.
+    pub weight: f64,
+}
+
+impl Cycle {
+    pub fn start_token(&self) -> Option<Address> {
+        self.hops.first().map(|hop| hop.token_in)
+    }
+
+    /// Product of the hops' marginal rates.
+    pub fn marginal_rate(&self) -> f64 {
+        (-self.weight).exp()
+    }
+
+    /// What an infinitesimal trade around the cycle gains, in basis points.
+    pub fn marginal_edge_bps(&self) -> f64 {
+        (self.marginal_rate() - 1.0) * 10_000.0
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Edge {
+    hop: Hop,
+    weight: f64,
+}
+
+/// Directed token graph for one block's pool state.
+#[derive(Clone, Debug, Default)]
+pub struct TokenGraph {
+    edges: Vec<Edge>,
+    outgoing: BTreeMap<Address, Vec<usize>>,
+}
+
+impl TokenGraph {
+    /// Adds an edge for every ordered token pair of every pool. Pools that
+    /// cannot report a marginal rate (state not loaded, empty reserves) are
+    /// left out until they can.
+    pub fn build(pools: &[Box<dyn PoolAdapter>]) -> Self {
+        let mut graph = Self::default();
+        for (index, pool) in pools.iter().enumerate() {
+            for &token_in in pool.tokens() {
+                for &token_out in pool.tokens().iter().filter(|token| **token != token_in) {
+                    let Ok(rate) = pool.marginal_rate(token_in, token_out) else {
+                        continue;
+                    };
+                    let rate = rate.to_f64();
+                    if !rate.is_finite() || rate <= 0.0 {
+                        continue;
+                    }
+                    graph.outgoing.entry(token_in).or_default().push(graph.edges.len());
+                    graph.edges.push(Edge {
+                        hop: Hop {
+                            pool: index,
+                            token_in,
+                            token_out,
+                        },
+                        weight: -rate.ln(),
+                    });
+                }
+            }
+        }
+        graph
+    }
+
+    /// Every marginally profitable cycle of at most `max_hops` hops from
+    /// `start` back to it, best first, by bounded depth-first search. A
+    /// cycle trades through each pool at most once and visits no token
+    /// twice other than `start`.
+    pub fn find_cycles(&self, start: Address, max_hops: usize) -> Vec<Cycle> {
+        let mut search = CycleSearch {
+            graph: self,
+            start,
+            max_hops,
+            path: Vec::with_capacity(max_hops),
+            visited: BTreeSet::from([start]),
+            cycles: Vec::new(),
+        };
+        search.extend(start, 0.0);
+        let mut cycles = search.cycles;
+        cycles.sort_by(|a, b| a.weight.total_cmp(&b.weight));
+        cycles
+    }
+}
+
+struct CycleSearch<'a> {
+    graph: &'a TokenGraph,
+    start: Address,
+    max_hops: usize,
+    path: Vec<Hop>,
+    visited: BTreeSet<Address>,
+    cycles: Vec<Cycle>,
+}
+
+impl CycleSearch<'_> {
+    fn extend(&mut self, token: Address, weight: f64) {
+        let Some(outgoing) = self.graph.outgoing.get(&token) else {
+            return;
+        };
+        for &edge_index in outgoing {
+            let edge = &self.graph.edges[edge_index];
+            if self.path.iter().any(|hop| hop.pool == edge.hop.pool) {
+                continue;
+            }
+            let next_weight = weight + edge.weight;
+            self.path.push(edge.hop);
+            if edge.hop.token_out == self.start {
+                if next_weight < 0.0 {
+                    self.cycles.push(Cycle {
+                        hops: self.path.clone(),
+                        weight: next_weight,
+                    });
+                }
+            } else if self.path.len() < self.max_hops && self.visited.insert(edge.hop.token_out) {
+                self.extend(edge.hop.token_out, next_weight);
+                self.visited.remove(&edge.hop.token_out);
+            }
+            self.path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    /// A graph from `(pool, token_in, token_out, marginal rate)` edges.
+    fn graph(edges: &[(usize, u64, u64, f64)]) -> TokenGraph {
+        let mut graph = TokenGraph::default();
+        for &(pool, token_in, token_out, rate) in edges {
+            graph.outgoing.entry(token(token_in)).or_default().push(graph.edges.len());
+            graph.edges.push(Edge {
+                hop: Hop {
+                    pool,
+                    token_in: token(token_in),
+                    token_out: token(token_out),
+                },
+                weight: -f64::ln(rate),
+            });
+        }
+        graph
+    }
+
+    fn pools(cycle: &Cycle) -> Vec<usize> {
+        cycle.hops.iter().map(|hop| hop.pool).collect()
+    }
+
+    #[test]
+    fn cycles_do_not_reuse_a_pool() {
+        // Round-tripping pool 0 alone would look profitable.
+        let graph = graph(&[(0, 1, 2, 1.1), (0, 2, 1, 1.1), (1, 2, 1, 1.0)]);
+        let cycles = graph.find_cycles(token(1), 4);
+        assert_eq!(cycles.iter().map(pools).collect::<Vec<_>>(), vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn cycles_do_not_revisit_a_token() {
+        // 1 -> 2 -> 3 -> 2 -> 1 is only profitable through the 2/3 loop.
+        let graph = graph(&[(0, 1, 2, 1.0), (1, 2, 3, 1.5), (2, 3, 2, 1.5), (3, 2, 1, 0.9)]);
+        assert!(graph.find_cycles(token(1), 4).is_empty());
+    }
+
+    #[test]
+    fn cycles_stop_at_max_hops() {
+        let graph = graph(&[(0, 1, 2, 1.01), (1, 2, 3, 1.01), (2, 3, 1, 1.01)]);
+        assert!(graph.find_cycles(token(1), 2).is_empty());
+        let cycles = graph.find_cycles(token(1), 3);
+        assert_eq!(cycles.iter().map(pools).collect::<Vec<_>>(), vec![vec![0, 1, 2]]);
+        assert_eq!(cycles[0].start_token(), Some(token(1)));
+    }
+
+    #[test]
+    fn only_negative_weight_cycles_are_returned_best_first() {
+        let graph = graph(&[
+            (0, 1, 2, 1.0),
+            (1, 2, 1, 1.002),
+            (2, 2, 1, 0.998),
+            (3, 2, 1, 1.005),
+            (4, 2, 1, 1.0),
+        ]);
+        let cycles = graph.find_cycles(token(1), 2);
+        assert_eq!(cycles.iter().map(pools).collect::<Vec<_>>(), vec![vec![0, 3], vec![0, 1]]);
+        assert!(cycles.iter().all(|cycle| cycle.weight < 0.0));
+        assert!((cycles[0].marginal_edge_bps() - 50.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,20 @@
+//! JSON state files the binaries rewrite between runs: backfill
+//! checkpoints, the pool registry and the token metadata cache.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// Writes `value` as pretty JSON through a temp file and rename so an
+/// interrupted write never leaves a truncated file behind. `what` names the
+/// file in errors.
+pub fn write_json_atomic<T: Serialize>(path: &Path, value: &T, what: &str) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).with_context(|| format!("failed creating {}", parent.display()))?;
+    }
+    let tmp = path.with_extension("tmp");
+    let json = serde_json::to_string_pretty(value).with_context(|| format!("failed to serialize {what}"))?;
+    fs::write(&tmp, json).with_context(|| format!("failed writing {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed replacing {}", path.display()))
+}
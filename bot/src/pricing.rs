@@ -0,0 +1,202 @@
+//! Converts gas, which is paid in the native token, into a loan token's
+//! units so it can be netted against a cycle's output. The rate comes from
+//! a reference pool's marginal price or a Chainlink feed, re-read every
+//! block.
+
+use crate::calls::{calldata, eth_call, token_as_uint};
+use crate::math::price::Price;
+use crate::venues::{PoolAdapter, block_id};
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, I256, U256, U512};
+
+/// Decimals of the native token gas is paid in.
+pub const NATIVE_DECIMALS: u8 = 18;
+
+/// Loan-token wei per native wei, and where it came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasRate {
+    pub price: Price,
+    /// `native` when the loan token is the native token itself,
+    /// `reference_pool` or `chainlink`.
+    pub source: &'static str,
+    pub address: Option<Address>,
+}
+
+impl GasRate {
+    /// The loan token is the (wrapped) native token.
+    pub fn native() -> Self {
+        Self {
+            price: Price::new(U512::one(), U512::one()).expect("one is a valid price"),
+            source: "native",
+            address: None,
+        }
+    }
+
+    /// `native_wei` in loan-token wei, rounded up so gas is never
+    /// under-counted.
+    pub fn convert(&self, native_wei: U256) -> U256 {
+        let product = U512::from(native_wei).saturating_mul(self.price.numerator());
+        let denominator = self.price.denominator();
+        let quotient = product / denominator;
+        let quotient = if (product % denominator).is_zero() {
+            quotient
+        } else {
+            quotient + 1
+        };
+        U256::try_from(quotient).unwrap_or(U256::MAX)
+    }
+
+    /// Loan-token wei per whole native token, for logs.
+    pub fn per_native_token(&self) -> U256 {
+        let product = U512::from(U256::exp10(usize::from(NATIVE_DECIMALS))).saturating_mul(self.price.numerator());
+        U256::try_from(product / self.price.denominator()).unwrap_or(U256::MAX)
+    }
+}
+
+/// What it costs in `token` to buy native wei on `pool` at its marginal
+/// rate, swap fee included, from the pool's local state.
+pub fn pool_rate(pool: &dyn PoolAdapter, native: Address, token: Address) -> Result<GasRate> {
+    let price = pool
+        .marginal_rate(token, native)?
+        .inverse()
+        .with_context(|| format!("zero marginal rate on reference pool {:#x}", pool.pool()))?;
+    Ok(GasRate {
+        price,
+        source: "reference_pool",
+        address: Some(pool.pool()),
+    })
+}
+
+/// A Chainlink aggregator pricing the native token in a loan token (e.g.
+/// ETH/USD for USDC), or with `invert` the loan token in the native token
+/// (e.g. BTC/ETH for cbBTC).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainlinkFeed {
+    pub address: Address,
+    pub decimals: u8,
+    pub invert: bool,
+    /// Answers older than this at the evaluated block are refused.
+    pub max_age_secs: u64,
+}
+
+impl ChainlinkFeed {
+    pub async fn connect(provider: &Provider<Http>, address: Address, invert: bool, max_age_secs: u64) -> Result<Self> {
+        let out = eth_call(provider, address, calldata("decimals()", &[]), None).await?;
+        let tokens = decode(&[ParamType::Uint(8)], out.as_ref())
+            .with_context(|| format!("decode failed for decimals() on {address:#x}"))?;
+        Ok(Self {
+            address,
+            decimals: token_as_uint(&tokens[0])?.low_u32() as u8,
+            invert,
+            max_age_secs,
+        })
+    }
+
+    /// `latestRoundData()` at `block` as loan-token wei per native wei.
+    /// Fails for a non-positive answer or one older than `max_age_secs`
+    /// at `block_timestamp`.
+    pub async fn rate(
+        &self,
+        provider: &Provider<Http>,
+        token_decimals: u8,
+        block: u64,
+        block_timestamp: u64,
+    ) -> Result<GasRate> {
+        let out = eth_call(provider, self.address, calldata("latestRoundData()", &[]), block_id(block)).await?;
+        let tokens = decode(
+            &[
+                ParamType::Uint(80),
+                ParamType::Int(256),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Uint(80),
+            ],
+            out.as_ref(),
+        )
+        .with_context(|| format!("decode failed for latestRoundData() on {:#x}", self.address))?;
+        let answer = match tokens[1] {
+            Token::Int(raw) => I256::from_raw(raw),
+            _ => anyhow::bail!("unexpected answer in latestRoundData on {:#x}", self.address),
+        };
+        if answer <= I256::zero() {
+            anyhow::bail!("non-positive answer {answer} from feed {:#x}", self.address);
+        }
+        let updated_at = token_as_uint(&tokens[3])?;
+        let age = U256::from(block_timestamp).saturating_sub(updated_at);
+        if age > U256::from(self.max_age_secs) {
+            anyhow::bail!("feed {:#x} answer is {age}s old", self.address);
+        }
+
+        let price = feed_price(answer.into_raw(), self.decimals, token_decimals, self.invert);
+        Ok(GasRate {
+            price: price.with_context(|| format!("feed {:#x} answer out of range", self.address))?,
+            source: "chainlink",
+            address: Some(self.address),
+        })
+    }
+}
+
+/// Loan-token wei per native wei from a feed `answer` with `feed_decimals`
+/// decimals: the native token priced in the loan token, or with `invert`
+/// the loan token priced in the native token.
+pub fn feed_price(answer: U256, feed_decimals: u8, token_decimals: u8, invert: bool) -> Option<Price> {
+    let answer = U512::from(answer);
+    let token_scale = pow10(token_decimals);
+    let feed_scale = pow10(feed_decimals);
+    let native_scale = pow10(NATIVE_DECIMALS);
+    if invert {
+        Price::new(token_scale * feed_scale, answer * native_scale)
+    } else {
+        Price::new(answer * token_scale, feed_scale * native_scale)
+    }
+}
+
+fn pow10(decimals: u8) -> U512 {
+    U512::from(10).pow(U512::from(decimals))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chainlink(price: Price) -> GasRate {
+        GasRate {
+            price,
+            source: "chainlink",
+            address: None,
+        }
+    }
+
+    #[test]
+    fn eth_usd_feed_prices_gas_in_usdc() {
+        // ETH/USD at 3000.12345678 with 8 feed decimals; USDC has 6.
+        let rate = chainlink(feed_price(U256::from(300_012_345_678_u64), 8, 6, false).unwrap());
+        assert_eq!(rate.per_native_token(), U256::from(3_000_123_456_u64));
+        // 3000123456.78 USDC wei rounds up.
+        assert_eq!(rate.convert(U256::exp10(18)), U256::from(3_000_123_457_u64));
+        // 0.001 ETH is 3000123.45678 USDC wei.
+        assert_eq!(rate.convert(U256::exp10(15)), U256::from(3_000_124));
+        assert_eq!(rate.convert(U256::zero()), U256::zero());
+    }
+
+    #[test]
+    fn inverted_btc_eth_feed_prices_gas_in_cbbtc() {
+        // BTC/ETH at 25.5 with 18 feed decimals; cbBTC has 8.
+        let answer = U256::from(255) * U256::exp10(17);
+        let rate = chainlink(feed_price(answer, 18, 8, true).unwrap());
+        // 1 ETH is 1e8 / 25.5 = 3921568.63 cbBTC wei.
+        assert_eq!(rate.per_native_token(), U256::from(3_921_568));
+        assert_eq!(rate.convert(U256::exp10(18)), U256::from(3_921_569));
+        // 25.5 ETH is exactly one cbBTC, with nothing to round.
+        assert_eq!(rate.convert(answer), U256::exp10(8));
+        // 21000 gas at 1 gwei is 82.35 cbBTC wei.
+        assert_eq!(rate.convert(U256::from(21_000_000_000_000_u64)), U256::from(83));
+    }
+
+    #[test]
+    fn zero_answer_has_no_inverted_price() {
+        assert!(feed_price(U256::zero(), 18, 8, true).is_none());
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::{Context, Result};
+use ethers::providers::Middleware;
+use ethers::types::{Block, H256, TxHash};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockRef {
+    pub number: u64,
+    pub hash: H256,
+    pub parent_hash: H256,
+}
+
+impl BlockRef {
+    pub fn from_block(block: &Block<TxHash>) -> Result<Self> {
+        Ok(Self {
+            number: block.number.context("block is missing number")?.as_u64(),
+            hash: block.hash.context("block is missing hash")?,
+            parent_hash: block.parent_hash,
+        })
+    }
+}
+
+/// JSONL record emitted when the canonical chain switched under us.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReorgEvent {
+    pub event: String,
+    pub new_head: u64,
+    pub new_head_hash: String,
+    pub common_ancestor: u64,
+    pub depth: u64,
+    pub orphaned_blocks: Vec<u64>,
+    pub exceeded_tracked_depth: bool,
+}
+
+/// Rolling window of recent block hashes used to detect reorgs.
+///
+/// Only the last `max_depth` heights are kept; a reorg deeper than that is
+/// still reported, flagged with `exceeded_tracked_depth`, and callers should
+/// re-seed any derived state from scratch.
+#[derive(Clone, Debug)]
+pub struct BlockTracker {
+    blocks: BTreeMap<u64, BlockRef>,
+    max_depth: u64,
+}
+
+impl BlockTracker {
+    pub fn new(max_depth: u64) -> Self {
+        Self {
+            blocks: BTreeMap::new(),
+            max_depth: max_depth.max(1),
+        }
+    }
+
+    pub fn head(&self) -> Option<BlockRef> {
+        self.blocks.values().next_back().copied()
+    }
+
+    pub fn lowest(&self) -> Option<u64> {
+        self.blocks.keys().next().copied()
+    }
+
+    pub fn hash_at(&self, number: u64) -> Option<H256> {
+        self.blocks.get(&number).map(|block| block.hash)
+    }
+
+    pub fn max_depth(&self) -> u64 {
+        self.max_depth
+    }
+
+    /// Whether `block` can be applied without first fetching its parent:
+    /// either nothing is tracked at the parent height, or the parent hash
+    /// matches what we recorded there.
+    fn links(&self, block: &BlockRef) -> bool {
+        match block.number.checked_sub(1) {
+            Some(parent) => self.hash_at(parent).is_none_or(|hash| hash == block.parent_hash),
+            None => true,
+        }
+    }
+
+    /// Whether `block` sits above a gap in the tracked range.
+    fn is_ahead_of_head(&self, block: &BlockRef) -> bool {
+        self.head()
+            .is_some_and(|head| block.number > head.number.saturating_add(1))
+    }
+
+    /// Applies an ascending, internally linked chain segment. Tracked blocks
+    /// at or above the segment start are replaced; any whose hash changed are
+    /// reported as orphaned. Orphans are only trusted to end at the common
+    /// ancestor when the segment links to a tracked parent.
+    pub fn apply_segment(&mut self, segment: &[BlockRef]) -> Option<ReorgEvent> {
+        let first = *segment.first()?;
+        let new_head = *segment.last()?;
+        let parent_tracked = first
+            .number
+            .checked_sub(1)
+            .is_some_and(|parent| self.hash_at(parent).is_some());
+
+        let replaced: Vec<BlockRef> = self
+            .blocks
+            .range(first.number..)
+            .map(|(_, block)| *block)
+            .collect();
+        let mut orphaned_blocks: Vec<u64> = replaced
+            .iter()
+            .filter(|old| {
+                segment
+                    .iter()
+                    .find(|new| new.number == old.number)
+                    .is_none_or(|new| new.hash != old.hash)
+            })
+            .map(|old| old.number)
+            .collect();
+        let exceeded_tracked_depth = !self.links(&first) || (!parent_tracked && !orphaned_blocks.is_empty());
+
+        if exceeded_tracked_depth {
+            orphaned_blocks.extend(self.blocks.range(..first.number).map(|(number, _)| *number));
+            orphaned_blocks.sort_unstable();
+            self.blocks.clear();
+        } else {
+            self.blocks.split_off(&first.number);
+        }
+
+        for block in segment {
+            self.blocks.insert(block.number, *block);
+        }
+        self.prune();
+
+        let oldest_orphan = *orphaned_blocks.first()?;
+        Some(ReorgEvent {
+            event: "reorg".to_string(),
+            new_head: new_head.number,
+            new_head_hash: format!("{:#x}", new_head.hash),
+            common_ancestor: oldest_orphan.saturating_sub(1),
+            depth: orphaned_blocks.len() as u64,
+            orphaned_blocks,
+            exceeded_tracked_depth,
+        })
+    }
+
+    fn prune(&mut self) {
+        let Some(head) = self.head() else {
+            return;
+        };
+        let keep_from = head.number.saturating_sub(self.max_depth.saturating_sub(1));
+        self.blocks = self.blocks.split_off(&keep_from);
+    }
+}
+
+/// Records `head` in the tracker, walking back through parent hashes until
+/// the new chain links to what we already know (or the tracked depth runs
+/// out). Returns the reorg, if the walk orphaned any tracked block.
+pub async fn track_block<M: Middleware>(
+    tracker: &mut BlockTracker,
+    provider: &M,
+    head: BlockRef,
+) -> Result<Option<ReorgEvent>> {
+    if tracker.hash_at(head.number) == Some(head.hash) {
+        return Ok(None);
+    }
+
+    let mut segment = vec![head];
+    loop {
+        let oldest = segment[segment.len() - 1];
+        let below_window = tracker.lowest().is_none_or(|lowest| oldest.number <= lowest);
+        let needs_parent = tracker.is_ahead_of_head(&oldest) || !tracker.links(&oldest);
+        if !needs_parent || below_window || segment.len() as u64 > tracker.max_depth() {
+            break;
+        }
+
+        let parent = provider
+            .get_block(oldest.parent_hash)
+            .await
+            .map_err(|err| anyhow::anyhow!("failed fetching parent block {:#x}: {err}", oldest.parent_hash))?
+            .with_context(|| format!("parent block {:#x} not found", oldest.parent_hash))?;
+        segment.push(BlockRef::from_block(&parent)?);
+    }
+
+    segment.reverse();
+    Ok(tracker.apply_segment(&segment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(number: u64, fork: u64) -> H256 {
+        H256::from_low_u64_be(number * 1_000 + fork)
+    }
+
+    /// Blocks `from..=to` on `fork`, the first one built on `parent_fork`.
+    fn chain(from: u64, to: u64, fork: u64, parent_fork: u64) -> Vec<BlockRef> {
+        (from..=to)
+            .map(|number| BlockRef {
+                number,
+                hash: hash(number, fork),
+                parent_hash: hash(number - 1, if number == from { parent_fork } else { fork }),
+            })
+            .collect()
+    }
+
+    fn tracker(max_depth: u64, to: u64) -> BlockTracker {
+        let mut tracker = BlockTracker::new(max_depth);
+        for block in chain(1, to, 0, 0) {
+            assert!(tracker.apply_segment(&[block]).is_none());
+        }
+        tracker
+    }
+
+    #[test]
+    fn extending_the_head_is_not_a_reorg() {
+        let tracker = tracker(64, 10);
+        assert_eq!(tracker.head().map(|head| head.number), Some(10));
+        assert_eq!(tracker.lowest(), Some(1));
+    }
+
+    #[test]
+    fn deep_reorg_reports_every_orphan_and_the_ancestor() {
+        let mut tracker = tracker(64, 10);
+        let reorg = tracker.apply_segment(&chain(7, 11, 1, 0)).unwrap();
+        assert_eq!(reorg.orphaned_blocks, vec![7, 8, 9, 10]);
+        assert_eq!(reorg.depth, 4);
+        assert_eq!(reorg.common_ancestor, 6);
+        assert_eq!(reorg.new_head, 11);
+        assert!(!reorg.exceeded_tracked_depth);
+        assert_eq!(tracker.hash_at(6), Some(hash(6, 0)));
+        assert_eq!(tracker.hash_at(8), Some(hash(8, 1)));
+    }
+
+    #[test]
+    fn replacing_a_shorter_tail_orphans_the_blocks_above_the_new_head() {
+        let mut tracker = tracker(64, 10);
+        let reorg = tracker.apply_segment(&chain(9, 9, 1, 0)).unwrap();
+        assert_eq!(reorg.orphaned_blocks, vec![9, 10]);
+        assert_eq!(reorg.common_ancestor, 8);
+        assert_eq!(tracker.head().map(|head| head.number), Some(9));
+    }
+
+    #[test]
+    fn gap_above_the_head_is_not_a_reorg() {
+        let mut tracker = tracker(64, 5);
+        assert!(tracker.apply_segment(&chain(8, 8, 0, 0)).is_none());
+        assert_eq!(tracker.head().map(|head| head.number), Some(8));
+        assert_eq!(tracker.hash_at(5), Some(hash(5, 0)));
+        assert_eq!(tracker.hash_at(7), None);
+
+        // The filled gap links on both ends.
+        assert!(tracker.apply_segment(&chain(6, 8, 0, 0)).is_none());
+        assert_eq!(tracker.hash_at(7), Some(hash(7, 0)));
+    }
+
+    #[test]
+    fn segment_not_linking_to_the_tracked_parent_exceeds_depth() {
+        let mut tracker = tracker(64, 10);
+        let reorg = tracker.apply_segment(&chain(8, 11, 1, 1)).unwrap();
+        assert!(reorg.exceeded_tracked_depth);
+        assert_eq!(reorg.orphaned_blocks, (1..=10).collect::<Vec<_>>());
+        assert_eq!(tracker.lowest(), Some(8));
+    }
+
+    #[test]
+    fn ancestor_older_than_max_depth_is_flagged() {
+        let mut tracker = tracker(4, 10);
+        assert_eq!(tracker.lowest(), Some(7));
+
+        // The fork point is at or below pruned block 6, so it cannot be
+        // checked against anything tracked.
+        let reorg = tracker.apply_segment(&chain(7, 11, 1, 1)).unwrap();
+        assert!(reorg.exceeded_tracked_depth);
+        assert_eq!(reorg.orphaned_blocks, vec![7, 8, 9, 10]);
+        assert_eq!(tracker.head().map(|head| head.number), Some(11));
+        assert_eq!(tracker.lowest(), Some(8));
+    }
+}
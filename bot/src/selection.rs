@@ -0,0 +1,310 @@
+//! Chooses which of a block's profitable candidates to act on. Candidates
+//! are ranked by net profit and taken greedily while they fit alongside the
+//! ones already chosen.
+//!
+//! Trades sent as separate transactions must not touch the same pool: the
+//! later one would execute against state its quote never saw. Trades bundled
+//! into one transaction may share pools, because each is re-quoted against
+//! the state the earlier ones leave behind, but the bundle repays a single
+//! flash loan and so borrows one token, and its candidates may not together
+//! exceed that token's loan cap. Separate transactions each borrow and repay
+//! within their own call, so the cap applies to each one alone, which the
+//! sizing already respects.
+
+use ethers::types::{Address, I256, U256};
+
+/// One quoted trade competing for the block.
+#[derive(Clone, Debug)]
+pub struct Opportunity {
+    /// Names the candidate in other candidates' drop reasons.
+    pub label: String,
+    pub pools: Vec<Address>,
+    pub loan_token: Address,
+    pub loan_amount: U256,
+    /// Quoted output less every cost, in loan-token units.
+    pub net_profit: I256,
+    /// Profit a bundled re-quote must still reach.
+    pub min_profit: U256,
+}
+
+/// A quoted trade's cost on its own, in loan-token units, with the parts a
+/// bundle pays once broken out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TradeCost {
+    /// Input, loan fee and gas.
+    pub total: U256,
+    pub flash_fee: U256,
+    /// The executor's share of the gas.
+    pub executor: U256,
+}
+
+impl TradeCost {
+    /// The cost of borrowing `input` more inside a bundle that already
+    /// borrows `borrowed`. A bundle takes one loan and runs the executor
+    /// once, so the trade pays what it adds to the fee on the total, and
+    /// only the first trade pays the executor's gas. `loan_fee` prices a
+    /// loan of the given size, `None` when no usable source covers it.
+    pub fn in_bundle(&self, input: U256, borrowed: U256, loan_fee: impl Fn(U256) -> Option<U256>) -> Option<U256> {
+        let before = if borrowed.is_zero() { U256::zero() } else { loan_fee(borrowed)? };
+        let added_fee = loan_fee(borrowed.saturating_add(input))?.saturating_sub(before);
+        let cost = self.total.saturating_sub(self.flash_fee).saturating_add(added_fee);
+        Some(if borrowed.is_zero() { cost } else { cost.saturating_sub(self.executor) })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelectionLimits {
+    pub max_selected: usize,
+    pub bundle: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// `net_profit` is the re-quoted profit when bundling and the
+    /// candidate's own quote otherwise.
+    Selected { net_profit: I256 },
+    Dropped { reason: String },
+}
+
+/// The verdict for `candidates[index]`; `rank` is its place by net profit,
+/// starting at 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub index: usize,
+    pub rank: usize,
+    pub verdict: Verdict,
+}
+
+/// Ranks `candidates` by net profit and keeps at most `max_selected` that do
+/// not conflict with a better one. `loan_cap` is the most the executor may
+/// borrow of a token in one call, checked against a bundle's total.
+///
+/// When bundling, `requote(index, state)` quotes a candidate against
+/// `state`, the pools as the already selected trades leave them, and returns
+/// its profit with the state after it; a selected candidate's state becomes
+/// the next one's starting point. `None` means the candidate could not be
+/// re-quoted. Outcomes come back in rank order.
+pub async fn select<S>(
+    candidates: &[Opportunity],
+    limits: SelectionLimits,
+    loan_cap: impl Fn(Address) -> U256,
+    mut state: S,
+    mut requote: impl AsyncFnMut(usize, &S) -> Option<(I256, S)>,
+) -> Vec<Outcome> {
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by(|a, b| candidates[*b].net_profit.cmp(&candidates[*a].net_profit).then(a.cmp(b)));
+
+    let mut selected: Vec<usize> = Vec::new();
+    let mut outcomes = Vec::with_capacity(order.len());
+    for (position, index) in order.into_iter().enumerate() {
+        let candidate = &candidates[index];
+        let verdict = if selected.len() >= limits.max_selected {
+            Verdict::Dropped {
+                reason: "not_in_top_k".to_string(),
+            }
+        } else if let Some(reason) = conflict(candidates, &selected, candidate, limits.bundle, &loan_cap) {
+            Verdict::Dropped { reason }
+        } else if limits.bundle {
+            match requote(index, &state).await {
+                Some((net_profit, next)) if net_profit >= min_profit(candidate) => {
+                    state = next;
+                    Verdict::Selected { net_profit }
+                }
+                Some(_) => Verdict::Dropped {
+                    reason: "below_min_profit:after_bundle".to_string(),
+                },
+                None => Verdict::Dropped {
+                    reason: "quote_error:requote_failed".to_string(),
+                },
+            }
+        } else {
+            Verdict::Selected {
+                net_profit: candidate.net_profit,
+            }
+        };
+        if matches!(verdict, Verdict::Selected { .. }) {
+            selected.push(index);
+        }
+        outcomes.push(Outcome {
+            index,
+            rank: position + 1,
+            verdict,
+        });
+    }
+    outcomes
+}
+
+/// The first reason `candidate` cannot join `selected`, naming the selected
+/// candidate it collides with.
+fn conflict(
+    candidates: &[Opportunity],
+    selected: &[usize],
+    candidate: &Opportunity,
+    bundle: bool,
+    loan_cap: &impl Fn(Address) -> U256,
+) -> Option<String> {
+    let mut borrowed = candidate.loan_amount;
+    for other in selected.iter().map(|index| &candidates[*index]) {
+        if !bundle && other.pools.iter().any(|pool| candidate.pools.contains(pool)) {
+            return Some(format!("conflict_with:{}:pool", other.label));
+        }
+        if bundle && other.loan_token != candidate.loan_token {
+            return Some(format!("conflict_with:{}:loan_token", other.label));
+        }
+        if bundle {
+            borrowed = borrowed.saturating_add(other.loan_amount);
+            if borrowed > loan_cap(candidate.loan_token) {
+                return Some(format!("conflict_with:{}:loan_cap", other.label));
+            }
+        }
+    }
+    None
+}
+
+fn min_profit(candidate: &Opportunity) -> I256 {
+    I256::try_from(candidate.min_profit).unwrap_or(I256::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opportunity(label: &str, pools: &[u64], loan_token: u64, loan_amount: u64, net_profit: i64) -> Opportunity {
+        Opportunity {
+            label: label.to_string(),
+            pools: pools.iter().map(|pool| Address::from_low_u64_be(*pool)).collect(),
+            loan_token: Address::from_low_u64_be(loan_token),
+            loan_amount: U256::from(loan_amount),
+            net_profit: I256::from(net_profit),
+            min_profit: U256::zero(),
+        }
+    }
+
+    fn limits(max_selected: usize, bundle: bool) -> SelectionLimits {
+        SelectionLimits { max_selected, bundle }
+    }
+
+    fn selected(net_profit: i64) -> Verdict {
+        Verdict::Selected {
+            net_profit: I256::from(net_profit),
+        }
+    }
+
+    fn dropped(reason: &str) -> Verdict {
+        Verdict::Dropped {
+            reason: reason.to_string(),
+        }
+    }
+
+    /// `(index, verdict)` in rank order.
+    fn verdicts(outcomes: Vec<Outcome>) -> Vec<(usize, Verdict)> {
+        outcomes.into_iter().map(|outcome| (outcome.index, outcome.verdict)).collect()
+    }
+
+    #[tokio::test]
+    async fn separate_trades_may_not_share_a_pool() {
+        let candidates = [
+            opportunity("b", &[2, 3], 1, 10, 90),
+            opportunity("a", &[1, 2], 1, 10, 100),
+            opportunity("c", &[4], 2, 10, 80),
+        ];
+        let outcomes = select(&candidates, limits(3, false), |_| U256::MAX, (), async |_, _: &()| {
+            panic!("separate trades are not re-quoted")
+        })
+        .await;
+        assert_eq!(
+            verdicts(outcomes),
+            [(1, selected(100)), (0, dropped("conflict_with:a:pool")), (2, selected(80))]
+        );
+    }
+
+    #[tokio::test]
+    async fn bundles_share_pools_but_not_loan_tokens_or_the_loan_cap() {
+        let candidates = [
+            opportunity("a", &[1, 2], 1, 60, 100),
+            opportunity("b", &[3], 2, 10, 90),
+            opportunity("c", &[1, 2], 1, 30, 80),
+            opportunity("d", &[5], 1, 20, 70),
+            opportunity("e", &[6], 1, 10, 60),
+        ];
+        let outcomes = select(&candidates, limits(5, true), |_| U256::from(100), (), async |index, _: &()| {
+            Some((candidates[index].net_profit, ()))
+        })
+        .await;
+        assert_eq!(
+            verdicts(outcomes),
+            [
+                (0, selected(100)),
+                (1, dropped("conflict_with:a:loan_token")),
+                (2, selected(80)),
+                // 60 + 30 + 20 is over the cap of 100; 60 + 30 + 10 is not.
+                (3, dropped("conflict_with:c:loan_cap")),
+                (4, selected(60)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_the_top_k_are_selected() {
+        let candidates = [
+            opportunity("a", &[1], 1, 10, 50),
+            opportunity("b", &[2], 1, 10, 100),
+            opportunity("c", &[1], 1, 10, 10),
+        ];
+        let outcomes = select(&candidates, limits(1, false), |_| U256::MAX, (), async |_, _: &()| None).await;
+        assert_eq!(
+            verdicts(outcomes),
+            [(1, selected(100)), (0, dropped("not_in_top_k")), (2, dropped("not_in_top_k"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn bundled_requotes_must_still_clear_min_profit() {
+        let mut candidates = [
+            opportunity("a", &[1], 1, 10, 100),
+            opportunity("b", &[1], 1, 10, 60),
+            opportunity("c", &[1], 1, 10, 50),
+            opportunity("d", &[1], 1, 10, 40),
+        ];
+        candidates[1].min_profit = U256::from(30);
+        // Every selected trade takes 40 from the ones after it.
+        let outcomes = select(&candidates, limits(4, true), |_| U256::MAX, 0_i64, async |index, taken: &i64| {
+            (index != 3).then(|| (candidates[index].net_profit - I256::from(*taken), taken + 40))
+        })
+        .await;
+        assert_eq!(
+            verdicts(outcomes),
+            [
+                (0, selected(100)),
+                (1, dropped("below_min_profit:after_bundle")),
+                (2, selected(10)),
+                (3, dropped("quote_error:requote_failed")),
+            ]
+        );
+    }
+
+    #[test]
+    fn bundled_trades_share_the_loan_fee_and_executor_gas() {
+        // 0.1% rounded up, as Balancer's `mulUp` charges.
+        let loan_fee = |amount: U256| Some((amount + 999) / 1_000);
+        let cost = |input: u64, flash_fee: u64| TradeCost {
+            total: U256::from(input + flash_fee + 300),
+            flash_fee: U256::from(flash_fee),
+            executor: U256::from(200),
+        };
+        // First in a bundle, a trade pays what it would alone.
+        assert_eq!(cost(1_000, 1).in_bundle(U256::from(1_000), U256::zero(), loan_fee), Some(U256::from(1_301)));
+        // Later ones pay no executor gas and what they add to the fee.
+        assert_eq!(
+            cost(1_000, 1).in_bundle(U256::from(1_000), U256::from(1_000), loan_fee),
+            Some(U256::from(1_101))
+        );
+        // The fee rounds once on the total: 1_100 alone pays 2, but taking
+        // the bundle from 900 to 2_000 adds only 1.
+        assert_eq!(
+            cost(1_100, 2).in_bundle(U256::from(1_100), U256::from(900), loan_fee),
+            Some(U256::from(1_201))
+        );
+        assert_eq!(cost(1_000, 1).in_bundle(U256::from(1_000), U256::from(1_000), |_| None), None);
+    }
+}
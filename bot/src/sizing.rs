@@ -0,0 +1,197 @@
+//! Trade sizing: the input that maximizes a cycle's profit
+//! `output(x) - x - flash_fee(x)`. Gas is a fixed cost per trade and does not
+//! move the optimum, so callers add it afterwards.
+
+use ethers::types::{I256, U256, U512};
+
+/// Basis-point denominator shared by pair and flash-loan fees.
+const BPS: u64 = 10_000;
+
+/// `1/φ` in millionths, the golden-section step.
+const INVERSE_PHI_MICROS: u64 = 618_034;
+
+/// One constant-product (`x*y=k`) swap with the fee taken from the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConstantProductLeg {
+    pub reserve_in: U256,
+    pub reserve_out: U256,
+    pub fee_bps: u64,
+}
+
+/// Closed-form optimum for a cycle through two constant-product legs.
+///
+/// With `γ = 1 - fee`, the two swaps compose to `out(x) = A·x / (B + C·x)`
+/// where `A = γ₁γ₂·r₁out·r₂out`, `B = r₁in·r₂in` and
+/// `C = γ₁·(r₂in + γ₂·r₁out)`. Setting `out'(x) = 1 + flash fee` gives
+/// `x* = (√(A·B / (1 + φ)) − B) / C`. Returns zero when no positive size
+/// is profitable and `None` if the intermediate products overflow.
+pub fn optimal_constant_product_input(
+    first: ConstantProductLeg,
+    second: ConstantProductLeg,
+    flash_fee_bps: u64,
+) -> Option<U256> {
+    let bps = U512::from(BPS);
+    let gamma1 = U512::from(BPS.checked_sub(first.fee_bps)?);
+    let gamma2 = U512::from(BPS.checked_sub(second.fee_bps)?);
+    let (reserve1_in, reserve1_out) = (U512::from(first.reserve_in), U512::from(first.reserve_out));
+    let (reserve2_in, reserve2_out) = (U512::from(second.reserve_in), U512::from(second.reserve_out));
+
+    // `root` is √(A·B / (1 + φ)) and `floor` is `B`, both scaled by BPS.
+    let product = gamma1
+        .checked_mul(gamma2)?
+        .checked_mul(reserve1_in)?
+        .checked_mul(reserve1_out)?
+        .checked_mul(reserve2_in)?
+        .checked_mul(reserve2_out)?
+        .checked_mul(bps)?
+        / (bps + U512::from(flash_fee_bps));
+    let root = product.integer_sqrt();
+    let floor = bps.checked_mul(reserve1_in)?.checked_mul(reserve2_in)?;
+    if root <= floor {
+        return Some(U256::zero());
+    }
+    let second_in = bps.checked_mul(reserve2_in)?.checked_add(gamma2.checked_mul(reserve1_out)?)?;
+    let denominator = gamma1.checked_mul(second_in)?;
+    if denominator.is_zero() {
+        return None;
+    }
+    U256::try_from(bps.checked_mul(root - floor)? / denominator).ok()
+}
+
+/// Golden-section search for the maximum of a concave `profit` over
+/// `[low, high]`, stopping once the bracket is no wider than `tolerance`.
+/// `profit` returns `None` for sizes that cannot be quoted, which rank below
+/// any quote. Returns the best size seen and its profit, or `None` if no
+/// size could be quoted.
+pub async fn golden_section_max(
+    low: U256,
+    high: U256,
+    tolerance: U256,
+    mut profit: impl AsyncFnMut(U256) -> Option<I256>,
+) -> Option<(U256, I256)> {
+    let (mut low, mut high) = (low.min(high), high.max(low));
+    let tolerance = tolerance.max(U256::from(2));
+    let mut best = None;
+    let step = |low: U256, high: U256| (high - low) * U256::from(INVERSE_PHI_MICROS) / U256::from(1_000_000);
+
+    let mut lower = high - step(low, high);
+    let mut upper = low + step(low, high);
+    let mut lower_profit = profit(lower).await;
+    keep_best(&mut best, lower, lower_profit);
+    let mut upper_profit = profit(upper).await;
+    keep_best(&mut best, upper, upper_profit);
+
+    while high - low > tolerance && lower < upper {
+        if lower_profit < upper_profit {
+            low = lower;
+            (lower, lower_profit) = (upper, upper_profit);
+            upper = low + step(low, high);
+            upper_profit = profit(upper).await;
+            keep_best(&mut best, upper, upper_profit);
+        } else {
+            high = upper;
+            (upper, upper_profit) = (lower, lower_profit);
+            lower = high - step(low, high);
+            lower_profit = profit(lower).await;
+            keep_best(&mut best, lower, lower_profit);
+        }
+    }
+    for edge in [low, high] {
+        let edge_profit = profit(edge).await;
+        keep_best(&mut best, edge, edge_profit);
+    }
+    best
+}
+
+fn keep_best(best: &mut Option<(U256, I256)>, size: U256, profit: Option<I256>) {
+    if let Some(profit) = profit
+        && best.is_none_or(|(_, current)| profit > current)
+    {
+        *best = Some((size, profit));
+    }
+}
+
+/// `output - cost` as a signed amount, saturating at the `I256` range.
+pub fn signed_net(output: U256, cost: U256) -> I256 {
+    if output >= cost {
+        I256::try_from(output - cost).unwrap_or(I256::MAX)
+    } else {
+        I256::try_from(cost - output).map_or(I256::MIN, |loss| -loss)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::v2;
+
+    fn leg(reserve_in: u64, reserve_out: u64) -> ConstantProductLeg {
+        ConstantProductLeg {
+            reserve_in: U256::from(reserve_in),
+            reserve_out: U256::from(reserve_out),
+            fee_bps: 30,
+        }
+    }
+
+    /// Integer profit of the cycle as the pairs would pay it out.
+    fn cycle_profit(first: ConstantProductLeg, second: ConstantProductLeg, flash_fee_bps: u64, input: u64) -> I256 {
+        let swap = |leg: ConstantProductLeg, amount| {
+            v2::get_amount_out(amount, leg.reserve_in, leg.reserve_out, leg.fee_bps).unwrap()
+        };
+        let input = U256::from(input);
+        let output = swap(second, swap(first, input));
+        let flash_fee = input * U256::from(flash_fee_bps) / U256::from(BPS);
+        signed_net(output, input + flash_fee)
+    }
+
+    #[test]
+    fn closed_form_matches_brute_force() {
+        // The first pair prices token1 5% above the second.
+        let (first, second) = (leg(1_000_000, 2_100_000), leg(2_000_000, 1_000_000));
+        for flash_fee_bps in [0, 5] {
+            let optimal = optimal_constant_product_input(first, second, flash_fee_bps).unwrap();
+            let best = (0..60_000)
+                .map(|input| cycle_profit(first, second, flash_fee_bps, input))
+                .max()
+                .unwrap();
+            // Integer rounding flattens the peak, so the closed form may
+            // land a unit of profit below the best integer size.
+            let profit = cycle_profit(first, second, flash_fee_bps, optimal.as_u64());
+            assert!(profit > I256::zero());
+            assert!(best - profit <= I256::one(), "fee={flash_fee_bps} optimal={optimal} {profit} vs {best}");
+        }
+    }
+
+    #[test]
+    fn closed_form_is_zero_without_an_edge() {
+        let second = leg(2_000_000, 1_000_000);
+        // Same price on both pairs: the fees alone make every size a loss.
+        assert_eq!(optimal_constant_product_input(leg(1_000_000, 2_000_000), second, 0), Some(U256::zero()));
+        // An edge smaller than the two pair fees.
+        assert_eq!(optimal_constant_product_input(leg(1_000_000, 2_001_000), second, 0), Some(U256::zero()));
+        // An edge the flash fee eats.
+        assert_eq!(optimal_constant_product_input(leg(1_000_000, 2_100_000), second, 500), Some(U256::zero()));
+    }
+
+    #[tokio::test]
+    async fn golden_section_finds_the_peak_around_unquotable_sizes() {
+        // Concave with its peak at 700; sizes above 900 and a band at the
+        // low end cannot be quoted.
+        let profit = async |size: U256| {
+            let size = size.as_u64() as i64;
+            let unquotable = (50..=120).contains(&size) || size > 900;
+            (!unquotable).then(|| I256::from(10_000 - (size - 700).pow(2)))
+        };
+        let (size, best) = golden_section_max(U256::zero(), U256::from(1_000), U256::from(2), profit)
+            .await
+            .unwrap();
+        assert!(size.as_u64().abs_diff(700) <= 2, "size={size}");
+        assert!(best >= I256::from(10_000 - 4));
+    }
+
+    #[tokio::test]
+    async fn golden_section_returns_none_when_nothing_quotes() {
+        let result = golden_section_max(U256::zero(), U256::from(1_000), U256::from(2), async |_| None).await;
+        assert_eq!(result, None);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::calls::{calldata, multicall, token_as_uint};
+use crate::persist::write_json_atomic;
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, Bytes};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    pub address: Address,
+    pub decimals: u8,
+    pub symbol: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct TokenCacheFile {
+    chain_id: u64,
+    #[serde(default)]
+    tokens: BTreeMap<Address, TokenMetadata>,
+}
+
+/// Default on-disk cache location for `chain_id`.
+pub fn default_cache_path(chain_id: u64) -> PathBuf {
+    PathBuf::from(format!("logs/token_metadata.{chain_id}.json"))
+}
+
+/// Resolves `decimals()`, `symbol()` and `name()` over `eth_call`, caching
+/// results on disk per chain. Metadata is immutable for every token we
+/// care about, so cached entries are never refreshed.
+#[derive(Clone, Debug)]
+pub struct TokenResolver {
+    path: Option<PathBuf>,
+    cache: TokenCacheFile,
+}
+
+impl TokenResolver {
+    /// Opens the cache at `path` (or an in-memory cache for `None`). A
+    /// cache written for another chain is an error.
+    pub fn open(path: Option<PathBuf>, chain_id: u64) -> Result<Self> {
+        let cache = match &path {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("failed reading token metadata cache at {}", path.display()))?;
+                let cache: TokenCacheFile = serde_json::from_str(&content)
+                    .with_context(|| format!("failed parsing token metadata cache at {}", path.display()))?;
+                if cache.chain_id != chain_id {
+                    anyhow::bail!(
+                        "token metadata cache at {} is for chain {}, expected {chain_id}",
+                        path.display(),
+                        cache.chain_id
+                    );
+                }
+                cache
+            }
+            _ => TokenCacheFile {
+                chain_id,
+                tokens: BTreeMap::new(),
+            },
+        };
+        Ok(Self { path, cache })
+    }
+
+    pub fn cached(&self, token: Address) -> Option<&TokenMetadata> {
+        self.cache.tokens.get(&token)
+    }
+
+    pub async fn resolve(&mut self, provider: &Provider<Http>, token: Address) -> Result<TokenMetadata> {
+        if let Some(metadata) = self.cached(token) {
+            return Ok(metadata.clone());
+        }
+        let metadata = fetch_token_metadata(provider, token).await?;
+        self.cache.tokens.insert(token, metadata.clone());
+        self.save()?;
+        Ok(metadata)
+    }
+
+    /// Resolves `token` and fails if a configured symbol or decimals value
+    /// disagrees with the chain. Symbols compare case-insensitively.
+    pub async fn resolve_checked(
+        &mut self,
+        provider: &Provider<Http>,
+        token: Address,
+        configured_symbol: Option<&str>,
+        configured_decimals: Option<u8>,
+    ) -> Result<TokenMetadata> {
+        let metadata = self.resolve(provider, token).await?;
+        check_configured(&metadata, configured_symbol, configured_decimals)?;
+        Ok(metadata)
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_json_atomic(path, &self.cache, "token metadata cache")
+    }
+}
+
+pub fn check_configured(
+    metadata: &TokenMetadata,
+    configured_symbol: Option<&str>,
+    configured_decimals: Option<u8>,
+) -> Result<()> {
+    if let Some(decimals) = configured_decimals
+        && decimals != metadata.decimals
+    {
+        anyhow::bail!(
+            "token decimals mismatch for {:#x} ({}): configured={} onchain={}",
+            metadata.address,
+            metadata.symbol,
+            decimals,
+            metadata.decimals
+        );
+    }
+    if let Some(symbol) = configured_symbol
+        && !symbol.eq_ignore_ascii_case(&metadata.symbol)
+    {
+        anyhow::bail!(
+            "token symbol mismatch for {:#x}: configured={} onchain={}",
+            metadata.address,
+            symbol,
+            metadata.symbol
+        );
+    }
+    Ok(())
+}
+
+/// Reads all three views in one Multicall3 call. `decimals()` is required;
+/// tokens without a usable `symbol()` fall back to their address and
+/// tokens without `name()` to their symbol.
+pub async fn fetch_token_metadata(provider: &Provider<Http>, token: Address) -> Result<TokenMetadata> {
+    let results = multicall(
+        provider,
+        &[
+            (token, calldata("decimals()", &[])),
+            (token, calldata("symbol()", &[])),
+            (token, calldata("name()", &[])),
+        ],
+        None,
+    )
+    .await?;
+    let [decimals, symbol, name] = results
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("unexpected token metadata result count"))?;
+
+    let decimals = decimals.with_context(|| format!("decimals() reverted on {token:#x}"))?;
+    let decimals = decode(&[ParamType::Uint(256)], decimals.as_ref())
+        .with_context(|| format!("decode failed for decimals() on {token:#x}"))?;
+    let decimals = token_as_uint(&decimals[0])?;
+    if decimals > 255.into() {
+        anyhow::bail!("decimals() out of range on {token:#x}: {decimals}");
+    }
+
+    let symbol = symbol
+        .as_ref()
+        .and_then(decode_text)
+        .unwrap_or_else(|| format!("{token:#x}"));
+    let name = name.as_ref().and_then(decode_text).unwrap_or_else(|| symbol.clone());
+    Ok(TokenMetadata {
+        address: token,
+        decimals: decimals.as_u32() as u8,
+        symbol,
+        name,
+    })
+}
+
+/// Decodes a `string` return value, or a `bytes32` one as used by MKR-era
+/// tokens (NUL-padded). Invalid UTF-8 is replaced rather than rejected.
+/// Returns `None` for empty or undecodable values.
+pub fn decode_text(raw: &Bytes) -> Option<String> {
+    let bytes = if raw.len() == 32 {
+        raw.to_vec()
+    } else {
+        match decode(&[ParamType::Bytes], raw.as_ref()).ok()?.into_iter().next()? {
+            Token::Bytes(bytes) => bytes,
+            _ => return None,
+        }
+    };
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    let text = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::encode;
+
+    fn bytes32(text: &[u8]) -> Bytes {
+        let mut word = [0_u8; 32];
+        word[..text.len()].copy_from_slice(text);
+        Bytes::from(word.to_vec())
+    }
+
+    #[test]
+    fn decodes_abi_string() {
+        let raw = Bytes::from(encode(&[Token::String("USD Coin".to_string())]));
+        assert_eq!(decode_text(&raw).as_deref(), Some("USD Coin"));
+    }
+
+    #[test]
+    fn decodes_nul_padded_bytes32() {
+        assert_eq!(decode_text(&bytes32(b"MKR")).as_deref(), Some("MKR"));
+        assert_eq!(decode_text(&bytes32(b"Maker")).as_deref(), Some("Maker"));
+    }
+
+    #[test]
+    fn empty_values_decode_to_none() {
+        assert_eq!(decode_text(&bytes32(b"")), None);
+        assert_eq!(decode_text(&Bytes::from(encode(&[Token::String(String::new())]))), None);
+        assert_eq!(decode_text(&Bytes::new()), None);
+    }
+
+    #[test]
+    fn garbage_decodes_to_none_or_lossy_text() {
+        assert_eq!(decode_text(&Bytes::from(vec![0xde, 0xad, 0xbe, 0xef])), None);
+        // An offset pointing past the end of the data.
+        let mut raw = vec![0_u8; 64];
+        raw[31] = 0xff;
+        assert_eq!(decode_text(&Bytes::from(raw)), None);
+        // Invalid UTF-8 in a `bytes32` is replaced, not rejected.
+        assert_eq!(decode_text(&bytes32(&[b'A', 0xff, b'B'])).as_deref(), Some("A\u{fffd}B"));
+    }
+}
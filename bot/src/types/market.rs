@@ -1,18 +1,19 @@
+use crate::math::balancer::BalancerPoolState;
+use crate::math::price::{DEFAULT_PRICE_DECIMALS, Price};
+use crate::math::v3::V3PoolState;
 use anyhow::{Context, Result};
 use ethers::abi::{ParamType, Token, decode};
-use ethers::types::{Address, Bytes, H256, U256};
+use ethers::types::{Address, Bytes, H256, Log, U256, U512};
 use ethers::utils::{format_units, keccak256};
 use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
-#[derive(Clone, Debug)]
-pub enum MarketKind {
-    V2Sync,
-    V3Swap,
-}
-
+/// The one `token0`/`token1` pair of a pool that its normalized updates are
+/// quoted in, with both tokens' metadata. A Balancer pool holding more
+/// tokens still gets a single `Market`, for the pair it was configured with.
 #[derive(Clone, Debug)]
 pub struct Market {
-    pub kind: MarketKind,
     pub pool: Address,
     pub token0: Address,
     pub token1: Address,
@@ -20,6 +21,8 @@ pub struct Market {
     pub token1_symbol: String,
     pub token0_decimals: u8,
     pub token1_decimals: u8,
+    /// Fractional digits in the exact price strings of normalized updates.
+    pub price_decimals: u32,
 }
 
 #[derive(Debug, Serialize)]
@@ -31,7 +34,8 @@ pub struct V2NormalizedUpdate {
     pub token1: String,
     pub reserve0: String,
     pub reserve1: String,
-    pub price_token1_per_token0: Option<f64>,
+    #[serde(flatten)]
+    pub prices: NormalizedPrices,
 }
 
 #[derive(Debug, Serialize)]
@@ -45,13 +49,226 @@ pub struct V3SwapNormalizedUpdate {
     pub amount1: String,
     pub sqrt_price_x96: String,
     pub tick: i32,
+    #[serde(flatten)]
+    pub prices: NormalizedPrices,
+}
+
+/// Two token balances of a pool whose state is read each block rather
+/// than carried by logs, with the fee-free spot price between them.
+#[derive(Debug, Serialize)]
+pub struct BalancesNormalizedUpdate {
+    pub event: String,
+    pub block: u64,
+    pub pool: String,
+    pub token0: String,
+    pub token1: String,
+    pub balance0: String,
+    pub balance1: String,
+    #[serde(flatten)]
+    pub prices: NormalizedPrices,
+}
+
+/// The token1-per-token0 price as the `f64` rows have always carried, for
+/// display, plus exact prices in both orientations as truncated decimal
+/// strings. Decisions should use [`Price`] or the strings, never the float.
+/// Fields are `None` when the price is zero or undefined (e.g. an empty
+/// reserve).
+#[derive(Debug, Serialize)]
+pub struct NormalizedPrices {
     pub price_token1_per_token0: Option<f64>,
+    pub price_token1_per_token0_exact: Option<String>,
+    pub price_token0_per_token1_exact: Option<String>,
+}
+
+impl NormalizedPrices {
+    pub fn new(price: Option<Price>, decimals: u32) -> Self {
+        Self {
+            price_token1_per_token0: price.map(|price| price.to_f64()),
+            price_token1_per_token0_exact: price.map(|price| price.to_decimal_string(decimals)),
+            price_token0_per_token1_exact: price
+                .and_then(|price| price.inverse())
+                .map(|inverse| inverse.to_decimal_string(decimals)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum NormalizedUpdate {
+    V2(V2NormalizedUpdate),
+    V3(V3SwapNormalizedUpdate),
+    Balances(BalancesNormalizedUpdate),
+}
+
+/// Position of a log within the chain, used to order pool state updates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct EventPosition {
+    pub block: u64,
+    pub log_index: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PoolState {
+    V2 {
+        reserve0: U256,
+        reserve1: U256,
+    },
+    /// Uniswap V3 and Slipstream pools: slot0, active liquidity and the
+    /// ticks loaded around them.
+    V3(V3PoolState),
+    /// Balancer pools are reloaded every block rather than tracked from logs.
+    Balancer(BalancerPoolState),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolSnapshot {
+    pub state: PoolState,
+    pub position: EventPosition,
+}
+
+/// Latest known state per pool, driven by decoded events and by the state
+/// adapters load over RPC. Adapters quote from what they read here.
+///
+/// Updates older than the stored position are ignored so the same log
+/// delivered twice (WebSocket plus `eth_getLogs` catch-up) is harmless.
+/// Earlier snapshots are kept until pruned so a reorg can roll state back
+/// to the common ancestor instead of mixing two histories.
+#[derive(Clone, Debug, Default)]
+pub struct PoolStateStore {
+    pools: HashMap<Address, Vec<PoolSnapshot>>,
+}
+
+pub type SharedPoolStateStore = Arc<RwLock<PoolStateStore>>;
+
+impl PoolStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn shared() -> SharedPoolStateStore {
+        Arc::new(RwLock::new(Self::default()))
+    }
+
+    pub fn apply_v2_sync(&mut self, pool: Address, position: EventPosition, reserve0: U256, reserve1: U256) -> bool {
+        self.apply(pool, position, PoolState::V2 { reserve0, reserve1 })
+    }
+
+    /// Stores `state` unless a newer or equal position is already recorded.
+    pub fn apply(&mut self, pool: Address, position: EventPosition, state: PoolState) -> bool {
+        let history = self.pools.entry(pool).or_default();
+        if history.last().is_some_and(|latest| latest.position >= position) {
+            return false;
+        }
+        history.push(PoolSnapshot { state, position });
+        true
+    }
+
+    /// Drops everything known about `pool`, so it is reloaded on the next
+    /// refresh.
+    pub fn forget(&mut self, pool: Address) -> bool {
+        self.pools.remove(&pool).is_some()
+    }
+
+    /// Drops every snapshot for `pool` at or after `from`. Returns whether
+    /// anything was removed.
+    pub fn rollback_pool(&mut self, pool: Address, from: EventPosition) -> bool {
+        let Some(history) = self.pools.get_mut(&pool) else {
+            return false;
+        };
+        let before = history.len();
+        history.retain(|snapshot| snapshot.position < from);
+        let changed = history.len() != before;
+        if history.is_empty() {
+            self.pools.remove(&pool);
+        }
+        changed
+    }
+
+    /// Drops every snapshot newer than `ancestor_block` across all pools.
+    /// Pools left without any snapshot are forgotten and need reloading.
+    /// Returns the number of pools whose state changed.
+    pub fn rollback_to(&mut self, ancestor_block: u64) -> usize {
+        let mut changed = 0;
+        self.pools.retain(|_, history| {
+            let before = history.len();
+            history.retain(|snapshot| snapshot.position.block <= ancestor_block);
+            if history.len() != before {
+                changed += 1;
+            }
+            !history.is_empty()
+        });
+        changed
+    }
+
+    /// Forgets snapshots that can no longer be rolled back to, keeping the
+    /// newest one before `keep_from_block` as each pool's base state.
+    pub fn prune_history(&mut self, keep_from_block: u64) {
+        for history in self.pools.values_mut() {
+            let first_kept = history
+                .iter()
+                .position(|snapshot| snapshot.position.block >= keep_from_block)
+                .unwrap_or(history.len());
+            if first_kept > 1 {
+                history.drain(..first_kept - 1);
+            }
+        }
+    }
+
+    pub fn get(&self, pool: Address) -> Option<&PoolSnapshot> {
+        self.pools.get(&pool)?.last()
+    }
+
+    pub fn state(&self, pool: Address) -> Option<&PoolState> {
+        self.get(pool).map(|snapshot| &snapshot.state)
+    }
+
+    pub fn v2_reserves(&self, pool: Address) -> Option<(U256, U256)> {
+        match self.state(pool)? {
+            PoolState::V2 { reserve0, reserve1 } => Some((*reserve0, *reserve1)),
+            _ => None,
+        }
+    }
+
+    pub fn v3_slot(&self, pool: Address) -> Option<(U256, U256, i32)> {
+        self.v3_state(pool)
+            .map(|state| (state.sqrt_price_x96, U256::from(state.liquidity), state.tick))
+    }
+
+    pub fn v3_state(&self, pool: Address) -> Option<&V3PoolState> {
+        match self.state(pool)? {
+            PoolState::V3(state) => Some(state),
+            _ => None,
+        }
+    }
+
+    pub fn balancer_state(&self, pool: Address) -> Option<&BalancerPoolState> {
+        match self.state(pool)? {
+            PoolState::Balancer(state) => Some(state),
+            _ => None,
+        }
+    }
+
+    pub fn position(&self, pool: Address) -> Option<EventPosition> {
+        self.get(pool).map(|snapshot| snapshot.position)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Address, &PoolSnapshot)> {
+        self.pools
+            .iter()
+            .filter_map(|(pool, history)| history.last().map(|latest| (pool, latest)))
+    }
 }
 
 impl Market {
-    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        kind: MarketKind,
         pool: Address,
         token0: Address,
         token1: Address,
@@ -61,7 +278,6 @@ impl Market {
         token1_decimals: u8,
     ) -> Self {
         Self {
-            kind,
             pool,
             token0,
             token1,
@@ -69,9 +285,15 @@ impl Market {
             token1_symbol,
             token0_decimals,
             token1_decimals,
+            price_decimals: DEFAULT_PRICE_DECIMALS,
         }
     }
 
+    pub fn with_price_decimals(mut self, price_decimals: u32) -> Self {
+        self.price_decimals = price_decimals;
+        self
+    }
+
     pub fn normalize_v2_sync(&self, block: u64, reserve0: U256, reserve1: U256) -> V2NormalizedUpdate {
         V2NormalizedUpdate {
             event: "v2_sync".to_string(),
@@ -81,7 +303,10 @@ impl Market {
             token1: self.token1_symbol.clone(),
             reserve0: format_unsigned_amount(reserve0, self.token0_decimals),
             reserve1: format_unsigned_amount(reserve1, self.token1_decimals),
-            price_token1_per_token0: v2_price(reserve0, reserve1, self.token0_decimals, self.token1_decimals),
+            prices: NormalizedPrices::new(
+                Price::from_reserves(reserve0, reserve1, self.token0_decimals, self.token1_decimals),
+                self.price_decimals,
+            ),
         }
     }
 
@@ -103,21 +328,113 @@ impl Market {
             amount1: format_signed_amount(amount1_raw, self.token1_decimals),
             sqrt_price_x96: sqrt_price_x96.to_string(),
             tick,
-            price_token1_per_token0: v3_price(sqrt_price_x96, self.token0_decimals, self.token1_decimals),
+            prices: NormalizedPrices::new(
+                Price::from_sqrt_price_x96(sqrt_price_x96, self.token0_decimals, self.token1_decimals),
+                self.price_decimals,
+            ),
+        }
+    }
+
+    /// `price` is token1 per token0 in raw units, as adapters measure it.
+    pub fn normalize_balances(
+        &self,
+        event: &str,
+        block: u64,
+        balance0: U256,
+        balance1: U256,
+        price: Option<Price>,
+    ) -> BalancesNormalizedUpdate {
+        BalancesNormalizedUpdate {
+            event: event.to_string(),
+            block,
+            pool: format!("{:#x}", self.pool),
+            token0: self.token0_symbol.clone(),
+            token1: self.token1_symbol.clone(),
+            balance0: format_unsigned_amount(balance0, self.token0_decimals),
+            balance1: format_unsigned_amount(balance1, self.token1_decimals),
+            prices: NormalizedPrices::new(
+                price.and_then(|price| self.scale_raw_price(price)),
+                self.price_decimals,
+            ),
         }
     }
+
+    /// A raw token1-per-token0 rate in whole-token units.
+    fn scale_raw_price(&self, price: Price) -> Option<Price> {
+        let scale = |decimals: u8| U512::from(10).checked_pow(U512::from(decimals));
+        Price::new(
+            price.numerator().checked_mul(scale(self.token0_decimals)?)?,
+            price.denominator().checked_mul(scale(self.token1_decimals)?)?,
+        )
+    }
+}
+
+/// Position of a log; a missing log index sorts last within its block.
+pub fn log_position(log: &Log) -> Result<EventPosition> {
+    let block = log
+        .block_number
+        .context("log is missing block number")?
+        .as_u64();
+    let log_index = log.log_index.map(|value| value.as_u64()).unwrap_or(u64::MAX);
+    Ok(EventPosition { block, log_index })
+}
+
+/// Position for state read via `eth_call` at the end of `block`, so any log
+/// from that block is treated as already included.
+pub fn end_of_block_position(block: u64) -> EventPosition {
+    EventPosition {
+        block,
+        log_index: u64::MAX,
+    }
 }
 
 pub fn v2_sync_topic() -> H256 {
     H256::from(keccak256("Sync(uint112,uint112)"))
 }
 
+pub fn solidly_sync_topic() -> H256 {
+    H256::from(keccak256("Sync(uint256,uint256)"))
+}
+
 pub fn v3_swap_topic() -> H256 {
     H256::from(keccak256(
         "Swap(address,address,int256,int256,uint160,uint128,int24)",
     ))
 }
 
+pub fn v3_mint_topic() -> H256 {
+    H256::from(keccak256("Mint(address,address,int24,int24,uint128,uint256,uint256)"))
+}
+
+pub fn v3_burn_topic() -> H256 {
+    H256::from(keccak256("Burn(address,int24,int24,uint128,uint256,uint256)"))
+}
+
+pub fn v3_collect_topic() -> H256 {
+    H256::from(keccak256("Collect(address,address,int24,int24,uint128,uint128)"))
+}
+
+/// Decoded V3 `Mint` or `Burn`. `amount` is the liquidity added or removed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct V3LiquidityEvent {
+    pub owner: Address,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub amount: u128,
+    pub amount0: U256,
+    pub amount1: U256,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct V3CollectEvent {
+    pub owner: Address,
+    pub recipient: Address,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub amount0: u128,
+    pub amount1: u128,
+}
+
 pub fn decode_v2_sync(data: &Bytes) -> Result<(U256, U256)> {
     let tokens = decode(&[ParamType::Uint(112), ParamType::Uint(112)], data.as_ref())
         .context("Failed to decode V2 Sync event data")?;
@@ -128,6 +445,16 @@ pub fn decode_v2_sync(data: &Bytes) -> Result<(U256, U256)> {
     Ok((token_as_uint(&tokens[0])?, token_as_uint(&tokens[1])?))
 }
 
+pub fn decode_solidly_sync(data: &Bytes) -> Result<(U256, U256)> {
+    let tokens = decode(&[ParamType::Uint(256), ParamType::Uint(256)], data.as_ref())
+        .context("Failed to decode Solidly Sync event data")?;
+    if tokens.len() != 2 {
+        anyhow::bail!("Unexpected token length for Solidly Sync: {}", tokens.len());
+    }
+
+    Ok((token_as_uint(&tokens[0])?, token_as_uint(&tokens[1])?))
+}
+
 pub fn decode_v3_swap(data: &Bytes) -> Result<(U256, U256, U256, U256, i32)> {
     let tokens = decode(
         &[
@@ -154,6 +481,129 @@ pub fn decode_v3_swap(data: &Bytes) -> Result<(U256, U256, U256, U256, i32)> {
     Ok((amount0_raw, amount1_raw, sqrt_price_x96, liquidity, tick))
 }
 
+/// `Mint(address sender, address indexed owner, int24 indexed tickLower,
+/// int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1)`
+pub fn decode_v3_mint(log: &Log) -> Result<V3LiquidityEvent> {
+    let (owner, tick_lower, tick_upper) = position_topics(log, "Mint")?;
+    let tokens = decode(
+        &[
+            ParamType::Address,
+            ParamType::Uint(128),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+        ],
+        log.data.as_ref(),
+    )
+    .context("Failed to decode V3 Mint event data")?;
+    if tokens.len() != 4 {
+        anyhow::bail!("Unexpected token length for V3 Mint: {}", tokens.len());
+    }
+
+    Ok(V3LiquidityEvent {
+        owner,
+        tick_lower,
+        tick_upper,
+        amount: token_as_uint(&tokens[1])?.as_u128(),
+        amount0: token_as_uint(&tokens[2])?,
+        amount1: token_as_uint(&tokens[3])?,
+    })
+}
+
+/// `Burn(address indexed owner, int24 indexed tickLower, int24 indexed
+/// tickUpper, uint128 amount, uint256 amount0, uint256 amount1)`
+pub fn decode_v3_burn(log: &Log) -> Result<V3LiquidityEvent> {
+    let (owner, tick_lower, tick_upper) = position_topics(log, "Burn")?;
+    let tokens = decode(
+        &[ParamType::Uint(128), ParamType::Uint(256), ParamType::Uint(256)],
+        log.data.as_ref(),
+    )
+    .context("Failed to decode V3 Burn event data")?;
+    if tokens.len() != 3 {
+        anyhow::bail!("Unexpected token length for V3 Burn: {}", tokens.len());
+    }
+
+    Ok(V3LiquidityEvent {
+        owner,
+        tick_lower,
+        tick_upper,
+        amount: token_as_uint(&tokens[0])?.as_u128(),
+        amount0: token_as_uint(&tokens[1])?,
+        amount1: token_as_uint(&tokens[2])?,
+    })
+}
+
+/// `Collect(address indexed owner, address recipient, int24 indexed
+/// tickLower, int24 indexed tickUpper, uint128 amount0, uint128 amount1)`
+pub fn decode_v3_collect(log: &Log) -> Result<V3CollectEvent> {
+    let (owner, tick_lower, tick_upper) = position_topics(log, "Collect")?;
+    let tokens = decode(
+        &[ParamType::Address, ParamType::Uint(128), ParamType::Uint(128)],
+        log.data.as_ref(),
+    )
+    .context("Failed to decode V3 Collect event data")?;
+    if tokens.len() != 3 {
+        anyhow::bail!("Unexpected token length for V3 Collect: {}", tokens.len());
+    }
+    let recipient = match &tokens[0] {
+        Token::Address(value) => *value,
+        token => anyhow::bail!("Expected address token, found {token:?}"),
+    };
+
+    Ok(V3CollectEvent {
+        owner,
+        recipient,
+        tick_lower,
+        tick_upper,
+        amount0: token_as_uint(&tokens[1])?.as_u128(),
+        amount1: token_as_uint(&tokens[2])?.as_u128(),
+    })
+}
+
+/// Applies a V3 `Swap`, `Mint` or `Burn` log to a local pool model. Other
+/// topics (including `Collect`, which moves no liquidity) are ignored.
+pub fn apply_v3_log(state: &mut V3PoolState, log: &Log) -> Result<bool> {
+    match log.topics.first() {
+        Some(topic) if *topic == v3_swap_topic() => {
+            let (_, _, sqrt_price_x96, liquidity, tick) = decode_v3_swap(&log.data)?;
+            state.apply_swap(sqrt_price_x96, liquidity.as_u128(), tick);
+            Ok(true)
+        }
+        Some(topic) if *topic == v3_mint_topic() => {
+            let mint = decode_v3_mint(log)?;
+            state.apply_liquidity_delta(mint.tick_lower, mint.tick_upper, liquidity_delta(mint.amount, false)?)?;
+            Ok(true)
+        }
+        Some(topic) if *topic == v3_burn_topic() => {
+            let burn = decode_v3_burn(log)?;
+            state.apply_liquidity_delta(burn.tick_lower, burn.tick_upper, liquidity_delta(burn.amount, true)?)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn liquidity_delta(amount: u128, negative: bool) -> Result<i128> {
+    let delta = i128::try_from(amount).context("liquidity amount exceeds int128")?;
+    Ok(if negative { -delta } else { delta })
+}
+
+/// Reads `(owner, tickLower, tickUpper)` from the indexed topics shared by
+/// `Mint`, `Burn` and `Collect`.
+fn position_topics(log: &Log, event: &str) -> Result<(Address, i32, i32)> {
+    if log.topics.len() != 4 {
+        anyhow::bail!("Unexpected topic count for V3 {event}: {}", log.topics.len());
+    }
+    let owner = Address::from_slice(&log.topics[1].as_bytes()[12..]);
+    let tick_lower = topic_as_int24(&log.topics[2]);
+    let tick_upper = topic_as_int24(&log.topics[3]);
+    Ok((owner, tick_lower, tick_upper))
+}
+
+fn topic_as_int24(topic: &H256) -> i32 {
+    let bytes = topic.as_bytes();
+    i32::from_be_bytes([bytes[28], bytes[29], bytes[30], bytes[31]])
+}
+
 fn token_as_uint(token: &Token) -> Result<U256> {
     match token {
         Token::Uint(value) => Ok(*value),
@@ -209,27 +659,65 @@ fn int256_abs(value: U256) -> U256 {
     }
 }
 
-fn v2_price(reserve0: U256, reserve1: U256, decimals0: u8, decimals1: u8) -> Option<f64> {
-    let r0 = scale(reserve0, decimals0)?;
-    let r1 = scale(reserve1, decimals1)?;
-    if r0 == 0.0 {
-        None
-    } else {
-        Some(r1 / r0)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(block: u64, log_index: u64) -> EventPosition {
+        EventPosition { block, log_index }
     }
-}
 
-fn v3_price(sqrt_price_x96: U256, decimals0: u8, decimals1: u8) -> Option<f64> {
-    let sqrt = as_f64(sqrt_price_x96)?;
-    let ratio = (sqrt * sqrt) / 2_f64.powi(192);
-    let decimal_adjustment = 10_f64.powi(i32::from(decimals0) - i32::from(decimals1));
-    Some(ratio * decimal_adjustment)
-}
+    fn store_with_history(pool: Address) -> PoolStateStore {
+        let mut store = PoolStateStore::new();
+        for block in 10..=14 {
+            assert!(store.apply_v2_sync(pool, at(block, 3), U256::from(block), U256::from(block * 2)));
+        }
+        store
+    }
 
-fn scale(raw: U256, decimals: u8) -> Option<f64> {
-    as_f64(raw).map(|value| value / 10_f64.powi(i32::from(decimals)))
-}
+    #[test]
+    fn stale_and_duplicate_updates_are_ignored() {
+        let pool = Address::repeat_byte(1);
+        let mut store = store_with_history(pool);
+        assert!(!store.apply_v2_sync(pool, at(14, 3), U256::one(), U256::one()));
+        assert!(!store.apply_v2_sync(pool, at(13, 9), U256::one(), U256::one()));
+        assert_eq!(store.v2_reserves(pool), Some((U256::from(14), U256::from(28))));
+        assert_eq!(store.position(pool), Some(at(14, 3)));
+    }
+
+    #[test]
+    fn rollback_to_restores_the_ancestor_state() {
+        let (pool, late) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let mut store = store_with_history(pool);
+        store.apply_v2_sync(late, end_of_block_position(13), U256::one(), U256::one());
+
+        assert_eq!(store.rollback_to(12), 2);
+        assert_eq!(store.v2_reserves(pool), Some((U256::from(12), U256::from(24))));
+        assert!(store.get(late).is_none());
+
+        // The replayed canonical block applies on top of the ancestor.
+        assert!(store.apply_v2_sync(pool, at(13, 0), U256::from(7), U256::from(7)));
+    }
+
+    #[test]
+    fn removed_log_rolls_its_pool_back_to_just_before_it() {
+        let pool = Address::repeat_byte(1);
+        let mut store = store_with_history(pool);
+        assert!(store.rollback_pool(pool, at(14, 3)));
+        assert_eq!(store.position(pool), Some(at(13, 3)));
+        assert!(!store.rollback_pool(pool, at(14, 0)));
+    }
 
-fn as_f64(value: U256) -> Option<f64> {
-    value.to_string().parse::<f64>().ok()
+    #[test]
+    fn prune_keeps_one_base_snapshot_below_the_window() {
+        let pool = Address::repeat_byte(1);
+        let mut store = store_with_history(pool);
+        store.prune_history(13);
+        let mut too_deep = store.clone();
+        too_deep.rollback_to(11);
+        assert!(too_deep.get(pool).is_none());
+
+        store.rollback_to(12);
+        assert_eq!(store.position(pool), Some(at(12, 3)));
+    }
 }
@@ -0,0 +1,282 @@
+//! Balancer V2 weighted and composable stable pools. Balances live in the
+//! Vault and move on joins and exits as well as swaps, so state is reloaded
+//! in one Multicall3 call every block rather than tracked from logs.
+
+use super::{
+    MARGINAL_PROBE_DIVISOR, OnChainQuote, PoolAdapter, PoolSpec, SwapCall, Venue, block_id, exact_out_by_search,
+    rate_by_probe, token_indices,
+};
+use crate::calls::{calldata, eth_call};
+use crate::loaders::{BALANCER_VAULT, BalancerPoolType, load_balancer_pool_state};
+use crate::math::balancer::BalancerPoolState;
+use crate::math::price::Price;
+use crate::sizing::ConstantProductLeg;
+use crate::types::market::{Market, NormalizedUpdate, PoolState, PoolStateStore};
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, H256, I256, Log, U256};
+use futures_util::future::BoxFuture;
+use std::str::FromStr;
+
+#[derive(Clone)]
+pub struct BalancerPool {
+    venue: Venue,
+    pool: Address,
+    vault: Address,
+    pool_id: [u8; 32],
+    pool_type: BalancerPoolType,
+    tokens: Vec<Address>,
+    state: Option<BalancerPoolState>,
+}
+
+impl BalancerPool {
+    /// Resolves the pool id and the token list the Vault reports, without
+    /// the BPT entry of composable stable pools.
+    pub async fn connect(provider: &Provider<Http>, spec: &PoolSpec) -> Result<Self> {
+        let pool_type = match spec.venue {
+            Venue::BalancerWeighted => BalancerPoolType::Weighted,
+            Venue::BalancerStable => BalancerPoolType::ComposableStable,
+            other => anyhow::bail!("{} is not a balancer venue", other.as_str()),
+        };
+        let vault = Address::from_str(BALANCER_VAULT).context("invalid balancer vault address")?;
+        let out = eth_call(provider, spec.pool, calldata("getPoolId()", &[]), None).await?;
+        let pool_id = match decode(&[ParamType::FixedBytes(32)], out.as_ref())
+            .with_context(|| format!("decode failed for getPoolId() on {:#x}", spec.pool))?
+            .first()
+        {
+            Some(Token::FixedBytes(bytes)) if bytes.len() == 32 => {
+                let mut id = [0_u8; 32];
+                id.copy_from_slice(bytes);
+                id
+            }
+            _ => anyhow::bail!("unexpected getPoolId() response on {:#x}", spec.pool),
+        };
+        let (tokens, _) = load_balancer_pool_state(provider, vault, spec.pool, pool_id, pool_type, None).await?;
+
+        Ok(Self {
+            venue: spec.venue,
+            pool: spec.pool,
+            vault,
+            pool_id,
+            pool_type,
+            tokens,
+            state: None,
+        })
+    }
+
+    fn local_state(&self) -> Result<&BalancerPoolState> {
+        self.state
+            .as_ref()
+            .with_context(|| format!("balancer state not loaded for pool {:#x}", self.pool))
+    }
+}
+
+impl PoolAdapter for BalancerPool {
+    fn venue(&self) -> Venue {
+        self.venue
+    }
+
+    fn pool(&self) -> Address {
+        self.pool
+    }
+
+    fn tokens(&self) -> &[Address] {
+        &self.tokens
+    }
+
+    fn state_topics(&self) -> Vec<H256> {
+        Vec::new()
+    }
+
+    fn apply_log(&self, _store: &mut PoolStateStore, _log: &Log) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn refresh<'a>(
+        &'a mut self,
+        provider: &'a Provider<Http>,
+        _current: Option<&'a PoolState>,
+        block: u64,
+    ) -> BoxFuture<'a, Result<Option<PoolState>>> {
+        Box::pin(async move {
+            let (tokens, state) =
+                load_balancer_pool_state(provider, self.vault, self.pool, self.pool_id, self.pool_type, block_id(block))
+                    .await?;
+            if tokens != self.tokens {
+                anyhow::bail!("bad_pool_state: balancer pool {:#x} tokens changed to {tokens:?}", self.pool);
+            }
+            Ok(Some(PoolState::Balancer(state)))
+        })
+    }
+
+    fn sync(&mut self, store: &PoolStateStore) {
+        self.state = store.balancer_state(self.pool).cloned();
+    }
+
+    fn quote_exact_in(&self, token_in: Address, token_out: Address, amount_in: U256) -> Result<U256> {
+        let (index_in, index_out) = token_indices(&self.tokens, token_in, token_out)?;
+        self.local_state()?.exact_input(index_in, index_out, amount_in)
+    }
+
+    fn quote_exact_out(&self, token_in: Address, token_out: Address, amount_out: U256) -> Result<U256> {
+        let (index_in, index_out) = token_indices(&self.tokens, token_in, token_out)?;
+        let state = self.local_state()?;
+        exact_out_by_search(amount_out, |amount_in| state.exact_input(index_in, index_out, amount_in))
+    }
+
+    /// Probed with a millionth of the input balance, which keeps the
+    /// stable invariant and weighted power math out of this adapter.
+    fn marginal_rate(&self, token_in: Address, token_out: Address) -> Result<Price> {
+        let (index_in, index_out) = token_indices(&self.tokens, token_in, token_out)?;
+        let state = self.local_state()?;
+        let balance_in = state.balances.get(index_in).copied().unwrap_or_default();
+        rate_by_probe(balance_in / MARGINAL_PROBE_DIVISOR, |amount_in| {
+            state.exact_input(index_in, index_out, amount_in)
+        })
+    }
+
+    fn constant_product(&self, _token_in: Address, _token_out: Address) -> Option<ConstantProductLeg> {
+        None
+    }
+
+    /// The swap fee stays in the pool; the protocol's share of it is
+    /// ignored.
+    fn simulate_swap(&mut self, token_in: Address, token_out: Address, amount_in: U256) -> Result<U256> {
+        let (index_in, index_out) = token_indices(&self.tokens, token_in, token_out)?;
+        let amount_out = self.local_state()?.exact_input(index_in, index_out, amount_in)?;
+        let pool = self.pool;
+        let state = self
+            .state
+            .as_mut()
+            .with_context(|| format!("balancer state not loaded for pool {pool:#x}"))?;
+        state.balances[index_in] = state.balances[index_in]
+            .checked_add(amount_in)
+            .context("balance overflow")?;
+        state.balances[index_out] = state.balances[index_out]
+            .checked_sub(amount_out)
+            .context("swap output exceeds balance")?;
+        Ok(amount_out)
+    }
+
+    fn clone_box(&self) -> Box<dyn PoolAdapter> {
+        Box::new(self.clone())
+    }
+
+    /// Vault `queryBatchSwap` for a single GIVEN_IN step, which reports no
+    /// gas.
+    fn query_exact_in<'a>(
+        &'a self,
+        provider: &'a Provider<Http>,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+        block: u64,
+    ) -> BoxFuture<'a, Result<Option<OnChainQuote>>> {
+        Box::pin(async move {
+            let swap = Token::Tuple(vec![
+                Token::FixedBytes(self.pool_id.to_vec()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::one()),
+                Token::Uint(amount_in),
+                Token::Bytes(Vec::new()),
+            ]);
+            let data = calldata(
+                "queryBatchSwap(uint8,(bytes32,uint256,uint256,uint256,bytes)[],address[],(address,bool,address,bool))",
+                &[
+                    Token::Uint(U256::zero()),
+                    Token::Array(vec![swap]),
+                    Token::Array(vec![Token::Address(token_in), Token::Address(token_out)]),
+                    fund_management(Address::zero()),
+                ],
+            );
+            let out = eth_call(provider, self.vault, data, block_id(block)).await?;
+            let tokens = decode(&[ParamType::Array(Box::new(ParamType::Int(256)))], out.as_ref())
+                .context("failed decoding queryBatchSwap response")?;
+            let Some(Token::Array(deltas)) = tokens.first() else {
+                anyhow::bail!("unexpected queryBatchSwap response shape");
+            };
+            match deltas.get(1) {
+                Some(Token::Int(delta)) => Ok(Some(OnChainQuote {
+                    amount_out: I256::from_raw(*delta).unsigned_abs(),
+                    gas_estimate: None,
+                })),
+                _ => anyhow::bail!("unexpected queryBatchSwap deltas {deltas:?}"),
+            }
+        })
+    }
+
+    /// Vault `swap` with a GIVEN_IN single swap. `recipient` is also the
+    /// sender, so it must be the contract holding the input.
+    fn encode_swap(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+        min_amount_out: U256,
+        recipient: Address,
+    ) -> Result<SwapCall> {
+        token_indices(&self.tokens, token_in, token_out)?;
+        let single_swap = Token::Tuple(vec![
+            Token::FixedBytes(self.pool_id.to_vec()),
+            Token::Uint(U256::zero()),
+            Token::Address(token_in),
+            Token::Address(token_out),
+            Token::Uint(amount_in),
+            Token::Bytes(Vec::new()),
+        ]);
+        Ok(SwapCall {
+            target: self.vault,
+            data: calldata(
+                "swap((bytes32,uint8,address,address,uint256,bytes),(address,bool,address,bool),uint256,uint256)",
+                &[
+                    single_swap,
+                    fund_management(recipient),
+                    Token::Uint(min_amount_out),
+                    Token::Uint(U256::MAX),
+                ],
+            ),
+        })
+    }
+
+    fn describe_state(&self) -> String {
+        match &self.state {
+            Some(state) => format!("balances={:?}, swap_fee={}", state.balances, state.swap_fee),
+            None => "balances=unloaded".to_string(),
+        }
+    }
+
+    /// A row of the market's two balances after every refresh, priced by a
+    /// probe swap with the fee set to zero. Balancer logs are not tracked.
+    fn normalize(&self, market: &Market, block: u64, log: Option<&Log>) -> Result<Option<NormalizedUpdate>> {
+        if log.is_some() {
+            return Ok(None);
+        }
+        let (index0, index1) = token_indices(&self.tokens, market.token0, market.token1)?;
+        let state = self.local_state()?;
+        let balance0 = state.balances.get(index0).copied().unwrap_or_default();
+        let balance1 = state.balances.get(index1).copied().unwrap_or_default();
+        let fee_free = BalancerPoolState {
+            swap_fee: U256::zero(),
+            ..state.clone()
+        };
+        let price = rate_by_probe(balance0 / MARGINAL_PROBE_DIVISOR, |amount_in| {
+            fee_free.exact_input(index0, index1, amount_in)
+        })
+        .ok();
+        let event = format!("{}_state", self.venue.as_str());
+        Ok(Some(NormalizedUpdate::Balances(market.normalize_balances(
+            &event, block, balance0, balance1, price,
+        ))))
+    }
+}
+
+/// `FundManagement` moving external balances from and to `account`.
+fn fund_management(account: Address) -> Token {
+    Token::Tuple(vec![
+        Token::Address(account),
+        Token::Bool(false),
+        Token::Address(account),
+        Token::Bool(false),
+    ])
+}
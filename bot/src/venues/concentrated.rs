@@ -0,0 +1,328 @@
+//! Concentrated-liquidity pools: Uniswap V3 and Aerodrome Slipstream. Both
+//! share the V3 events and swap math; Slipstream pools are keyed by tick
+//! spacing, charge a dynamic fee and ship their own QuoterV2.
+
+use super::{AdapterOptions, OnChainQuote, PoolAdapter, PoolSpec, SwapCall, Venue, block_id, token_indices};
+use crate::calls::{calldata, eth_call, get_address_view, get_i24_view, get_u24_view, int_token, token_as_uint};
+use crate::loaders::load_v3_pool_state;
+use crate::math::price::Price;
+use crate::math::v3::{FEE_DENOMINATOR, V3PoolState, max_sqrt_ratio, min_sqrt_ratio};
+use crate::sizing::ConstantProductLeg;
+use crate::types::market::{
+    Market, NormalizedUpdate, PoolState, PoolStateStore, apply_v3_log, decode_v3_swap, log_position, v3_burn_topic,
+    v3_mint_topic, v3_swap_topic,
+};
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, H256, I256, Log, U256, U512};
+use futures_util::future::BoxFuture;
+
+#[derive(Clone)]
+pub struct ConcentratedPool {
+    venue: Venue,
+    pool: Address,
+    tokens: [Address; 2],
+    /// Fee tier read on connect. Slipstream's live fee is in `state`.
+    fee_pips: u32,
+    tick_spacing: i32,
+    quoter: Address,
+    options: AdapterOptions,
+    state: Option<V3PoolState>,
+}
+
+impl ConcentratedPool {
+    pub async fn connect(provider: &Provider<Http>, spec: &PoolSpec, options: &AdapterOptions) -> Result<Self> {
+        let token0 = get_address_view(provider, spec.pool, "token0()").await?;
+        let token1 = get_address_view(provider, spec.pool, "token1()").await?;
+        let fee_pips = get_u24_view(provider, spec.pool, "fee()").await?;
+        let tick_spacing = get_i24_view(provider, spec.pool, "tickSpacing()").await?;
+        match spec.venue {
+            Venue::UniswapV3 => {
+                if let Some(configured) = spec.fee_pips
+                    && configured != fee_pips
+                {
+                    anyhow::bail!(
+                        "bad_pool_state: v3 pool fee mismatch pool={:#x} configured={} onchain={}",
+                        spec.pool,
+                        configured,
+                        fee_pips
+                    );
+                }
+            }
+            Venue::Slipstream => {
+                let configured = spec
+                    .tick_spacing
+                    .context("tick spacing is required for slipstream pools")?;
+                if configured != tick_spacing {
+                    anyhow::bail!(
+                        "bad_pool_state: slipstream tick spacing mismatch pool={:#x} configured={} onchain={}",
+                        spec.pool,
+                        configured,
+                        tick_spacing
+                    );
+                }
+            }
+            other => anyhow::bail!("{} is not a concentrated-liquidity venue", other.as_str()),
+        }
+        let quoter = spec
+            .quoter
+            .with_context(|| format!("{} pool {:#x} needs a QuoterV2 address", spec.venue.as_str(), spec.pool))?;
+
+        Ok(Self {
+            venue: spec.venue,
+            pool: spec.pool,
+            tokens: [token0, token1],
+            fee_pips,
+            tick_spacing,
+            quoter,
+            options: *options,
+            state: None,
+        })
+    }
+
+    fn zero_for_one(&self, token_in: Address, token_out: Address) -> Result<bool> {
+        Ok(token_indices(&self.tokens, token_in, token_out)?.0 == 0)
+    }
+
+    fn local_state(&self) -> Result<&V3PoolState> {
+        self.state
+            .as_ref()
+            .with_context(|| format!("no local tick state for pool {:#x}", self.pool))
+    }
+}
+
+impl PoolAdapter for ConcentratedPool {
+    fn venue(&self) -> Venue {
+        self.venue
+    }
+
+    fn pool(&self) -> Address {
+        self.pool
+    }
+
+    fn tokens(&self) -> &[Address] {
+        &self.tokens
+    }
+
+    fn state_topics(&self) -> Vec<H256> {
+        if self.options.simulate_locally {
+            vec![v3_swap_topic(), v3_mint_topic(), v3_burn_topic()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Logs for a pool the store has no state for are dropped; the next
+    /// refresh loads state at the current block instead.
+    fn apply_log(&self, store: &mut PoolStateStore, log: &Log) -> Result<bool> {
+        let position = log_position(log)?;
+        let Some(mut state) = store.v3_state(self.pool).cloned() else {
+            return Ok(false);
+        };
+        if !apply_v3_log(&mut state, log)? {
+            return Ok(false);
+        }
+        Ok(store.apply(self.pool, position, PoolState::V3(state)))
+    }
+
+    fn refresh<'a>(
+        &'a mut self,
+        provider: &'a Provider<Http>,
+        current: Option<&'a PoolState>,
+        block: u64,
+    ) -> BoxFuture<'a, Result<Option<PoolState>>> {
+        Box::pin(async move {
+            if !self.options.simulate_locally {
+                return Ok(None);
+            }
+            match current {
+                Some(PoolState::V3(state)) if !state.near_window_edge() => {
+                    if self.venue != Venue::Slipstream {
+                        return Ok(None);
+                    }
+                    // Slipstream fees move without an event, so re-read every block.
+                    let fee_pips = get_fee_at(provider, self.pool, block).await?;
+                    Ok((fee_pips != state.fee_pips).then(|| {
+                        PoolState::V3(V3PoolState {
+                            fee_pips,
+                            ..state.clone()
+                        })
+                    }))
+                }
+                _ => Ok(Some(PoolState::V3(
+                    load_v3_pool_state(provider, self.pool, block_id(block), self.options.v3_bitmap_word_radius)
+                        .await?,
+                ))),
+            }
+        })
+    }
+
+    fn sync(&mut self, store: &PoolStateStore) {
+        self.state = if self.options.simulate_locally {
+            store.v3_state(self.pool).cloned()
+        } else {
+            None
+        };
+    }
+
+    fn quote_exact_in(&self, token_in: Address, token_out: Address, amount_in: U256) -> Result<U256> {
+        let zero_for_one = self.zero_for_one(token_in, token_out)?;
+        Ok(self.local_state()?.exact_input(zero_for_one, amount_in)?.amount_out)
+    }
+
+    fn quote_exact_out(&self, token_in: Address, token_out: Address, amount_out: U256) -> Result<U256> {
+        let zero_for_one = self.zero_for_one(token_in, token_out)?;
+        Ok(self.local_state()?.exact_output(zero_for_one, amount_out)?.amount_in)
+    }
+
+    /// The pool price `sqrtPriceX96² / 2^192` (or its inverse for
+    /// one-for-zero swaps) less the fee.
+    fn marginal_rate(&self, token_in: Address, token_out: Address) -> Result<Price> {
+        let zero_for_one = self.zero_for_one(token_in, token_out)?;
+        let state = self.local_state()?;
+        let fee_denominator = U512::from(FEE_DENOMINATOR);
+        let fee_complement = U512::from(FEE_DENOMINATOR.saturating_sub(state.fee_pips));
+        let price_x192 = state.sqrt_price_x96.full_mul(state.sqrt_price_x96);
+        let q192 = U512::one() << 192;
+        let rate = if zero_for_one {
+            Price::new(price_x192 * fee_complement, q192 * fee_denominator)
+        } else {
+            Price::new(q192 * fee_complement, price_x192 * fee_denominator)
+        };
+        rate.with_context(|| format!("zero sqrt price on pool {:#x}", self.pool))
+    }
+
+    fn constant_product(&self, _token_in: Address, _token_out: Address) -> Option<ConstantProductLeg> {
+        None
+    }
+
+    fn simulate_swap(&mut self, token_in: Address, token_out: Address, amount_in: U256) -> Result<U256> {
+        let zero_for_one = self.zero_for_one(token_in, token_out)?;
+        let state = self
+            .state
+            .as_mut()
+            .with_context(|| format!("no local tick state for pool {:#x}", self.pool))?;
+        let result = state.exact_input(zero_for_one, amount_in)?;
+        state.apply_swap(result.sqrt_price_x96_after, result.liquidity_after, result.tick_after);
+        Ok(result.amount_out)
+    }
+
+    fn clone_box(&self) -> Box<dyn PoolAdapter> {
+        Box::new(self.clone())
+    }
+
+    /// QuoterV2 `quoteExactInputSingle`. Uniswap's quoter identifies the
+    /// pool by fee tier, Slipstream's by tick spacing. Its `gasEstimate` is
+    /// the gas the swap used in the quoter's simulation.
+    fn query_exact_in<'a>(
+        &'a self,
+        provider: &'a Provider<Http>,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+        block: u64,
+    ) -> BoxFuture<'a, Result<Option<OnChainQuote>>> {
+        Box::pin(async move {
+            let (signature, key) = match self.venue {
+                Venue::Slipstream => (
+                    "quoteExactInputSingle((address,address,uint256,int24,uint160))",
+                    int_token(i64::from(self.tick_spacing)),
+                ),
+                _ => (
+                    "quoteExactInputSingle((address,address,uint256,uint24,uint160))",
+                    Token::Uint(U256::from(self.fee_pips)),
+                ),
+            };
+            let params = Token::Tuple(vec![
+                Token::Address(token_in),
+                Token::Address(token_out),
+                Token::Uint(amount_in),
+                key,
+                Token::Uint(U256::zero()),
+            ]);
+            let out = eth_call(provider, self.quoter, calldata(signature, &[params]), block_id(block)).await?;
+            let tokens = decode(
+                &[
+                    ParamType::Uint(256),
+                    ParamType::Uint(160),
+                    ParamType::Uint(32),
+                    ParamType::Uint(256),
+                ],
+                out.as_ref(),
+            )
+            .context("failed decoding quoter response")?;
+            Ok(Some(OnChainQuote {
+                amount_out: token_as_uint(&tokens[0])?,
+                gas_estimate: Some(token_as_uint(&tokens[3])?.min(U256::from(u64::MAX)).as_u64()),
+            }))
+        })
+    }
+
+    /// `swap(recipient, zeroForOne, amountSpecified, sqrtPriceLimitX96, data)`
+    /// with no price limit. The pool cannot enforce `min_amount_out`; the
+    /// caller's swap callback has to.
+    fn encode_swap(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+        _min_amount_out: U256,
+        recipient: Address,
+    ) -> Result<SwapCall> {
+        let zero_for_one = self.zero_for_one(token_in, token_out)?;
+        let amount_specified = I256::try_from(amount_in).context("swap amount exceeds int256")?;
+        let limit = if zero_for_one {
+            min_sqrt_ratio() + 1
+        } else {
+            max_sqrt_ratio() - 1
+        };
+        Ok(SwapCall {
+            target: self.pool,
+            data: calldata(
+                "swap(address,bool,int256,uint160,bytes)",
+                &[
+                    Token::Address(recipient),
+                    Token::Bool(zero_for_one),
+                    Token::Int(amount_specified.into_raw()),
+                    Token::Uint(limit),
+                    Token::Bytes(Vec::new()),
+                ],
+            ),
+        })
+    }
+
+    fn describe_state(&self) -> String {
+        match &self.state {
+            Some(state) => format!(
+                "tick={}, liquidity={}, fee_pips={}, ticks_loaded={}",
+                state.tick,
+                state.liquidity,
+                state.fee_pips,
+                state.ticks.len()
+            ),
+            None => "ticks=unloaded".to_string(),
+        }
+    }
+
+    /// A row per `Swap`, tagged `slipstream_swap` for Slipstream pools.
+    /// `Mint` and `Burn` move liquidity but not the price, so print nothing.
+    fn normalize(&self, market: &Market, block: u64, log: Option<&Log>) -> Result<Option<NormalizedUpdate>> {
+        let Some(log) = log.filter(|log| log.topics.first() == Some(&v3_swap_topic())) else {
+            return Ok(None);
+        };
+        let (amount0, amount1, sqrt_price_x96, _liquidity, tick) = decode_v3_swap(&log.data)?;
+        let mut update = market.normalize_v3_swap(block, amount0, amount1, sqrt_price_x96, tick);
+        if self.venue == Venue::Slipstream {
+            update.event = "slipstream_swap".to_string();
+        }
+        Ok(Some(NormalizedUpdate::V3(update)))
+    }
+}
+
+async fn get_fee_at(provider: &Provider<Http>, pool: Address, block: u64) -> Result<u32> {
+    let out = eth_call(provider, pool, calldata("fee()", &[]), block_id(block)).await?;
+    let tokens = decode(&[ParamType::Uint(24)], out.as_ref())
+        .with_context(|| format!("decode failed for fee() on {:#x}", pool))?;
+    Ok(token_as_uint(&tokens[0])?.low_u32())
+}
@@ -0,0 +1,348 @@
+//! Pool adapters: one implementation of [`PoolAdapter`] per AMM family, so
+//! route code can track, quote and encode swaps on any pool without knowing
+//! which venue it belongs to. Adding a venue means a new adapter here and a
+//! [`Venue`] variant wired into [`connect`].
+
+pub mod balancer;
+pub mod concentrated;
+pub mod reserves;
+
+use crate::math::price::Price;
+use crate::sizing::ConstantProductLeg;
+use crate::types::market::{Market, NormalizedUpdate, PoolState, PoolStateStore, end_of_block_position};
+use anyhow::{Context, Result};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::{Address, BlockId, BlockNumber, Bytes, Filter, H256, Log, U256, U512};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// AMM families a route leg can trade on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Venue {
+    UniswapV2,
+    /// Aerodrome and Velodrome V2 volatile and stable pools.
+    Aerodrome,
+    UniswapV3,
+    /// Aerodrome Slipstream, keyed by tick spacing with a dynamic fee.
+    Slipstream,
+    BalancerWeighted,
+    /// Balancer composable stable pools.
+    BalancerStable,
+}
+
+impl Venue {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UniswapV2 => "uniswap_v2",
+            Self::Aerodrome => "aerodrome",
+            Self::UniswapV3 => "uniswap_v3",
+            Self::Slipstream => "slipstream",
+            Self::BalancerWeighted => "balancer_weighted",
+            Self::BalancerStable => "balancer_stable",
+        }
+    }
+}
+
+/// What a route config says about one pool. Venue-specific fields are
+/// optional; adapters require the ones they need and check the rest
+/// against the chain when connecting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolSpec {
+    pub venue: Venue,
+    pub pool: Address,
+    /// Fee in basis points. Required for Uniswap V2 pairs, which do not
+    /// expose one; checked against Aerodrome pools.
+    pub fee_bps: Option<u64>,
+    /// Uniswap V3 fee tier in pips, checked against `fee()`.
+    pub fee_pips: Option<u32>,
+    /// Required for Slipstream pools and checked against `tickSpacing()`.
+    pub tick_spacing: Option<i32>,
+    /// QuoterV2 used for on-chain reference quotes on concentrated pools.
+    pub quoter: Option<Address>,
+}
+
+/// Settings shared by every adapter a route builds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdapterOptions {
+    /// Whether concentrated-liquidity pools keep a local tick model. When
+    /// off they are quoted only through their QuoterV2.
+    pub simulate_locally: bool,
+    /// Tick bitmap words loaded either side of the current tick.
+    pub v3_bitmap_word_radius: i16,
+}
+
+impl Default for AdapterOptions {
+    fn default() -> Self {
+        Self {
+            simulate_locally: true,
+            v3_bitmap_word_radius: 2,
+        }
+    }
+}
+
+/// An on-chain quoter's answer: the output and, for quoters that report
+/// it, the gas the swap used in the quoter's simulation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OnChainQuote {
+    pub amount_out: U256,
+    pub gas_estimate: Option<u64>,
+}
+
+/// A call the executor contract would make to perform one swap. Pair-style
+/// pools expect the input to have been transferred to `target` first;
+/// concentrated pools pull it through their swap callback.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SwapCall {
+    pub target: Address,
+    pub data: Bytes,
+}
+
+/// One pool on one venue.
+///
+/// The pool's state lives in a [`PoolStateStore`]: logs matching
+/// [`state_topics`](Self::state_topics) are recorded there through
+/// [`apply_log`](Self::apply_log), [`refresh`](Self::refresh) loads anything
+/// the logs cannot provide, and [`sync`](Self::sync) copies the latest state
+/// back into the adapter, which quotes from that copy. Async methods return
+/// boxed futures so adapters can be held as `Box<dyn PoolAdapter>`.
+pub trait PoolAdapter: Send + Sync {
+    fn venue(&self) -> Venue;
+
+    fn pool(&self) -> Address;
+
+    fn tokens(&self) -> &[Address];
+
+    /// Event topics whose logs from [`pool`](Self::pool) change quoted
+    /// state. Empty for adapters that reload on every refresh.
+    fn state_topics(&self) -> Vec<H256>;
+
+    /// Decodes one log emitted by this pool and records the state it leads
+    /// to in `store` at the log's position. Returns whether the store
+    /// changed. On error the caller should forget the pool.
+    fn apply_log(&self, store: &mut PoolStateStore, log: &Log) -> Result<bool>;
+
+    /// State to record at the end of `block`, given the pool's latest state
+    /// in the store: a full reload when `current` is missing or stale, the
+    /// parts no log carries when those moved, otherwise `None`.
+    fn refresh<'a>(
+        &'a mut self,
+        provider: &'a Provider<Http>,
+        current: Option<&'a PoolState>,
+        block: u64,
+    ) -> BoxFuture<'a, Result<Option<PoolState>>>;
+
+    /// Takes the pool's latest state in `store` as the state to quote from.
+    fn sync(&mut self, store: &PoolStateStore);
+
+    /// Output for selling `amount_in` of `token_in`, from local state.
+    fn quote_exact_in(&self, token_in: Address, token_out: Address, amount_in: U256) -> Result<U256>;
+
+    /// Input needed to buy `amount_out` of `token_out`, from local state.
+    fn quote_exact_out(&self, token_in: Address, token_out: Address, amount_out: U256) -> Result<U256>;
+
+    /// Output per unit of input for a vanishingly small swap, net of the
+    /// swap fee and in raw token units (no decimals applied), from local
+    /// state.
+    fn marginal_rate(&self, token_in: Address, token_out: Address) -> Result<Price>;
+
+    /// Reserves and fee when this pool prices the swap as plain `x*y=k`
+    /// with the fee taken from the input, which admits closed-form sizing.
+    fn constant_product(&self, token_in: Address, token_out: Address) -> Option<ConstantProductLeg>;
+
+    /// Swaps `amount_in` against local state and moves the state as the
+    /// pool would, returning the output. Callers simulate on a
+    /// [`clone_box`](Self::clone_box) copy, e.g. to quote a trade that runs
+    /// after another one in the same transaction.
+    fn simulate_swap(&mut self, token_in: Address, token_out: Address, amount_in: U256) -> Result<U256>;
+
+    /// A copy of the adapter, state included.
+    fn clone_box(&self) -> Box<dyn PoolAdapter>;
+
+    /// The venue's own on-chain quote for an exact-input swap at `block`,
+    /// or `None` for venues without a quoter.
+    fn query_exact_in<'a>(
+        &'a self,
+        provider: &'a Provider<Http>,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+        block: u64,
+    ) -> BoxFuture<'a, Result<Option<OnChainQuote>>>;
+
+    /// Calldata that swaps `amount_in` of `token_in` for at least
+    /// `min_amount_out` of `token_out`, paying `recipient`.
+    fn encode_swap(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+        min_amount_out: U256,
+        recipient: Address,
+    ) -> Result<SwapCall>;
+
+    /// Short `key=value` description of the current state for diagnostics.
+    fn describe_state(&self) -> String;
+
+    /// The row the pool listener prints for `market`: for `log`, which has
+    /// just been applied, or for the state after a refresh at `block` when
+    /// `log` is `None`. `None` when there is nothing to report.
+    fn normalize(&self, market: &Market, block: u64, log: Option<&Log>) -> Result<Option<NormalizedUpdate>>;
+}
+
+impl Clone for Box<dyn PoolAdapter> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Builds the adapter for `spec`, reading and checking its static
+/// parameters on chain. State is loaded by the first `refresh`.
+pub async fn connect(
+    provider: &Provider<Http>,
+    spec: &PoolSpec,
+    options: &AdapterOptions,
+) -> Result<Box<dyn PoolAdapter>> {
+    let adapter: Box<dyn PoolAdapter> = match spec.venue {
+        Venue::UniswapV2 | Venue::Aerodrome => Box::new(reserves::ReservePool::connect(provider, spec).await?),
+        Venue::UniswapV3 | Venue::Slipstream => {
+            Box::new(concentrated::ConcentratedPool::connect(provider, spec, options).await?)
+        }
+        Venue::BalancerWeighted | Venue::BalancerStable => {
+            Box::new(balancer::BalancerPool::connect(provider, spec).await?)
+        }
+    };
+    Ok(adapter)
+}
+
+/// Records `from_block..=to_block` logs of every event-driven adapter in
+/// `store` with one `eth_getLogs` call. A pool whose log fails to apply is
+/// forgotten rather than failing the whole batch. Adapters are not synced.
+/// Returns the number of logs applied.
+pub async fn replay_logs(
+    provider: &Provider<Http>,
+    adapters: &[Box<dyn PoolAdapter>],
+    store: &mut PoolStateStore,
+    from_block: u64,
+    to_block: u64,
+) -> Result<usize> {
+    let mut addresses = Vec::new();
+    let mut topics = BTreeSet::new();
+    for adapter in adapters {
+        let adapter_topics = adapter.state_topics();
+        if !adapter_topics.is_empty() {
+            addresses.push(adapter.pool());
+            topics.extend(adapter_topics);
+        }
+    }
+    if addresses.is_empty() || from_block > to_block {
+        return Ok(0);
+    }
+
+    let filter = Filter::new()
+        .address(addresses)
+        .topic0(topics.into_iter().collect::<Vec<_>>())
+        .from_block(from_block)
+        .to_block(to_block);
+    let logs = provider
+        .get_logs(&filter)
+        .await
+        .with_context(|| format!("eth_getLogs failed for blocks {from_block}..={to_block}"))?;
+
+    let mut applied = 0;
+    for log in &logs {
+        for adapter in adapters.iter().filter(|adapter| adapter.pool() == log.address) {
+            match adapter.apply_log(store, log) {
+                Ok(true) => applied += 1,
+                Ok(false) => {}
+                Err(_) => {
+                    store.forget(adapter.pool());
+                }
+            }
+        }
+    }
+    Ok(applied)
+}
+
+/// Refreshes `adapter` at `block`, records what it loaded in `store` and
+/// syncs the adapter from it. On failure the pool is forgotten, so the
+/// adapter has no state to quote from until a later refresh succeeds.
+pub async fn refresh_pool(
+    provider: &Provider<Http>,
+    adapter: &mut dyn PoolAdapter,
+    store: &mut PoolStateStore,
+    block: u64,
+) -> Result<()> {
+    let pool = adapter.pool();
+    let refreshed = adapter.refresh(provider, store.state(pool), block).await;
+    let result = match refreshed {
+        Ok(Some(state)) => {
+            store.apply(pool, end_of_block_position(block), state);
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(err) => {
+            store.forget(pool);
+            Err(err)
+        }
+    };
+    adapter.sync(store);
+    result
+}
+
+/// Position of `token_in` and `token_out` in an adapter's token list.
+pub fn token_indices(tokens: &[Address], token_in: Address, token_out: Address) -> Result<(usize, usize)> {
+    let index_of = |token: Address| {
+        tokens
+            .iter()
+            .position(|candidate| *candidate == token)
+            .with_context(|| format!("token {token:#x} is not in pool tokens {tokens:?}"))
+    };
+    let indices = (index_of(token_in)?, index_of(token_out)?);
+    if indices.0 == indices.1 {
+        anyhow::bail!("swap token_in and token_out are both {token_in:#x}");
+    }
+    Ok(indices)
+}
+
+/// Exact output for curves without a closed-form inverse: doubles an input
+/// until `quote` reaches `amount_out`, then bisects to the smallest input
+/// that does. `quote` must be non-decreasing.
+pub fn exact_out_by_search(amount_out: U256, quote: impl Fn(U256) -> Result<U256>) -> Result<U256> {
+    if amount_out.is_zero() {
+        return Ok(U256::zero());
+    }
+    let mut high = amount_out.max(U256::one());
+    let mut low = U256::zero();
+    while quote(high)? < amount_out {
+        low = high;
+        high = high
+            .checked_mul(U256::from(2))
+            .context("insufficient liquidity for exact output")?;
+    }
+    while high - low > U256::one() {
+        let mid = low + (high - low) / 2;
+        if quote(mid)? >= amount_out {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    Ok(high)
+}
+
+/// Probe size, as a fraction of the input-side balance, for curves whose
+/// marginal rate is easier to measure than to derive.
+pub(crate) const MARGINAL_PROBE_DIVISOR: u64 = 1_000_000;
+
+/// Marginal rate measured as `quote(amount_in) / amount_in` for a small
+/// `amount_in`.
+pub(crate) fn rate_by_probe(amount_in: U256, quote: impl Fn(U256) -> Result<U256>) -> Result<Price> {
+    let amount_in = amount_in.max(U256::one());
+    Price::new(U512::from(quote(amount_in)?), U512::from(amount_in)).context("marginal probe has no input")
+}
+
+pub(crate) fn block_id(block: u64) -> Option<BlockId> {
+    Some(BlockId::Number(BlockNumber::Number(block.into())))
+}
@@ -0,0 +1,372 @@
+//! Reserve-based pools: Uniswap V2 pairs and Aerodrome/Velodrome V2 pools.
+//! Both emit a `Sync` with the full reserves after every change, so state is
+//! loaded once with `getReserves()` and then advanced from logs.
+
+use super::{
+    MARGINAL_PROBE_DIVISOR, OnChainQuote, PoolAdapter, PoolSpec, SwapCall, Venue, block_id, exact_out_by_search,
+    rate_by_probe, token_indices,
+};
+use crate::calls::{calldata, eth_call, get_address_view, token_as_uint};
+use crate::math::solidly::{FEE_DENOMINATOR, SolidlyPool};
+use crate::math::price::Price;
+use crate::math::v2;
+use crate::sizing::ConstantProductLeg;
+use crate::types::market::{
+    Market, NormalizedUpdate, PoolState, PoolStateStore, decode_solidly_sync, decode_v2_sync, log_position,
+    solidly_sync_topic, v2_sync_topic,
+};
+use anyhow::{Context, Result};
+use ethers::abi::{ParamType, Token, decode};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, BlockId, H256, Log, U256, U512};
+use futures_util::future::BoxFuture;
+
+#[derive(Clone)]
+pub struct ReservePool {
+    venue: Venue,
+    pool: Address,
+    tokens: [Address; 2],
+    fee_bps: u64,
+    /// Curve parameters for Aerodrome pools; `None` for Uniswap V2.
+    solidly: Option<SolidlyPool>,
+    /// The Aerodrome pool's factory, whose `getFee` sets its fee.
+    factory: Option<Address>,
+    reserves: Option<(U256, U256)>,
+}
+
+impl ReservePool {
+    pub async fn connect(provider: &Provider<Http>, spec: &PoolSpec) -> Result<Self> {
+        let token0 = get_address_view(provider, spec.pool, "token0()").await?;
+        let token1 = get_address_view(provider, spec.pool, "token1()").await?;
+        let (fee_bps, solidly, factory) = match spec.venue {
+            Venue::UniswapV2 => {
+                let fee_bps = spec
+                    .fee_bps
+                    .with_context(|| format!("uniswap_v2 pool {:#x} needs a configured fee_bps", spec.pool))?;
+                (fee_bps.min(v2::FEE_DENOMINATOR), None, None)
+            }
+            Venue::Aerodrome => {
+                let factory = get_address_view(provider, spec.pool, "factory()").await?;
+                let solidly = load_solidly_pool(provider, spec.pool, factory).await?;
+                if let Some(configured) = spec.fee_bps
+                    && configured != solidly.fee_bps
+                {
+                    anyhow::bail!(
+                        "bad_pool_state: aerodrome pool fee mismatch pool={:#x} configured={} onchain={}",
+                        spec.pool,
+                        configured,
+                        solidly.fee_bps
+                    );
+                }
+                (solidly.fee_bps, Some(solidly), Some(factory))
+            }
+            other => anyhow::bail!("{} is not a reserve-based venue", other.as_str()),
+        };
+        Ok(Self {
+            venue: spec.venue,
+            pool: spec.pool,
+            tokens: [token0, token1],
+            fee_bps,
+            solidly,
+            factory,
+            reserves: None,
+        })
+    }
+
+    /// `(reserve_in, reserve_out, token0_in)` for a swap direction.
+    fn oriented(&self, token_in: Address, token_out: Address) -> Result<(U256, U256, bool)> {
+        let (index_in, _) = token_indices(&self.tokens, token_in, token_out)?;
+        let (reserve0, reserve1) = self
+            .reserves
+            .with_context(|| format!("reserves not loaded for pool {:#x}", self.pool))?;
+        Ok(if index_in == 0 {
+            (reserve0, reserve1, true)
+        } else {
+            (reserve1, reserve0, false)
+        })
+    }
+}
+
+impl PoolAdapter for ReservePool {
+    fn venue(&self) -> Venue {
+        self.venue
+    }
+
+    fn pool(&self) -> Address {
+        self.pool
+    }
+
+    fn tokens(&self) -> &[Address] {
+        &self.tokens
+    }
+
+    fn state_topics(&self) -> Vec<H256> {
+        match self.solidly {
+            Some(_) => vec![solidly_sync_topic()],
+            None => vec![v2_sync_topic()],
+        }
+    }
+
+    /// `Sync` carries the full reserves, so it also seeds a pool the store
+    /// has no state for.
+    fn apply_log(&self, store: &mut PoolStateStore, log: &Log) -> Result<bool> {
+        let (reserve0, reserve1) = match log.topics.first() {
+            Some(topic) if *topic == v2_sync_topic() && self.solidly.is_none() => decode_v2_sync(&log.data)?,
+            Some(topic) if *topic == solidly_sync_topic() && self.solidly.is_some() => {
+                decode_solidly_sync(&log.data)?
+            }
+            _ => return Ok(false),
+        };
+        Ok(store.apply_v2_sync(self.pool, log_position(log)?, reserve0, reserve1))
+    }
+
+    fn refresh<'a>(
+        &'a mut self,
+        provider: &'a Provider<Http>,
+        current: Option<&'a PoolState>,
+        block: u64,
+    ) -> BoxFuture<'a, Result<Option<PoolState>>> {
+        Box::pin(async move {
+            // Aerodrome fees move without an event, so re-read every block.
+            if let (Some(solidly), Some(factory)) = (&mut self.solidly, self.factory) {
+                let fee_bps = get_solidly_fee(provider, factory, self.pool, solidly.stable, block_id(block)).await?;
+                solidly.fee_bps = fee_bps;
+                self.fee_bps = fee_bps;
+            }
+            if matches!(current, Some(PoolState::V2 { .. })) {
+                return Ok(None);
+            }
+            let (reserve0, reserve1) = get_reserves(provider, self.pool, block).await?;
+            Ok(Some(PoolState::V2 { reserve0, reserve1 }))
+        })
+    }
+
+    fn sync(&mut self, store: &PoolStateStore) {
+        self.reserves = store.v2_reserves(self.pool);
+    }
+
+    fn quote_exact_in(&self, token_in: Address, token_out: Address, amount_in: U256) -> Result<U256> {
+        let (reserve_in, reserve_out, token0_in) = self.oriented(token_in, token_out)?;
+        match &self.solidly {
+            Some(pool) => {
+                let (reserve0, reserve1) = if token0_in {
+                    (reserve_in, reserve_out)
+                } else {
+                    (reserve_out, reserve_in)
+                };
+                pool.get_amount_out(amount_in, reserve0, reserve1, token0_in)
+            }
+            None => v2::get_amount_out(amount_in, reserve_in, reserve_out, self.fee_bps),
+        }
+    }
+
+    fn quote_exact_out(&self, token_in: Address, token_out: Address, amount_out: U256) -> Result<U256> {
+        let (reserve_in, reserve_out, _) = self.oriented(token_in, token_out)?;
+        match &self.solidly {
+            // Aerodrome pools have no `getAmountIn`; search `getAmountOut`.
+            Some(_) => {
+                if amount_out >= reserve_out {
+                    anyhow::bail!("insufficient liquidity for exact output: wanted {amount_out}, reserve {reserve_out}");
+                }
+                exact_out_by_search(amount_out, |amount_in| self.quote_exact_in(token_in, token_out, amount_in))
+            }
+            None => v2::get_amount_in(amount_out, reserve_in, reserve_out, self.fee_bps),
+        }
+    }
+
+    /// `reserve_out / reserve_in` less the fee. Stable Aerodrome curves are
+    /// probed instead.
+    fn marginal_rate(&self, token_in: Address, token_out: Address) -> Result<Price> {
+        let (reserve_in, reserve_out, _) = self.oriented(token_in, token_out)?;
+        if self.solidly.as_ref().is_some_and(|pool| pool.stable) {
+            return rate_by_probe(reserve_in / MARGINAL_PROBE_DIVISOR, |amount_in| {
+                self.quote_exact_in(token_in, token_out, amount_in)
+            });
+        }
+        // Uniswap V2 and Solidly fees share the same 10_000 denominator.
+        let fee_complement = v2::FEE_DENOMINATOR.saturating_sub(self.fee_bps);
+        Price::new(
+            U512::from(reserve_out) * U512::from(fee_complement),
+            U512::from(reserve_in) * U512::from(v2::FEE_DENOMINATOR),
+        )
+        .with_context(|| format!("empty reserves on pool {:#x}", self.pool))
+    }
+
+    /// Uniswap V2 pairs and volatile Aerodrome pools; stable curves are not
+    /// constant-product.
+    fn constant_product(&self, token_in: Address, token_out: Address) -> Option<ConstantProductLeg> {
+        if self.solidly.as_ref().is_some_and(|pool| pool.stable) {
+            return None;
+        }
+        let (reserve_in, reserve_out, _) = self.oriented(token_in, token_out).ok()?;
+        Some(ConstantProductLeg {
+            reserve_in,
+            reserve_out,
+            fee_bps: self.fee_bps,
+        })
+    }
+
+    /// Uniswap V2 keeps the whole input in reserves; Aerodrome sends the fee
+    /// to its fee contract first.
+    fn simulate_swap(&mut self, token_in: Address, token_out: Address, amount_in: U256) -> Result<U256> {
+        let amount_out = self.quote_exact_in(token_in, token_out, amount_in)?;
+        let (reserve_in, reserve_out, token0_in) = self.oriented(token_in, token_out)?;
+        let retained_in = match &self.solidly {
+            Some(pool) => {
+                amount_in - amount_in.saturating_mul(U256::from(pool.fee_bps)) / U256::from(FEE_DENOMINATOR)
+            }
+            None => amount_in,
+        };
+        let reserve_in = reserve_in.checked_add(retained_in).context("reserve overflow")?;
+        let reserve_out = reserve_out
+            .checked_sub(amount_out)
+            .context("swap output exceeds reserve")?;
+        self.reserves = Some(if token0_in {
+            (reserve_in, reserve_out)
+        } else {
+            (reserve_out, reserve_in)
+        });
+        Ok(amount_out)
+    }
+
+    fn clone_box(&self) -> Box<dyn PoolAdapter> {
+        Box::new(self.clone())
+    }
+
+    fn query_exact_in<'a>(
+        &'a self,
+        _provider: &'a Provider<Http>,
+        _token_in: Address,
+        _token_out: Address,
+        _amount_in: U256,
+        _block: u64,
+    ) -> BoxFuture<'a, Result<Option<OnChainQuote>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// `swap(amount0Out, amount1Out, to, data)` on the pool itself, asking
+    /// for exactly `min_amount_out`; the input must already be in the pool.
+    fn encode_swap(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        _amount_in: U256,
+        min_amount_out: U256,
+        recipient: Address,
+    ) -> Result<SwapCall> {
+        let (index_in, _) = token_indices(&self.tokens, token_in, token_out)?;
+        let (amount0_out, amount1_out) = if index_in == 0 {
+            (U256::zero(), min_amount_out)
+        } else {
+            (min_amount_out, U256::zero())
+        };
+        Ok(SwapCall {
+            target: self.pool,
+            data: calldata(
+                "swap(uint256,uint256,address,bytes)",
+                &[
+                    Token::Uint(amount0_out),
+                    Token::Uint(amount1_out),
+                    Token::Address(recipient),
+                    Token::Bytes(Vec::new()),
+                ],
+            ),
+        })
+    }
+
+    fn describe_state(&self) -> String {
+        match self.reserves {
+            Some((reserve0, reserve1)) => format!("reserve0={reserve0}, reserve1={reserve1}"),
+            None => "reserves=unloaded".to_string(),
+        }
+    }
+
+    /// A row per `Sync`, from the reserves it carries.
+    fn normalize(&self, market: &Market, block: u64, log: Option<&Log>) -> Result<Option<NormalizedUpdate>> {
+        let Some(log) = log else {
+            return Ok(None);
+        };
+        let (event, (reserve0, reserve1)) = match log.topics.first() {
+            Some(topic) if *topic == v2_sync_topic() && self.solidly.is_none() => {
+                ("v2_sync", decode_v2_sync(&log.data)?)
+            }
+            Some(topic) if *topic == solidly_sync_topic() && self.solidly.is_some() => {
+                ("solidly_sync", decode_solidly_sync(&log.data)?)
+            }
+            _ => return Ok(None),
+        };
+        let mut update = market.normalize_v2_sync(block, reserve0, reserve1);
+        update.event = event.to_string();
+        Ok(Some(NormalizedUpdate::V2(update)))
+    }
+}
+
+/// `getReserves()` at `block`. Uniswap V2 returns `uint112`s and Aerodrome
+/// `uint256`s; both decode as full words.
+async fn get_reserves(provider: &Provider<Http>, pair: Address, block: u64) -> Result<(U256, U256)> {
+    let out = eth_call(provider, pair, calldata("getReserves()", &[]), block_id(block)).await?;
+    let tokens = decode(
+        &[ParamType::Uint(256), ParamType::Uint(256), ParamType::Uint(256)],
+        out.as_ref(),
+    )
+    .context("failed decoding getReserves response")?;
+    Ok((token_as_uint(&tokens[0])?, token_as_uint(&tokens[1])?))
+}
+
+/// Reads curve type and token scales from `metadata()` and the pool's fee
+/// from its factory's `getFee(pool, stable)`, as `Pool.getAmountOut` does.
+async fn load_solidly_pool(provider: &Provider<Http>, pool: Address, factory: Address) -> Result<SolidlyPool> {
+    let out = eth_call(provider, pool, calldata("metadata()", &[]), None).await?;
+    let tokens = decode(
+        &[
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Bool,
+            ParamType::Address,
+            ParamType::Address,
+        ],
+        out.as_ref(),
+    )
+    .with_context(|| format!("decode failed for metadata() on {:#x}", pool))?;
+    let decimals0 = token_as_uint(&tokens[0])?;
+    let decimals1 = token_as_uint(&tokens[1])?;
+    let stable = match tokens[4] {
+        Token::Bool(value) => value,
+        _ => anyhow::bail!("unexpected stable flag in metadata() on {:#x}", pool),
+    };
+    if decimals0.is_zero() || decimals1.is_zero() {
+        anyhow::bail!("bad_pool_state: zero decimals scale in metadata() on {:#x}", pool);
+    }
+
+    Ok(SolidlyPool {
+        stable,
+        decimals0,
+        decimals1,
+        fee_bps: get_solidly_fee(provider, factory, pool, stable, None).await?,
+    })
+}
+
+async fn get_solidly_fee(
+    provider: &Provider<Http>,
+    factory: Address,
+    pool: Address,
+    stable: bool,
+    block: Option<BlockId>,
+) -> Result<u64> {
+    let out = eth_call(
+        provider,
+        factory,
+        calldata("getFee(address,bool)", &[Token::Address(pool), Token::Bool(stable)]),
+        block,
+    )
+    .await?;
+    let fee = decode(&[ParamType::Uint(256)], out.as_ref())
+        .with_context(|| format!("decode failed for getFee on {:#x}", factory))?;
+    let fee_bps = token_as_uint(&fee[0])?;
+    if fee_bps > U256::from(FEE_DENOMINATOR) {
+        anyhow::bail!("bad_pool_state: aerodrome fee {fee_bps} out of range on {:#x}", pool);
+    }
+    Ok(fee_bps.as_u64())
+}